@@ -0,0 +1,126 @@
+use ring::constant_time::verify_slices_are_equal;
+use ring::digest::{digest, SHA256};
+use std::cmp;
+use std::time::{Duration, Instant};
+
+/// How many times in a row a peer may present a wrong token before it is locked out.
+const MAX_FAILED_ATTEMPTS: u32 = 5;
+
+/// How long a peer stays locked out, during which even the right token is turned away.
+const LOCKOUT_DURATION_SECS: u64 = 60;
+
+/// How long a failed attempt counts against a peer, so that the occasional typo never adds up to
+/// a lockout.
+const FAILED_ATTEMPT_TTL_SECS: u64 = 600;
+
+/// How often failed attempts that no longer count are forgotten.
+pub const EXPIRY_INTERVAL_SECS: u64 = 60;
+
+/// How long local peers have to wait for their next turn after one of them presented a wrong token.
+const LOCAL_FAILED_ATTEMPT_DELAY_MS: u64 = 500;
+
+/// Failed attempts to authenticate from a single peer.
+#[derive(Default)]
+pub struct FailedAttempts {
+    count: u32,
+    last_failed_at: Option<Instant>,
+    locked_until: Option<Instant>,
+}
+
+impl FailedAttempts {
+    pub fn is_locked_out(&self) -> bool {
+        self.locked_until
+            .is_some_and(|locked_until| Instant::now() < locked_until)
+    }
+
+    /// Whether the attempts no longer count against the peer, either because its lockout has run
+    /// out or because the last of them is long enough ago.
+    pub fn is_expired(&self) -> bool {
+        match self.locked_until {
+            Some(_) => !self.is_locked_out(),
+            None => self.last_failed_at.is_none_or(|last_failed_at| {
+                last_failed_at.elapsed() >= Duration::from_secs(FAILED_ATTEMPT_TTL_SECS)
+            }),
+        }
+    }
+
+    pub fn record_failure(&mut self) {
+        if self.is_expired() {
+            *self = FailedAttempts::default();
+        }
+
+        self.count += 1;
+        self.last_failed_at = Some(Instant::now());
+        if self.count >= MAX_FAILED_ATTEMPTS {
+            self.locked_until = Some(Instant::now() + Duration::from_secs(LOCKOUT_DURATION_SECS));
+        }
+    }
+}
+
+/// Turns for local peers to have their tokens checked in. Local peers cannot be told apart, so
+/// locking out the one presenting wrong tokens would lock out every other. Instead, each wrong
+/// token puts off the turns of those that come after it.
+#[derive(Default)]
+pub struct LocalAttempts {
+    next_turn_at: Option<Instant>,
+}
+
+impl LocalAttempts {
+    /// Takes the next turn, returning when the attempt may be answered.
+    pub fn take_turn(&mut self, is_valid: bool) -> Instant {
+        let now = Instant::now();
+        let turn_at = self.next_turn_at.map_or(now, |next_turn_at| cmp::max(next_turn_at, now));
+        if !is_valid {
+            self.next_turn_at =
+                Some(turn_at + Duration::from_millis(LOCAL_FAILED_ATTEMPT_DELAY_MS));
+        }
+        turn_at
+    }
+}
+
+/// Compares a token presented by a client with the expected one. Their digests are compared in
+/// constant time, so the time taken gives away neither where the tokens differ nor their lengths.
+pub fn verify_token(expected: &str, presented: Option<&str>) -> bool {
+    let presented = match presented {
+        Some(presented) => presented,
+        None => return false,
+    };
+
+    let expected = digest(&SHA256, expected.as_bytes());
+    let presented = digest(&SHA256, presented.as_bytes());
+    verify_slices_are_equal(expected.as_ref(), presented.as_ref()).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_token, LocalAttempts, LOCAL_FAILED_ATTEMPT_DELAY_MS};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn only_accepts_the_expected_token() {
+        assert!(verify_token("secret", Some("secret")));
+        assert!(!verify_token("secret", Some("secreT")));
+        assert!(!verify_token("secret", Some("secret!")));
+        assert!(!verify_token("secret", Some("")));
+        assert!(!verify_token("secret", None));
+    }
+
+    #[test]
+    fn puts_off_local_attempts_after_a_wrong_token() {
+        let delay = Duration::from_millis(LOCAL_FAILED_ATTEMPT_DELAY_MS);
+        let mut local_attempts = LocalAttempts::default();
+        let start = Instant::now();
+
+        // Right tokens never put anyone off.
+        assert!(local_attempts.take_turn(true) < start + delay);
+        assert!(local_attempts.take_turn(true) < start + delay);
+
+        // Wrong ones put off everyone that comes after them, in turn.
+        let first = local_attempts.take_turn(false);
+        assert!(first < start + delay);
+        let second = local_attempts.take_turn(false);
+        assert_eq!(second, first + delay);
+        assert_eq!(local_attempts.take_turn(true), first + delay * 2);
+        assert_eq!(local_attempts.take_turn(true), first + delay * 2);
+    }
+}
@@ -1,17 +1,54 @@
+use std::net::SocketAddr;
 use std::sync::RwLock;
 
+/// What to do with a bisect session whose client disconnects before it is finished.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BisectOnDisconnect {
+    /// Runs `git bisect reset`, checking out the commit the session started from.
+    Abort,
+    /// Leaves the repository mid-bisect, for the user to carry on with by hand.
+    Keep,
+}
+
 pub struct Config {
+    /// The addresses to listen on, filled in from `--bind` and `--port`.
+    pub bind_addresses: Vec<SocketAddr>,
+    pub bisect_on_disconnect: BisectOnDisconnect,
     pub debug: bool,
     pub git_path: Option<String>,
     pub exec_path: Option<String>,
     pub port: u32,
+    /// Listen on this Unix domain socket instead of a TCP port.
+    #[cfg(unix)]
+    pub socket_path: Option<String>,
+    /// Terminate TLS on the TCP listeners with this certificate chain and key.
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    /// Require clients to present a certificate issued by one of these CAs.
+    pub tls_client_ca_path: Option<String>,
+    /// The token clients have to present in their `Hello`, if any.
+    pub token: Option<String>,
+    /// The origins of the web pages allowed to open a WebSocket connection.
+    pub websocket_origins: Vec<String>,
+    /// Also accept WebSocket connections on this port of every bind address.
+    pub websocket_port: Option<u16>,
 }
 
 lazy_static! {
     pub static ref CONFIG: RwLock<Config> = RwLock::new(Config {
+        bind_addresses: Vec::new(),
+        bisect_on_disconnect: BisectOnDisconnect::Abort,
         debug: false,
         git_path: None,
         exec_path: None,
         port: 5134,
+        #[cfg(unix)]
+        socket_path: None,
+        tls_cert_path: None,
+        tls_key_path: None,
+        tls_client_ca_path: None,
+        token: None,
+        websocket_origins: Vec::new(),
+        websocket_port: None,
     });
 }
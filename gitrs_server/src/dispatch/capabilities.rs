@@ -0,0 +1,190 @@
+use super::command_schemas;
+use message::protocol::git_command::Inbound as GitCommand;
+use message::protocol::{CommandCapability, CommandOption, Outbound};
+use schema::CommandSchema;
+use schemars::gen::SchemaSettings;
+use serde_json::{self, Map, Value};
+use state;
+use types::DispatchFuture;
+use util::transport::send_message;
+
+type Definitions = Map<String, Value>;
+
+// Follows `$ref`s into the definitions, and looks past the `null` an `Option` allows for.
+fn resolve<'a>(definitions: &'a Definitions, schema: &'a Value) -> &'a Value {
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        if let Some(schema) = definitions.get(reference.trim_start_matches("#/definitions/")) {
+            return resolve(definitions, schema);
+        }
+    }
+
+    if let Some(alternatives) = schema.get("anyOf").and_then(Value::as_array) {
+        let mut alternatives = alternatives
+            .iter()
+            .filter(|alternative| alternative.get("type").and_then(Value::as_str) != Some("null"));
+        if let (Some(alternative), None) = (alternatives.next(), alternatives.next()) {
+            return resolve(definitions, alternative);
+        }
+    }
+
+    schema
+}
+
+// The variants of an externally tagged enum, along with the contents of those that have any. Unit
+// variants are listed under `enum`, the others are objects with the variant as their only
+// property. Anything else has no variants.
+fn variants<'a>(
+    definitions: &'a Definitions,
+    schema: &'a Value,
+) -> Vec<(&'a str, Option<&'a Value>)> {
+    let schema = resolve(definitions, schema);
+    let alternatives = match schema.get("oneOf").and_then(Value::as_array) {
+        Some(alternatives) => alternatives
+            .iter()
+            .map(|alternative| resolve(definitions, alternative))
+            .collect(),
+        None => vec![schema],
+    };
+
+    let mut variants = Vec::new();
+    for alternative in alternatives {
+        if let Some(names) = alternative.get("enum").and_then(Value::as_array) {
+            variants.extend(names.iter().filter_map(Value::as_str).map(|name| (name, None)));
+        } else if alternative.get("additionalProperties") == Some(&Value::Bool(false)) {
+            let mut properties = alternative
+                .get("properties")
+                .and_then(Value::as_object)
+                .into_iter()
+                .flatten();
+            if let (Some((name, contents)), None) = (properties.next(), properties.next()) {
+                variants.push((name.as_str(), Some(contents)));
+            }
+        }
+    }
+    variants
+}
+
+// An option set to a unit-only enum lists its variants as the values it may take.
+fn describe_option(definitions: &Definitions, name: &str, schema: &Value) -> CommandOption {
+    let variants = variants(definitions, schema);
+    let values = if variants.iter().all(|&(_, contents)| contents.is_none()) {
+        variants.iter().map(|&(variant, _)| String::from(variant)).collect()
+    } else {
+        Vec::new()
+    };
+
+    CommandOption {
+        name: String::from(name),
+        values,
+    }
+}
+
+// `contents` is what the command's variant of the request wraps. Commands made up of
+// sub-commands wrap an enum with a variant for each of them, other commands take the fields of
+// the struct they wrap as options.
+fn describe_command(
+    definitions: &Definitions,
+    command: &CommandSchema,
+    contents: Option<&Value>,
+) -> CommandCapability {
+    let contents = contents.map(|contents| resolve(definitions, contents));
+
+    let properties = contents
+        .and_then(|contents| contents.get("properties"))
+        .and_then(Value::as_object);
+    let options = match properties {
+        Some(properties) if command.sub_commands().is_empty() => properties
+            .iter()
+            .map(|(name, schema)| describe_option(definitions, name, schema))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    let variants = contents.map_or_else(Vec::new, |contents| variants(definitions, contents));
+    let sub_commands = command
+        .sub_commands()
+        .iter()
+        .map(|sub_command| {
+            let contents = variants
+                .iter()
+                .find(|&&(variant, _)| variant == sub_command.name())
+                .and_then(|&(_, contents)| contents);
+            describe_command(definitions, sub_command, contents)
+        })
+        .collect();
+
+    CommandCapability {
+        interactive: command.is_interactive(),
+        name: command.name(),
+        options,
+        streaming: command.is_streaming(),
+        sub_commands,
+    }
+}
+
+/// Describes every `GitCommand` from the same schemas `--print-schema` prints, so that the list
+/// always matches what the server accepts.
+pub fn list_commands() -> Vec<CommandCapability> {
+    let mut generator = SchemaSettings::draft07().into_generator();
+    let inbound = serde_json::to_value(generator.subschema_for::<GitCommand>())
+        .expect("Could not serialize schema!");
+    let git_command = CommandSchema::group("GitCommand", command_schemas(&mut generator));
+    let definitions = generator
+        .take_definitions()
+        .into_iter()
+        .map(|(name, schema)| {
+            let schema = serde_json::to_value(schema).expect("Could not serialize schema!");
+            (name, schema)
+        })
+        .collect();
+
+    describe_command(&definitions, &git_command, Some(&inbound)).sub_commands
+}
+
+pub fn dispatch(connection_state: state::Connection) -> DispatchFuture {
+    send_message(
+        connection_state,
+        Outbound::Capabilities {
+            commands: list_commands(),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::list_commands;
+    use message::protocol::CommandCapability;
+
+    fn find<'a>(commands: &'a [CommandCapability], name: &str) -> &'a CommandCapability {
+        commands
+            .iter()
+            .find(|command| command.name == name)
+            .unwrap_or_else(|| panic!("{} is not listed!", name))
+    }
+
+    #[test]
+    fn describes_commands_from_their_schemas() {
+        let commands = list_commands();
+
+        let bisect = find(&commands, "Bisect");
+        assert!(bisect.interactive && !bisect.streaming);
+        let options: Vec<_> = bisect.options.iter().map(|option| &option.name[..]).collect();
+        assert_eq!(options, ["bad", "good"]);
+
+        let watch = find(&commands, "Watch");
+        assert!(watch.streaming && !watch.interactive);
+
+        let status = find(&commands, "Status");
+        let untracked = status
+            .options
+            .iter()
+            .find(|option| option.name == "untracked")
+            .unwrap();
+        assert_eq!(untracked.values, ["All", "No", "Normal"]);
+
+        let list = find(&find(&commands, "Config").sub_commands, "List");
+        assert_eq!(list.options.len(), 1);
+        assert_eq!(list.options[0].values, ["Global", "Local", "System", "Worktree"]);
+        assert!(find(&find(&commands, "Worktree").sub_commands, "List").options.is_empty());
+    }
+}
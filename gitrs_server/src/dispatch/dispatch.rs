@@ -1,3 +1,4 @@
+use super::capabilities;
 use super::git_command;
 use futures::future;
 use message;
@@ -14,6 +15,7 @@ pub fn dispatch(
 
     match message {
         Inbound::GitCommand(git_command) => git_command::dispatch(connection_state, git_command),
+        Inbound::ListCapabilities => capabilities::dispatch(connection_state),
         _ => Box::new(future::err((
             Error::InboundMessage(Unexpected),
             connection_state,
@@ -11,7 +11,8 @@ use std::process;
 use std::str;
 use tokio_process::CommandExt;
 use types::DispatchFuture;
-use util::git;
+use util::git::{self, RepoPath};
+use util::git_error::classify;
 use util::transport::{read_message, send_message};
 
 // See https://github.com/rust-lang/rfcs/issues/2407#issuecomment-385291238.
@@ -24,25 +25,27 @@ macro_rules! enclose {
     };
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(rename = "BisectInbound")]
 #[serde(tag = "type")]
-enum InboundMessage {
+pub enum InboundMessage {
     Bad,
     Good,
     Reset,
     Visualize,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 #[serde(tag = "reason")]
-enum BisectError {
+pub enum BisectError {
     AlreadyBisecting,
     RepoPathNotSet,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "BisectOutbound")]
 #[serde(tag = "type")]
-enum OutboundMessage {
+pub enum OutboundMessage {
     Error(BisectError),
     Finish(BisectFinish),
     ReachedMergeBase(BisectReachedMergeBase),
@@ -51,9 +54,9 @@ enum OutboundMessage {
     Success,
 }
 
-type CommandBuilder = Box<Fn() -> process::Command + Send>;
+type CommandBuilder = Box<dyn Fn() -> process::Command + Send>;
 
-fn verify_repo_path(repo_path: Option<String>) -> Result<String, SubhandlerError<BisectError>> {
+fn verify_repo_path(repo_path: Option<RepoPath>) -> Result<RepoPath, SubhandlerError<BisectError>> {
     use self::BisectError::RepoPathNotSet;
 
     match repo_path {
@@ -66,20 +69,31 @@ fn run_command(
     build_command: CommandBuilder,
 ) -> impl Future<Item = String, Error = SubhandlerError<BisectError>> {
     use error::protocol::Error::Process;
-    use error::protocol::ProcessError::{Encoding, Failed};
+    use error::protocol::ProcessError::{Failed, Git};
 
+    // Only the shas are parsed out, the commit summaries printed next to them may be in any
+    // encoding.
     build_command()
         .output_async()
         .map_err(|_| SubhandlerError::Shared(Process(Failed)))
         .and_then(|output| {
-            let output = if output.stdout.is_empty() {
-                output.stderr
+            let message = if output.stdout.is_empty() {
+                &output.stderr
             } else {
-                output.stdout
+                &output.stdout
             };
-            str::from_utf8(&output)
-                .map(String::from)
-                .map_err(|_| SubhandlerError::Shared(Process(Encoding)))
+            let message = String::from_utf8_lossy(message).into_owned();
+            // Finding that a bug was fixed rather than introduced ends the bisection with a
+            // failure, but is as much of an answer as any other.
+            let is_found_range = matches!(
+                parse_bisect(&message),
+                Ok((_, BisectOutput::Finish(BisectFinish::FoundRange(_))))
+            );
+            if output.status.success() || is_found_range {
+                Ok(message)
+            } else {
+                Err(SubhandlerError::Shared(Process(Git(classify(&output)))))
+            }
         })
 }
 
@@ -90,7 +104,7 @@ fn finish_bisect(
     build_command_reset: CommandBuilder,
     connection_state: state::Connection,
 ) -> impl Future<Item = state::Connection, Error = FinishBisectError> {
-    run_command(build_command_reset).then(|result| -> Box<Future<Item = _, Error = _> + Send> {
+    run_command(build_command_reset).then(|result| -> Box<dyn Future<Item = _, Error = _> + Send> {
         match result {
             Ok(_) => Box::new(
                 send_message(connection_state, OutboundMessage::Finish(bisect_finish)).map_err(
@@ -113,8 +127,31 @@ fn handle_errors(
     }
 }
 
+// A client that goes away mid-bisect cannot tell it which commits are good or bad anymore, so
+// the session is ended for them unless the server was told to leave it be.
+fn abort_on_disconnect(
+    repo_path: RepoPath,
+    err: SubhandlerError<BisectError>,
+    connection_state: state::Connection,
+) -> Box<dyn Future<Item = state::Connection, Error = FinishBisectError> + Send> {
+    use config::BisectOnDisconnect::Abort;
+
+    let is_disconnected = match err {
+        SubhandlerError::Shared(ref err) => !err.is_recoverable(),
+        SubhandlerError::Subhandler(_) => false,
+    };
+
+    if is_disconnected && config::CONFIG.read().unwrap().bisect_on_disconnect == Abort {
+        let mut command = git::new_command_with_repo_path(&repo_path);
+        command.arg("bisect").arg("reset");
+        Box::new(command.output_async().then(|_| Err((err, connection_state))))
+    } else {
+        Box::new(future::err((err, connection_state)))
+    }
+}
+
 type LoopFuture = Box<
-    Future<
+    dyn Future<
             Item = Loop<state::Connection, (CommandBuilder, state::Connection)>,
             Error = (SubhandlerError<BisectError>, state::Connection),
         >
@@ -122,7 +159,7 @@ type LoopFuture = Box<
 >;
 
 fn build_bisect_step_handler(
-    repo_path: String,
+    repo_path: RepoPath,
 ) -> impl FnOnce((String, state::Connection)) -> LoopFuture {
     use error::protocol::Error::Process;
     use error::protocol::ProcessError::Parsing;
@@ -256,7 +293,7 @@ fn build_bisect_step_handler(
 pub fn dispatch(connection_state: state::Connection, bad: String, good: String) -> DispatchFuture {
     use self::BisectError::AlreadyBisecting;
     use error::protocol::Error::Process;
-    use error::protocol::ProcessError::Failed;
+    use error::protocol::ProcessError::{Failed, Git};
 
     Box::new(
         future::result(match verify_repo_path(connection_state.repo_path.clone()) {
@@ -278,7 +315,7 @@ pub fn dispatch(connection_state: state::Connection, bad: String, good: String)
                             Box::new(future::ok((repo_path, connection_state)))
                         },
                         None => Box::new(future::err((
-                            SubhandlerError::Shared(Process(Failed)),
+                            SubhandlerError::Shared(Process(Git(classify(&output)))),
                             connection_state,
                         ))),
                     },
@@ -313,8 +350,50 @@ pub fn dispatch(connection_state: state::Connection, bad: String, good: String)
                                 .and_then(build_bisect_step_handler(repo_path.clone()))
                         }
                     },
-                )
+                ).or_else(move |(err, connection_state)| {
+                    abort_on_disconnect(repo_path, err, connection_state)
+                })
             })
             .or_else(handle_errors),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{run_command, CommandBuilder};
+    use error::protocol::{Error, ProcessError, SubhandlerError};
+    use util::git;
+    use util::git_error::GitErrorKind;
+    use util::test_repo::{block_on, TestRepo};
+
+    fn bisect(repo: &TestRepo, args: &'static [&'static str]) -> CommandBuilder {
+        let repo_path = repo.repo_path();
+        Box::new(move || {
+            let mut command = git::new_command_with_repo_path(&repo_path);
+            command.arg("bisect").args(args);
+            command
+        })
+    }
+
+    #[test]
+    fn fails_with_git_unless_the_output_is_an_answer() {
+        let repo = TestRepo::new();
+        repo.commit("base");
+        repo.git(&["checkout", "-q", "-b", "side"]);
+        repo.commit("side");
+        repo.git(&["checkout", "-q", "master"]);
+        repo.commit("master");
+
+        match block_on(run_command(bisect(&repo, &["start", "master", "no-such-commit", "--"]))) {
+            Err(SubhandlerError::Shared(Error::Process(ProcessError::Git(ref git_error))))
+                if git_error.kind == GitErrorKind::BadRevision => {}
+            other => panic!("starting from an unknown commit resulted in {:?}", other),
+        }
+
+        // The merge base is tested first, and a bad one means the bug was fixed on `side`.
+        block_on(run_command(bisect(&repo, &["start", "master", "side", "--"]))).unwrap();
+        let output = block_on(run_command(bisect(&repo, &["bad"]))).unwrap();
+        assert!(output.contains("This means the bug has been fixed between"), "{}", output);
+        repo.git(&["bisect", "reset"]);
+    }
+}
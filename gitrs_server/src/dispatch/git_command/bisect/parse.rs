@@ -1,41 +1,41 @@
 use nom::digit1;
 use util::parse::{sha, parse_u32};
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct BisectStep {
     current_commit_sha: String,
     num_revisions_left: u32,
     num_steps_left: u32,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct BisectReachedMergeBase {
     merge_base_sha: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct BisectFoundRange {
     bad_commit_sha: String,
     good_commit_sha: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct BisectFoundSingle {
     bad_commit_sha: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub enum BisectFinish {
     FoundRange(BisectFoundRange),
     FoundSingle(BisectFoundSingle),
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct BisectVisualize {
     shas: Vec<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub enum BisectOutput {
     Finish(BisectFinish),
     ReachedMergeBase(BisectReachedMergeBase),
@@ -0,0 +1,34 @@
+use state;
+use types::DispatchFuture;
+use util::transport::send_message;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "CloseRepoErrorReason")]
+#[serde(tag = "reason")]
+pub enum ErrorReason {
+    RepoPathNotSet,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "CloseRepoOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Success,
+    Error(ErrorReason),
+}
+
+pub fn dispatch(mut connection_state: state::Connection, handle: Option<Uuid>) -> DispatchFuture {
+    use self::ErrorReason::RepoPathNotSet;
+
+    match handle {
+        Some(handle) => {
+            connection_state.close_repo(&handle);
+            Box::new(send_message(connection_state, OutboundMessage::Success))
+        }
+        None => Box::new(send_message(
+            connection_state,
+            OutboundMessage::Error(RepoPathNotSet),
+        )),
+    }
+}
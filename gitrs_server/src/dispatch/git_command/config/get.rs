@@ -0,0 +1,77 @@
+use super::parse::{parse_config_values, ConfigEntry};
+use super::{is_invalid_config_file, new_config_command, verify_key, ErrorReason,
+            EXIT_CODE_FATAL, EXIT_CODE_INVALID_KEY};
+use futures::Future;
+use message::protocol::git_command::config::{Scope, ValueType};
+use state;
+use tokio_process::CommandExt;
+use types::DispatchFuture;
+use util::git_error::classify;
+use util::transport::send_message;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "ConfigGetOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Success { entries: Vec<ConfigEntry> },
+    Error(ErrorReason),
+}
+
+pub fn dispatch(
+    connection_state: state::Connection,
+    key: String,
+    scope: Option<Scope>,
+    value_type: Option<ValueType>,
+) -> DispatchFuture {
+    use self::ErrorReason::{InvalidConfigFile, InvalidValueForType};
+    use error::protocol::{Error, ProcessError::{Failed, Git}};
+
+    if let Err(reason) = verify_key(&key) {
+        return Box::new(send_message(connection_state, OutboundMessage::Error(reason)));
+    }
+
+    let repo_path = connection_state.repo_path.clone();
+    let mut command = match new_config_command(repo_path.clone(), scope, value_type) {
+        Ok(command) => command,
+        Err(reason) => {
+            return Box::new(send_message(connection_state, OutboundMessage::Error(reason)))
+        }
+    };
+
+    Box::new(
+        command
+            .arg("--show-origin")
+            .arg("-z")
+            .arg("--get-all")
+            .arg(&key)
+            .output_async()
+            .then(move |result| match result {
+                Ok(output) => {
+                    if is_invalid_config_file(&output) {
+                        return Ok((OutboundMessage::Error(InvalidConfigFile), connection_state));
+                    }
+
+                    match output.status.code() {
+                        // The key was already validated, so this means it is not set.
+                        Some(0) | Some(EXIT_CODE_INVALID_KEY) => match parse_config_values(
+                            &output.stdout,
+                            &key,
+                            value_type,
+                            repo_path.as_ref().map(|repo_path| &repo_path.path[..]),
+                        ) {
+                            Ok(entries) => {
+                                Ok((OutboundMessage::Success { entries }, connection_state))
+                            }
+                            Err(err) => Err((err, connection_state)),
+                        },
+                        Some(EXIT_CODE_FATAL) if value_type.is_some() => {
+                            Ok((OutboundMessage::Error(InvalidValueForType), connection_state))
+                        }
+                        _ => Err((Error::Process(Git(classify(&output))), connection_state)),
+                    }
+                }
+                Err(_) => Err((Error::Process(Failed), connection_state)),
+            })
+            .and_then(|(message, connection_state)| send_message(connection_state, message)),
+    )
+}
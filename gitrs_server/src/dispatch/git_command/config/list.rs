@@ -0,0 +1,65 @@
+use super::parse::{parse_config_list, ConfigEntry};
+use super::{is_invalid_config_file, is_missing_config_file, new_config_command, ErrorReason};
+use futures::Future;
+use message::protocol::git_command::config::Scope;
+use state;
+use tokio_process::CommandExt;
+use types::DispatchFuture;
+use util::git_error::classify;
+use util::transport::send_message;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "ConfigListOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Success { entries: Vec<ConfigEntry> },
+    Error(ErrorReason),
+}
+
+pub fn dispatch(connection_state: state::Connection, scope: Option<Scope>) -> DispatchFuture {
+    use self::ErrorReason::InvalidConfigFile;
+    use error::protocol::{Error, ProcessError::{Failed, Git}};
+
+    let repo_path = connection_state.repo_path.clone();
+    let mut command = match new_config_command(repo_path.clone(), scope, None) {
+        Ok(command) => command,
+        Err(reason) => {
+            return Box::new(send_message(connection_state, OutboundMessage::Error(reason)))
+        }
+    };
+
+    Box::new(
+        command
+            .arg("--show-origin")
+            .arg("-z")
+            .arg("--list")
+            .output_async()
+            .then(move |result| match result {
+                Ok(output) => {
+                    if is_invalid_config_file(&output) {
+                        return Ok((OutboundMessage::Error(InvalidConfigFile), connection_state));
+                    }
+
+                    // A scope whose file does not exist yet simply has no entries.
+                    if is_missing_config_file(&output) {
+                        return Ok((
+                            OutboundMessage::Success { entries: Vec::new() },
+                            connection_state,
+                        ));
+                    }
+
+                    if !output.status.success() {
+                        return Err((Error::Process(Git(classify(&output))), connection_state));
+                    }
+
+                    let repo_path = repo_path.as_ref().map(|repo_path| &repo_path.path[..]);
+                    match parse_config_list(&output.stdout, repo_path) {
+                        Ok(entries) => Ok((OutboundMessage::Success { entries }, connection_state)),
+                        Err(err) => Err((err, connection_state)),
+                    }
+                }
+                Err(_) => Err((Error::Process(Failed), connection_state)),
+            })
+            .and_then(|(message, connection_state)| send_message(connection_state, message)),
+    )
+}
@@ -0,0 +1,177 @@
+mod get;
+mod list;
+mod parse;
+mod set;
+mod unset;
+
+use message::protocol::git_command::config::{self, Scope, ValueType};
+use schema::CommandSchema;
+use schemars::gen::SchemaGenerator;
+use state;
+use std::process::{Command, Output};
+use types::DispatchFuture;
+use util::git::{self, RepoPath};
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "ConfigErrorReason")]
+#[serde(tag = "reason")]
+pub enum ErrorReason {
+    CannotWriteConfigFile,
+    InvalidConfigFile,
+    InvalidKey,
+    InvalidValueForType,
+    KeyNotFound,
+    RepoPathNotSet,
+}
+
+// Exit codes documented in git-config(1).
+const EXIT_CODE_INVALID_KEY: i32 = 1;
+const EXIT_CODE_INVALID_CONFIG_FILE: i32 = 3;
+const EXIT_CODE_CANNOT_WRITE_CONFIG_FILE: i32 = 4;
+const EXIT_CODE_NO_SUCH_KEY: i32 = 5;
+const EXIT_CODE_FATAL: i32 = 128;
+
+fn verify_key(key: &str) -> Result<(), ErrorReason> {
+    use self::ErrorReason::InvalidKey;
+
+    let (section, name) = match (key.find('.'), key.rfind('.')) {
+        (Some(first_dot), Some(last_dot)) => (&key[..first_dot], &key[last_dot + 1..]),
+        _ => return Err(InvalidKey),
+    };
+
+    let is_valid = !section.is_empty()
+        && section.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        && name.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        && !key.contains('\n');
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(InvalidKey)
+    }
+}
+
+// Global and system configuration can be read without an open repo, but every other scope
+// (including git's default lookup across all scopes) needs one.
+fn new_config_command(
+    repo_path: Option<RepoPath>,
+    scope: Option<Scope>,
+    value_type: Option<ValueType>,
+) -> Result<Command, ErrorReason> {
+    use self::ErrorReason::RepoPathNotSet;
+
+    let mut command = match (repo_path, scope) {
+        (Some(repo_path), _) => git::new_command_with_repo_path(&repo_path),
+        (None, Some(Scope::Global)) | (None, Some(Scope::System)) => git::new_command(),
+        (None, _) => return Err(RepoPathNotSet),
+    };
+    command.arg("config");
+
+    match scope {
+        Some(Scope::Global) => command.arg("--global"),
+        Some(Scope::Local) => command.arg("--local"),
+        Some(Scope::System) => command.arg("--system"),
+        Some(Scope::Worktree) => command.arg("--worktree"),
+        None => &mut command,
+    };
+
+    match value_type {
+        Some(ValueType::Bool) => command.arg("--bool"),
+        Some(ValueType::Int) => command.arg("--int"),
+        Some(ValueType::Path) => command.arg("--path"),
+        None => &mut command,
+    };
+
+    Ok(command)
+}
+
+fn is_missing_config_file(output: &Output) -> bool {
+    output.status.code() == Some(EXIT_CODE_FATAL)
+        && String::from_utf8_lossy(&output.stderr).contains("No such file or directory")
+}
+
+fn is_invalid_config_file(output: &Output) -> bool {
+    match output.status.code() {
+        Some(EXIT_CODE_INVALID_CONFIG_FILE) => true,
+        Some(EXIT_CODE_FATAL) => String::from_utf8_lossy(&output.stderr).contains("bad config"),
+        _ => false,
+    }
+}
+
+pub fn dispatch(connection_state: state::Connection, message: config::Inbound) -> DispatchFuture {
+    use self::config::Inbound;
+
+    match message {
+        Inbound::Get {
+            key,
+            scope,
+            value_type,
+        } => get::dispatch(connection_state, key, scope, value_type),
+        Inbound::List { scope } => list::dispatch(connection_state, scope),
+        Inbound::Set {
+            key,
+            value,
+            scope,
+            value_type,
+        } => set::dispatch(connection_state, key, value, scope, value_type),
+        Inbound::Unset { key, scope } => unset::dispatch(connection_state, key, scope),
+    }
+}
+
+pub fn command_schema(generator: &mut SchemaGenerator) -> CommandSchema {
+    CommandSchema::group(
+        "Config",
+        vec![
+            CommandSchema::new::<get::OutboundMessage>("Get", generator),
+            CommandSchema::new::<list::OutboundMessage>("List", generator),
+            CommandSchema::new::<set::OutboundMessage>("Set", generator),
+            CommandSchema::new::<unset::OutboundMessage>("Unset", generator),
+        ],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_invalid_config_file, is_missing_config_file};
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use tempfile;
+    use util::test_repo::TestRepo;
+
+    #[test]
+    fn recognizes_an_invalid_config_file() {
+        let repo = TestRepo::new();
+        OpenOptions::new()
+            .append(true)
+            .open(repo.join(".git/config"))
+            .and_then(|mut config| config.write_all(b"[core\nbad"))
+            .unwrap();
+
+        for args in &[
+            &["config", "--get", "core.bare"][..],
+            &["config", "--local", "--list"],
+            &["config", "--local", "x.y", "1"],
+            &["config", "--local", "--unset", "x.y"],
+        ] {
+            let output = repo.run(args);
+            assert!(is_invalid_config_file(&output), "{:?}", args);
+            assert!(!is_missing_config_file(&output), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn recognizes_a_missing_config_file() {
+        let repo = TestRepo::new();
+        let home = tempfile::tempdir().unwrap();
+        let output = ::util::git::new_command_with_repo_path(&repo.repo_path())
+            .env("HOME", home.path())
+            .env("XDG_CONFIG_HOME", home.path())
+            .args(["config", "--global", "--list"])
+            .output()
+            .unwrap();
+
+        assert!(is_missing_config_file(&output));
+        assert!(!is_invalid_config_file(&output));
+    }
+}
@@ -0,0 +1,217 @@
+use error::protocol::{Error, ProcessError::Parsing};
+use message::protocol::git_command::config::ValueType;
+use std::path::Path;
+use std::str;
+use util::lossless::LosslessString;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(tag = "type")]
+pub enum ConfigOrigin {
+    Blob { name: LosslessString },
+    CommandLine,
+    File { path: LosslessString },
+    StandardInput,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ConfigValue {
+    Bool(bool),
+    Int(i64),
+    String(LosslessString),
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ConfigEntry {
+    key: LosslessString,
+    origin: ConfigOrigin,
+    value: Option<ConfigValue>,
+}
+
+named!(parse_list_entry<&[u8], (&[u8], &[u8], Option<&[u8]>)>,
+    do_parse!(
+        origin: take_until!("\0") >>
+        char!('\0') >>
+        key: take_till!(|c| c == b'\n' || c == b'\0') >>
+        value: opt!(preceded!(char!('\n'), take_until!("\0"))) >>
+        char!('\0') >>
+        ((origin, key, value))
+    )
+);
+
+named!(parse_list_entries<&[u8], Vec<(&[u8], &[u8], Option<&[u8]>)>>,
+    many0!(complete!(parse_list_entry))
+);
+
+named!(parse_get_entry<&[u8], (&[u8], &[u8])>,
+    do_parse!(
+        origin: take_until!("\0") >>
+        char!('\0') >>
+        value: take_until!("\0") >>
+        char!('\0') >>
+        ((origin, value))
+    )
+);
+
+named!(parse_get_entries<&[u8], Vec<(&[u8], &[u8])>>,
+    many0!(complete!(parse_get_entry))
+);
+
+// Origins of the local and worktree scopes are reported relative to the repo. A path that is not
+// valid UTF-8 cannot be joined onto it and is passed on as it is.
+fn parse_origin(origin: &[u8], repo_path: Option<&str>) -> Result<ConfigOrigin, Error> {
+    if origin.starts_with(b"file:") {
+        let path = LosslessString::from(&origin[b"file:".len()..]);
+        let path = match (path, repo_path) {
+            (LosslessString::Utf8(ref path), Some(repo_path)) if Path::new(path).is_relative() => {
+                LosslessString::Utf8(Path::new(repo_path).join(path).to_string_lossy().into_owned())
+            }
+            (path, _) => path,
+        };
+        Ok(ConfigOrigin::File { path })
+    } else if origin.starts_with(b"blob:") {
+        Ok(ConfigOrigin::Blob {
+            name: LosslessString::from(&origin[b"blob:".len()..]),
+        })
+    } else if origin.starts_with(b"command line:") {
+        Ok(ConfigOrigin::CommandLine)
+    } else if origin.starts_with(b"standard input:") {
+        Ok(ConfigOrigin::StandardInput)
+    } else {
+        Err(Error::Process(Parsing))
+    }
+}
+
+fn parse_value(value: &[u8], value_type: Option<ValueType>) -> Result<ConfigValue, Error> {
+    match value_type {
+        Some(ValueType::Bool) => match value {
+            b"true" => Ok(ConfigValue::Bool(true)),
+            b"false" => Ok(ConfigValue::Bool(false)),
+            _ => Err(Error::Process(Parsing)),
+        },
+        Some(ValueType::Int) => str::from_utf8(value)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .map(ConfigValue::Int)
+            .ok_or(Error::Process(Parsing)),
+        Some(ValueType::Path) | None => Ok(ConfigValue::String(LosslessString::from(value))),
+    }
+}
+
+pub fn parse_config_list(input: &[u8], repo_path: Option<&str>) -> Result<Vec<ConfigEntry>, Error> {
+    match parse_list_entries(input) {
+        Ok(([], entries)) => entries
+            .into_iter()
+            .map(|(origin, key, value)| {
+                Ok(ConfigEntry {
+                    key: LosslessString::from(key),
+                    origin: parse_origin(origin, repo_path)?,
+                    value: value.map(|value| ConfigValue::String(LosslessString::from(value))),
+                })
+            })
+            .collect(),
+        _ => Err(Error::Process(Parsing)),
+    }
+}
+
+pub fn parse_config_values(
+    input: &[u8],
+    key: &str,
+    value_type: Option<ValueType>,
+    repo_path: Option<&str>,
+) -> Result<Vec<ConfigEntry>, Error> {
+    match parse_get_entries(input) {
+        Ok(([], entries)) => entries
+            .into_iter()
+            .map(|(origin, value)| {
+                Ok(ConfigEntry {
+                    key: LosslessString::Utf8(String::from(key)),
+                    origin: parse_origin(origin, repo_path)?,
+                    value: Some(parse_value(value, value_type)?),
+                })
+            })
+            .collect(),
+        _ => Err(Error::Process(Parsing)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_config_list, parse_config_values, ConfigOrigin, ConfigValue};
+    use message::protocol::git_command::config::ValueType;
+    use util::lossless::LosslessString;
+
+    fn utf8(text: &str) -> LosslessString {
+        LosslessString::Utf8(String::from(text))
+    }
+
+    #[test]
+    fn lists_entries_with_their_origins() {
+        let input: &[u8] = b"file:.git/config\0core.bare\nfalse\0\
+            file:/home/user/.gitconfig\0user.name\nFirst\nSecond\0\
+            command line:\0core.flag\0\
+            blob:HEAD:.gitmodules\0submodule.lib.path\nlib\0\
+            standard input:\0alias.st\nstatus\0";
+
+        let entries = parse_config_list(input, Some("/repo")).unwrap();
+        assert_eq!(entries.len(), 5);
+
+        assert_eq!(entries[0].key, utf8("core.bare"));
+        assert!(matches!(
+            entries[0].origin,
+            ConfigOrigin::File { ref path } if *path == utf8("/repo/.git/config")
+        ));
+        assert!(matches!(
+            entries[1].value,
+            Some(ConfigValue::String(ref value)) if *value == utf8("First\nSecond")
+        ));
+        assert!(matches!(entries[2].origin, ConfigOrigin::CommandLine));
+        // A key without a value, as `[core] flag` gives, is not the same as an empty one.
+        assert!(entries[2].value.is_none());
+        assert!(matches!(
+            entries[3].origin,
+            ConfigOrigin::Blob { ref name } if *name == utf8("HEAD:.gitmodules")
+        ));
+        assert!(matches!(entries[4].origin, ConfigOrigin::StandardInput));
+    }
+
+    #[test]
+    fn keeps_paths_and_values_that_are_not_utf8() {
+        let input: &[u8] = b"file:caf\xe9/config\0user.name\nJos\xe9\0";
+
+        let entries = parse_config_list(input, Some("/repo")).unwrap();
+        assert!(matches!(
+            entries[0].origin,
+            ConfigOrigin::File { path: LosslessString::Bytes { ref bytes, .. } }
+                if &bytes[..] == b"caf\xe9/config"
+        ));
+        assert!(matches!(
+            entries[0].value,
+            Some(ConfigValue::String(LosslessString::Bytes { ref bytes, .. }))
+                if &bytes[..] == b"Jos\xe9"
+        ));
+    }
+
+    #[test]
+    fn converts_values_to_their_type() {
+        let input: &[u8] = b"file:.git/config\0true\0file:.git/config\0false\0";
+        let entries =
+            parse_config_values(input, "core.bare", Some(ValueType::Bool), None).unwrap();
+        assert!(matches!(entries[0].value, Some(ConfigValue::Bool(true))));
+        assert!(matches!(entries[1].value, Some(ConfigValue::Bool(false))));
+        assert_eq!(entries[0].key, utf8("core.bare"));
+
+        let input: &[u8] = b"file:.git/config\0-1048576\0";
+        let entries = parse_config_values(input, "pack.size", Some(ValueType::Int), None).unwrap();
+        assert!(matches!(entries[0].value, Some(ConfigValue::Int(-1_048_576))));
+
+        let input: &[u8] = b"file:.git/config\0many\0";
+        assert!(parse_config_values(input, "pack.size", Some(ValueType::Int), None).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(parse_config_list(b"file:.git/config\0core.bare\nfalse", None).is_err());
+        assert!(parse_config_list(b"somewhere:\0core.bare\nfalse\0", None).is_err());
+    }
+}
@@ -0,0 +1,69 @@
+use super::{is_invalid_config_file, new_config_command, verify_key, ErrorReason,
+            EXIT_CODE_CANNOT_WRITE_CONFIG_FILE, EXIT_CODE_FATAL};
+use futures::Future;
+use message::protocol::git_command::config::{Scope, ValueType};
+use state;
+use tokio_process::CommandExt;
+use types::DispatchFuture;
+use util::git_error::classify;
+use util::transport::send_message;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "ConfigSetOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Success,
+    Error(ErrorReason),
+}
+
+pub fn dispatch(
+    connection_state: state::Connection,
+    key: String,
+    value: String,
+    scope: Option<Scope>,
+    value_type: Option<ValueType>,
+) -> DispatchFuture {
+    use self::ErrorReason::{CannotWriteConfigFile, InvalidConfigFile, InvalidValueForType};
+    use error::protocol::{Error, ProcessError::{Failed, Git}};
+
+    if let Err(reason) = verify_key(&key) {
+        return Box::new(send_message(connection_state, OutboundMessage::Error(reason)));
+    }
+
+    let mut command = match new_config_command(connection_state.repo_path.clone(), scope, value_type)
+    {
+        Ok(command) => command,
+        Err(reason) => {
+            return Box::new(send_message(connection_state, OutboundMessage::Error(reason)))
+        }
+    };
+
+    // Settings are edited as a whole, so every existing value of a multi-valued key is replaced.
+    Box::new(
+        command
+            .arg("--replace-all")
+            .arg(key)
+            .arg(value)
+            .output_async()
+            .then(move |result| match result {
+                Ok(output) => {
+                    if is_invalid_config_file(&output) {
+                        return Ok((OutboundMessage::Error(InvalidConfigFile), connection_state));
+                    }
+
+                    match output.status.code() {
+                        Some(0) => Ok((OutboundMessage::Success, connection_state)),
+                        Some(EXIT_CODE_CANNOT_WRITE_CONFIG_FILE) => {
+                            Ok((OutboundMessage::Error(CannotWriteConfigFile), connection_state))
+                        }
+                        Some(EXIT_CODE_FATAL) if value_type.is_some() => {
+                            Ok((OutboundMessage::Error(InvalidValueForType), connection_state))
+                        }
+                        _ => Err((Error::Process(Git(classify(&output))), connection_state)),
+                    }
+                }
+                Err(_) => Err((Error::Process(Failed), connection_state)),
+            })
+            .and_then(|(message, connection_state)| send_message(connection_state, message)),
+    )
+}
@@ -1,54 +1,117 @@
 mod parse;
 
 use self::parse::{parse_log, LogEntry};
+use encoding_rs::{Encoding, UTF_8};
+use error::protocol::{Error, ProcessError::{Failed, Git, Parsing}};
 use futures::{future, Future};
 use state;
-use std::str;
 use tokio_process::CommandExt;
 use types::DispatchFuture;
-use util::git;
+use util::git::{self, RepoPath};
+use util::git_error::classify;
 use util::transport::send_message;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "LogErrorReason")]
 #[serde(tag = "reason")]
 pub enum ErrorReason {
     RepoHasNoCommits,
     RepoPathNotSet,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "LogOutbound")]
 #[serde(tag = "type")]
 pub enum OutboundMessage {
     Success { log: Vec<LogEntry> },
     Error(ErrorReason),
 }
 
+/// Reads the encoding git re-encodes commit messages into, `i18n.logOutputEncoding`, which falls
+/// back to `i18n.commitEncoding` and then to UTF-8. Encodings git knows but the decoder does not
+/// are read as UTF-8, which at worst flags the affected fields.
+pub fn read_log_output_encoding(
+    repo_path: &RepoPath,
+) -> impl Future<Item = &'static Encoding, Error = Error> {
+    git::new_command_with_repo_path(repo_path)
+        .arg("config")
+        .arg("--get-regexp")
+        .arg(r"^i18n\.(logoutputencoding|commitencoding)$")
+        .output_async()
+        .map_err(|_| Error::Process(Failed))
+        .map(|output| {
+            let mut log_output_encoding = None;
+            let mut commit_encoding = None;
+            for line in String::from_utf8_lossy(&output.stdout).lines() {
+                let mut fields = line.splitn(2, ' ');
+                match (fields.next(), fields.next()) {
+                    (Some("i18n.logoutputencoding"), Some(label)) => {
+                        log_output_encoding = Encoding::for_label(label.trim().as_bytes())
+                    }
+                    (Some("i18n.commitencoding"), Some(label)) => {
+                        commit_encoding = Encoding::for_label(label.trim().as_bytes())
+                    }
+                    _ => {}
+                }
+            }
+
+            log_output_encoding.or(commit_encoding).unwrap_or(UTF_8)
+        })
+}
+
+// Git fails to log a branch without commits, with a message that depends on the locale. Resolving
+// HEAD quietly fails without a word in that case, so it tells the two apart.
+fn is_unborn(repo_path: &RepoPath) -> impl Future<Item = bool, Error = Error> {
+    git::new_command_with_repo_path(repo_path)
+        .arg("rev-parse")
+        .arg("--verify")
+        .arg("--quiet")
+        .arg("HEAD^{commit}")
+        .output_async()
+        .map_err(|_| Error::Process(Failed))
+        .map(|output| !output.status.success() && output.stderr.is_empty())
+}
+
 pub fn dispatch(connection_state: state::Connection) -> DispatchFuture {
     use self::ErrorReason::{RepoHasNoCommits, RepoPathNotSet};
-    use error::protocol::{Error, ProcessError::{Encoding, Failed, Parsing}};
 
     match connection_state.repo_path.clone() {
         Some(repo_path) => Box::new(
-            git::new_command_with_repo_path(&repo_path)
-                .arg("log")
-                .arg("--format=sha %H%nparents %P%nauthor %an%nemail %ae%ndate %ai%nsummary %s%ndescription %b%x00%x00")
-                .output_async()
+            read_log_output_encoding(&repo_path)
+                .and_then(move |encoding| {
+                    git::new_command_with_repo_path(&repo_path)
+                        .arg("log")
+                        .arg("--format=sha %H%nparents %P%nauthor %an%nemail %ae%ndate %ai%nsummary %s%ndescription %b%x00%x00")
+                        .output_async()
+                        .map_err(|_| Error::Process(Failed))
+                        .and_then(move |output| -> Box<dyn Future<Item = _, Error = _> + Send> {
+                            if output.status.success() {
+                                return Box::new(future::ok(Some((output.stdout, encoding))));
+                            }
+
+                            Box::new(is_unborn(&repo_path).and_then(move |is_unborn| {
+                                if is_unborn {
+                                    Ok(None)
+                                } else {
+                                    Err(Error::Process(Git(classify(&output))))
+                                }
+                            }))
+                        })
+                })
                 .then(|result| match result {
-                    Ok(output) => match str::from_utf8(&output.stdout) {
-                        Ok(output) => future::ok((String::from(output), connection_state)),
-                        Err(_) => future::err((Error::Process(Encoding), connection_state)),
-                    },
-                    Err(_) => future::err((Error::Process(Failed), connection_state)),
+                    Ok(log) => future::ok((log, connection_state)),
+                    Err(err) => future::err((err, connection_state)),
                 })
-                .and_then(|(result, connection_state)| -> DispatchFuture {
-                    if result.len() == 0 {
-                        return Box::new(send_message(
+                .and_then(|(log, connection_state)| -> DispatchFuture {
+                    let (result, encoding) = match log {
+                        Some(log) => log,
+                        None => return Box::new(send_message(
                             connection_state,
                             OutboundMessage::Error(RepoHasNoCommits)
-                        ));
-                    }
+                        )),
+                    };
 
-                    match parse_log(&result) {
+                    match parse_log(&result, encoding) {
                         Ok(log) => Box::new(
                             send_message(connection_state, OutboundMessage::Success { log })
                         ),
@@ -61,3 +124,22 @@ pub fn dispatch(connection_state: state::Connection) -> DispatchFuture {
         None => Box::new(send_message(connection_state, OutboundMessage::Error(RepoPathNotSet))),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::is_unborn;
+    use std::fs;
+    use util::test_repo::{block_on, TestRepo};
+
+    #[test]
+    fn tells_an_unborn_branch_from_a_broken_repo() {
+        let repo = TestRepo::new();
+        assert!(block_on(is_unborn(&repo.repo_path())).unwrap());
+
+        repo.commit("Initial commit");
+        assert!(!block_on(is_unborn(&repo.repo_path())).unwrap());
+
+        fs::remove_dir_all(repo.join(".git/objects")).unwrap();
+        assert!(!block_on(is_unborn(&repo.repo_path())).unwrap());
+    }
+}
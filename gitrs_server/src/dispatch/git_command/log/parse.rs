@@ -1,61 +1,55 @@
+use encoding_rs::Encoding;
 use error::protocol::{Error, ProcessError::Parsing};
-use util::parse::sha;
+use util::lossless::LosslessString;
+use util::parse::sha_bytes;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct TreeInfo {
     sha: String,
     parents: Vec<String>,
 }
 
-#[derive(Debug, Serialize)]
-pub struct SignatureInfo {
-    author: String,
-    email: String,
-    date: String,
-}
-#[derive(Debug, Serialize)]
-pub struct BodyInfo {
-    summary: String,
-    description: String,
-}
+// Names and messages are still in the log output encoding, so the signature and body are only
+// decoded once the entry is complete.
+type RawLogEntry<'a> = (TreeInfo, (&'a [u8], &'a [u8], &'a [u8]), (&'a [u8], &'a [u8]));
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct LogEntry {
-    author: String,
+    author: LosslessString,
     date: String,
-    description: String,
-    email: String,
+    description: LosslessString,
+    email: LosslessString,
     parents: Vec<String>,
     sha: String,
-    summary: String,
+    summary: LosslessString,
 }
 
-named!(pub parse_parent_entries<&str, Vec<String>>,
+named!(pub parse_parent_entries<&[u8], Vec<String>>,
     switch!(peek!(take!(1)),
-        "\n" => value!(Vec::new()) |
+        b"\n" => value!(Vec::new()) |
         _ => separated_list!(
             char!(' '),
-            map!(sha, String::from)
+            map!(sha_bytes, String::from)
         )
     )
 );
 
-named!(pub parse_tree<&str, TreeInfo>,
+named!(pub parse_tree<&[u8], TreeInfo>,
     do_parse!(
         tag!("sha ") >>
-        sha: sha >>
+        sha: sha_bytes >>
         char!('\n') >>
         tag!("parents ") >>
         parents: parse_parent_entries >>
         char!('\n') >>
         (TreeInfo {
             sha: String::from(sha),
-            parents: parents,
+            parents,
         })
     )
 );
 
-named!(pub parse_signature<&str, SignatureInfo>,
+named!(pub parse_signature<&[u8], (&[u8], &[u8], &[u8])>,
     do_parse!(
         tag!("author ") >>
         author: take_until!("\n") >>
@@ -66,59 +60,128 @@ named!(pub parse_signature<&str, SignatureInfo>,
         tag!("date ") >>
         date: take_until!("\n") >>
         char!('\n') >>
-        (SignatureInfo {
-            author: String::from(author),
-            email: String::from(email),
-            date: String::from(date),
-        })
+        ((author, email, date))
     )
 );
 
-named!(pub parse_body<&str, BodyInfo>,
+named!(pub parse_body<&[u8], (&[u8], &[u8])>,
     do_parse!(
         tag!("summary ") >>
         summary: take_until!("\n") >>
         char!('\n') >>
         tag!("description ") >>
         description: take_until!("\0\0\n") >>
-        (BodyInfo {
-            summary: String::from(summary),
-            description: String::from(description),
-        })
+        ((summary, description))
     )
 );
 
-named!(pub parse_log_entry<&str, LogEntry>,
+named!(pub parse_log_entry<&[u8], RawLogEntry<'_>>,
     do_parse!(
         tree_info: parse_tree >>
         signature_info: parse_signature >>
         body_info: parse_body >>
-        (LogEntry {
-            author: signature_info.author,
-            date: signature_info.date,
-            description: body_info.description,
-            email: signature_info.email,
-            parents: tree_info.parents,
-            sha: tree_info.sha,
-            summary: body_info.summary,
-        })
+        ((tree_info, signature_info, body_info))
     )
 );
 
-named!(pub parse_log_entries<&str, Vec<LogEntry>>,
-    do_parse!(
-        entries: separated_list!(
-            tag!("\0\0\n"),
-            complete!(parse_log_entry)
-        ) >>
-        (entries)
-    )
+// Every entry, the last one included, ends with the separator git adds after the format.
+named!(pub parse_log_entries<&[u8], Vec<RawLogEntry<'_>>>,
+    many0!(complete!(terminated!(parse_log_entry, tag!("\0\0\n"))))
 );
 
-pub fn parse_log(input: &str) -> Result<Vec<LogEntry>, Error> {
-    let input = String::from(input);
+pub fn parse_log(input: &[u8], encoding: &'static Encoding) -> Result<Vec<LogEntry>, Error> {
+    match parse_log_entries(input) {
+        Ok(([], entries)) => Ok(entries
+            .into_iter()
+            .map(|(tree_info, (author, email, date), (summary, description))| LogEntry {
+                author: LosslessString::decode(author, encoding),
+                date: String::from_utf8_lossy(date).into_owned(),
+                description: LosslessString::decode(description, encoding),
+                email: LosslessString::decode(email, encoding),
+                parents: tree_info.parents,
+                sha: tree_info.sha,
+                summary: LosslessString::decode(summary, encoding),
+            })
+            .collect()),
+        _ => Err(Error::Process(Parsing)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_log;
+    use encoding_rs::{UTF_8, WINDOWS_1252};
+    use util::lossless::LosslessString;
+
+    const SHA_A: &str = "1111111111111111111111111111111111111111";
+    const SHA_B: &str = "2222222222222222222222222222222222222222";
+    const SHA_C: &str = "3333333333333333333333333333333333333333";
+
+    // Laid out the way the `--format` in `log::dispatch` has git print an entry.
+    fn entry(sha: &str, parents: &[&str], author: &[u8], summary: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut entry = format!("sha {}\nparents {}\nauthor ", sha, parents.join(" ")).into_bytes();
+        entry.extend_from_slice(author);
+        entry.extend_from_slice(b"\nemail a@example.com\ndate 2020-01-02 03:04:05 +0100\nsummary ");
+        entry.extend_from_slice(summary);
+        entry.extend_from_slice(b"\ndescription ");
+        entry.extend_from_slice(body);
+        entry.extend_from_slice(b"\0\0\n");
+        entry
+    }
+
+    fn utf8(text: &str) -> LosslessString {
+        LosslessString::Utf8(String::from(text))
+    }
+
+    #[test]
+    fn parses_merges_and_root_commits() {
+        let mut input = entry(SHA_A, &[SHA_B, SHA_C], b"Merger", b"Merge branch 'topic'", b"");
+        input.extend(entry(SHA_C, &[], b"Rooter", b"Initial commit", b""));
+
+        let log = parse_log(&input, UTF_8).unwrap();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].sha, SHA_A);
+        assert_eq!(log[0].parents, vec![String::from(SHA_B), String::from(SHA_C)]);
+        assert_eq!(log[0].summary, utf8("Merge branch 'topic'"));
+        assert_eq!(log[0].date, "2020-01-02 03:04:05 +0100");
+        assert!(log[1].parents.is_empty());
+        assert_eq!(log[1].author, utf8("Rooter"));
+    }
+
+    #[test]
+    fn keeps_newlines_and_tabs_in_the_description() {
+        let body = b"First paragraph\twith a tab.\n\nsha looks like a field\ndescription too\n";
+        let input = entry(SHA_A, &[SHA_B], b"Author", b"Summary", body);
+
+        let log = parse_log(&input, UTF_8).unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(
+            log[0].description,
+            utf8("First paragraph\twith a tab.\n\nsha looks like a field\ndescription too\n")
+        );
+    }
+
+    #[test]
+    fn decodes_the_log_output_encoding() {
+        let input = entry(SHA_A, &[], b"Jos\xe9", b"Caf\xe9", b"");
+
+        let log = parse_log(&input, WINDOWS_1252).unwrap();
+        assert_eq!(log[0].author, utf8("Jos\u{e9}"));
+        assert_eq!(log[0].summary, utf8("Caf\u{e9}"));
+
+        // The same bytes are not UTF-8, and are kept as they are.
+        let log = parse_log(&input, UTF_8).unwrap();
+        assert!(matches!(
+            log[0].author,
+            LosslessString::Bytes { ref bytes, .. } if &bytes[..] == b"Jos\xe9"
+        ));
+    }
 
-    parse_log_entries(&input)
-        .map_err(|_| Error::Process(Parsing))
-        .map(|(_, vec)| vec)
+    #[test]
+    fn rejects_a_truncated_entry() {
+        let mut input = entry(SHA_A, &[], b"Author", b"Summary", b"");
+        let second = entry(SHA_B, &[], b"Author", b"Summary", b"");
+        input.extend_from_slice(&second[..second.len() - 3]);
+        assert!(parse_log(&input, UTF_8).is_err());
+    }
 }
@@ -3,9 +3,11 @@ use state;
 use tokio_process::CommandExt;
 use types::DispatchFuture;
 use util::git;
+use util::git_error::{classify, GitErrorKind};
 use util::transport::send_message;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "MergeBaseIsAncestorErrorReason")]
 #[serde(tag = "reason")]
 pub enum ErrorReason {
     AncestorMustBeASha,
@@ -14,7 +16,8 @@ pub enum ErrorReason {
     ShaIsNotACommit,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "MergeBaseIsAncestorOutbound")]
 #[serde(tag = "type")]
 pub enum OutboundMessage {
     Success { is_ancestor: bool },
@@ -28,7 +31,7 @@ pub fn dispatch(
 ) -> DispatchFuture {
     use self::ErrorReason::{AncestorMustBeASha, DescendantMustBeASha, RepoPathNotSet,
                             ShaIsNotACommit};
-    use error::protocol::{Error, ProcessError::Failed};
+    use error::protocol::{Error, ProcessError::{Failed, Git}};
 
     if !git::verify_string_is_sha(&ancestor_sha) {
         return Box::new(send_message(
@@ -51,24 +54,24 @@ pub fn dispatch(
                 .arg("--is-ancestor")
                 .arg(ancestor_sha)
                 .arg(descendant_sha)
-                .output_async() // NOTE we should parse error messages someday
+                .output_async()
                 .then(|result| match result {
                     Ok(output) => match output.status.code() {
-                        Some(status) => future::ok((status, connection_state)),
-                        None => future::err((Error::Process(Failed), connection_state)),
+                        Some(status @ 0) | Some(status @ 1) => future::ok((
+                            OutboundMessage::Success { is_ancestor: status == 0 },
+                            connection_state,
+                        )),
+                        _ => match classify(&output) {
+                            ref err if err.kind == GitErrorKind::BadRevision => future::ok((
+                                OutboundMessage::Error(ShaIsNotACommit),
+                                connection_state,
+                            )),
+                            err => future::err((Error::Process(Git(err)), connection_state)),
+                        },
                     },
                     Err(_) => future::err((Error::Process(Failed), connection_state)),
                 })
-                .and_then(|(status, connection_state)| -> DispatchFuture {
-                    Box::new(send_message(
-                        connection_state,
-                        if status == 0 || status == 1 {
-                            OutboundMessage::Success { is_ancestor: status == 0 }
-                        } else {
-                            OutboundMessage::Error(ShaIsNotACommit)
-                        }
-                    ))
-                }),
+                .and_then(|(message, connection_state)| send_message(connection_state, message)),
         ),
         None => Box::new(send_message(
             connection_state,
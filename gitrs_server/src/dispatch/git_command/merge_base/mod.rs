@@ -1,6 +1,8 @@
 mod is_ancestor;
 
 use message::protocol::git_command::merge_base;
+use schema::CommandSchema;
+use schemars::gen::SchemaGenerator;
 use state;
 use types::DispatchFuture;
 
@@ -17,3 +19,12 @@ pub fn dispatch(
         } => is_ancestor::dispatch(connection_state, ancestor_sha, descendant_sha),
     }
 }
+
+pub fn command_schema(generator: &mut SchemaGenerator) -> CommandSchema {
+    CommandSchema::group(
+        "MergeBase",
+        vec![
+            CommandSchema::new::<is_ancestor::OutboundMessage>("IsAncestor", generator),
+        ],
+    )
+}
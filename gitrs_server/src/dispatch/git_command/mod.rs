@@ -1,26 +1,186 @@
 mod bisect;
+mod close_repo;
+mod config;
 mod log;
 mod merge_base;
 mod open_repo;
+mod reflog;
+mod remote;
+mod repo_events;
+mod repo_info;
+mod reset;
 mod status;
+mod undo;
+mod unwatch;
+mod watch;
+mod worktree;
 
-use message::protocol::git_command;
+use futures::Future;
+use message::protocol::git_command::{self, Inbound};
+use schema::CommandSchema;
+use schemars::gen::SchemaGenerator;
 use state;
+use std::time::Duration;
 use types::DispatchFuture;
+use util::transport::send_message;
+use uuid::Uuid;
 
-pub fn dispatch(
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "GitCommandErrorReason")]
+#[serde(tag = "reason")]
+pub enum ErrorReason {
+    /// `held_by` is the `connection` the handshake gave the connection holding the lock.
+    RepoBusy {
+        #[schemars(with = "String")]
+        held_by: Uuid,
+        operation: String,
+    },
+    UnknownRepo,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "GitCommandOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Error(ErrorReason),
+}
+
+/// The messages each command answers with, see `schema::protocol_schema`.
+pub fn command_schemas(generator: &mut SchemaGenerator) -> Vec<CommandSchema> {
+    vec![
+        CommandSchema::interactive::<bisect::InboundMessage, bisect::OutboundMessage>(
+            "Bisect", generator,
+        ),
+        CommandSchema::new::<close_repo::OutboundMessage>("CloseRepo", generator),
+        config::command_schema(generator),
+        CommandSchema::new::<log::OutboundMessage>("Log", generator),
+        merge_base::command_schema(generator),
+        CommandSchema::new::<open_repo::OutboundMessage>("OpenRepo", generator),
+        CommandSchema::new::<reflog::OutboundMessage>("Reflog", generator),
+        remote::command_schema(generator),
+        CommandSchema::new::<repo_info::OutboundMessage>("RepoInfo", generator),
+        CommandSchema::new::<reset::OutboundMessage>("Reset", generator),
+        CommandSchema::new::<status::OutboundMessage>("Status", generator),
+        undo::command_schema(generator),
+        CommandSchema::new::<unwatch::OutboundMessage>("Unwatch", generator),
+        CommandSchema::streaming::<watch::OutboundMessage>("Watch", generator),
+        worktree::command_schema(generator),
+    ]
+}
+
+// Names the commands that write to the repository. Only one of them runs on a repository at a
+// time, and other connections get told about the refs they update and where they move HEAD.
+fn write_operation(command: &Inbound) -> Option<&'static str> {
+    use self::git_command::{config, remote, undo, worktree};
+
+    match *command {
+        Inbound::Bisect { .. } => Some("Bisect"),
+        Inbound::Config(config::Inbound::Set { .. })
+        | Inbound::Config(config::Inbound::Unset { .. }) => Some("Config"),
+        Inbound::Remote(remote::Inbound::List) => None,
+        Inbound::Remote(_) => Some("Remote"),
+        Inbound::Reset { .. } => Some("Reset"),
+        Inbound::Undo(undo::Inbound::Restore { .. }) => Some("Undo"),
+        Inbound::Worktree(worktree::Inbound::List) => None,
+        Inbound::Worktree(_) => Some("Worktree"),
+        _ => None,
+    }
+}
+
+/// Whether a command reads from the client while it runs. Such a command notices the client
+/// hanging up by itself, see `bisect::abort_on_disconnect`.
+pub fn reads_from_client(command: &Inbound) -> bool {
+    matches!(*command, Inbound::Bisect { .. })
+}
+
+fn dispatch_command(
     connection_state: state::Connection,
-    message: git_command::Inbound,
+    handle: Option<Uuid>,
+    command: Inbound,
 ) -> DispatchFuture {
-    use self::git_command::Inbound;
-
-    match message {
+    match command {
         Inbound::Bisect { bad, good } => bisect::dispatch(connection_state, bad, good),
+        Inbound::CloseRepo => close_repo::dispatch(connection_state, handle),
+        Inbound::Config(config_arguments) => config::dispatch(connection_state, config_arguments),
         Inbound::Log => log::dispatch(connection_state),
         Inbound::MergeBase(merge_base_arguments) => {
             merge_base::dispatch(connection_state, merge_base_arguments)
         }
-        Inbound::OpenRepo { path } => open_repo::dispatch(connection_state, path),
-        Inbound::Status => status::dispatch(connection_state),
+        Inbound::OpenRepo { path, git_dir } => {
+            open_repo::dispatch(connection_state, path, git_dir)
+        }
+        Inbound::Reflog {
+            reference,
+            skip,
+            max_count,
+        } => reflog::dispatch(connection_state, reference, skip, max_count),
+        Inbound::Remote(remote_arguments) => remote::dispatch(connection_state, remote_arguments),
+        Inbound::RepoInfo => repo_info::dispatch(connection_state),
+        Inbound::Reset {
+            target,
+            mode,
+            paths,
+        } => reset::dispatch(connection_state, target, mode, paths),
+        Inbound::Status(options) => {
+            status::dispatch(connection_state, options.unwrap_or_default())
+        }
+        Inbound::Undo(undo_arguments) => undo::dispatch(connection_state, undo_arguments),
+        Inbound::Unwatch => unwatch::dispatch(connection_state),
+        Inbound::Watch => watch::dispatch(connection_state),
+        Inbound::Worktree(worktree_arguments) => {
+            worktree::dispatch(connection_state, worktree_arguments)
+        }
+    }
+}
+
+pub fn dispatch(
+    mut connection_state: state::Connection,
+    request: git_command::Request,
+) -> DispatchFuture {
+    let handle = request.repo.or(connection_state.default_repo);
+    connection_state.repo_path = match handle {
+        Some(handle) => match connection_state.repo_path_by_handle.get(&handle) {
+            Some(repo_path) => Some(repo_path.clone()),
+            None => {
+                return Box::new(send_message(
+                    connection_state,
+                    OutboundMessage::Error(ErrorReason::UnknownRepo),
+                ))
+            }
+        },
+        None => None,
+    };
+
+    let command = request.command;
+    match (connection_state.repo_path.clone(), write_operation(&command)) {
+        (Some(repo_path), Some(operation)) => {
+            let timeout = request.lock_timeout_ms.map(Duration::from_millis);
+            let lock = connection_state.lock_repo(repo_path.path.clone(), operation, timeout);
+
+            Box::new(lock.then(move |result| -> DispatchFuture {
+                match result {
+                    Ok(guard) => Box::new(
+                        repo_events::broadcast_changes(
+                            connection_state,
+                            repo_path,
+                            move |connection_state| {
+                                dispatch_command(connection_state, handle, command)
+                            },
+                        ).then(move |result| {
+                            drop(guard);
+                            result
+                        }),
+                    ),
+                    Err(holder) => Box::new(send_message(
+                        connection_state,
+                        OutboundMessage::Error(ErrorReason::RepoBusy {
+                            held_by: holder.held_by,
+                            operation: holder.operation,
+                        }),
+                    )),
+                }
+            }))
+        }
+        _ => dispatch_command(connection_state, handle, command),
     }
 }
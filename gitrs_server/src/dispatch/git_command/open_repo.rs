@@ -1,9 +1,19 @@
+use error::protocol::{Error, ProcessError::{Failed, Parsing}};
+use futures::{future, Future};
 use state;
-use std::path::Path;
+use std::fs;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tokio_process::CommandExt;
 use types::DispatchFuture;
+use util::git::{self, RepoPath};
+use util::lossless::{os_string_from_bytes, LosslessString};
 use util::transport::send_message;
+use uuid::Uuid;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "OpenRepoErrorReason")]
 #[serde(tag = "reason")]
 pub enum ErrorReason {
     InvalidPath,
@@ -11,30 +21,208 @@ pub enum ErrorReason {
     IsNotRepo,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "OpenRepoOutbound")]
 #[serde(tag = "type")]
 pub enum OutboundMessage {
-    Success,
+    Success {
+        common_dir: LosslessString,
+        git_dir: LosslessString,
+        is_bare: bool,
+        #[schemars(with = "String")]
+        repo: Uuid,
+        workdir: Option<LosslessString>,
+    },
     Error(ErrorReason),
 }
 
-pub fn dispatch(mut connection_state: state::Connection, workdir_path: String) -> DispatchFuture {
+pub struct Discovery {
+    pub common_dir: PathBuf,
+    pub git_dir: PathBuf,
+    pub is_bare: bool,
+}
+
+// Each line git printed, as the exact bytes of a path.
+fn lines(stdout: &[u8]) -> impl Iterator<Item = PathBuf> + '_ {
+    stdout
+        .split(|&byte| byte == b'\n')
+        .map(|line| PathBuf::from(os_string_from_bytes(line)))
+}
+
+// `--git-common-dir` is printed relative to the directory git was run in unless it lies elsewhere.
+fn resolve_dir(path: &str, dir: &Path) -> PathBuf {
+    let dir = Path::new(path).join(dir);
+    fs::canonicalize(&dir).unwrap_or(dir)
+}
+
+// Runs git in `path` the way `GIT_DIR` would have it, which makes `path` the top of the working
+// tree when a git dir is given.
+fn new_command(path: &str, git_dir: Option<&Path>) -> Command {
+    let mut command = git::new_command();
+    command.current_dir(path);
+    if let Some(git_dir) = git_dir {
+        command.arg("--git-dir").arg(git_dir);
+    }
+    command
+}
+
+/// Finds the repository containing `path`, or the one at `git_dir` when given, resolving to `None`
+/// when there is none.
+pub fn discover(
+    path: String,
+    git_dir: Option<PathBuf>,
+) -> impl Future<Item = Option<Discovery>, Error = Error> {
+    new_command(&path, git_dir.as_deref())
+        .arg("rev-parse")
+        .arg("--is-bare-repository")
+        .arg("--absolute-git-dir")
+        .arg("--git-common-dir")
+        .output_async()
+        .map_err(|_| Error::Process(Failed))
+        .and_then(move |output| {
+            if !output.status.success() {
+                return Ok(None);
+            }
+
+            let mut lines = lines(&output.stdout);
+            match (lines.next(), lines.next(), lines.next()) {
+                (Some(is_bare), Some(git_dir), Some(common_dir)) => Ok(Some(Discovery {
+                    common_dir: resolve_dir(&path, &common_dir),
+                    git_dir,
+                    is_bare: is_bare == Path::new("true"),
+                })),
+                _ => Err(Error::Process(Parsing)),
+            }
+        })
+}
+
+// Fails inside a bare repository or a git dir, where there is no working tree to report.
+fn show_toplevel(
+    path: String,
+    git_dir: Option<PathBuf>,
+) -> impl Future<Item = Option<PathBuf>, Error = Error> {
+    new_command(&path, git_dir.as_deref())
+        .arg("rev-parse")
+        .arg("--show-toplevel")
+        .output_async()
+        .map_err(|_| Error::Process(Failed))
+        .map(|output| {
+            if !output.status.success() {
+                return None;
+            }
+
+            lines(&output.stdout)
+                .next()
+                .filter(|toplevel| !toplevel.as_os_str().is_empty())
+        })
+}
+
+pub fn dispatch(
+    mut connection_state: state::Connection,
+    path: String,
+    git_dir: Option<String>,
+) -> DispatchFuture {
     use self::ErrorReason::{InvalidPath, IsNotRepo, MustBeAbsolutePath};
-    let repo_path = Path::new(&workdir_path).join(".git");
-
-    Box::new(if repo_path.is_relative() {
-        send_message(connection_state, OutboundMessage::Error(MustBeAbsolutePath))
-    } else {
-        match repo_path.metadata() {
-            Ok(metadata) => {
-                if metadata.is_dir() {
-                    connection_state.repo_path = Some(workdir_path);
-                    send_message(connection_state, OutboundMessage::Success)
-                } else {
-                    send_message(connection_state, OutboundMessage::Error(IsNotRepo))
+
+    let paths = || iter::once(&path).chain(git_dir.iter()).map(Path::new);
+
+    if paths().any(Path::is_relative) {
+        return Box::new(send_message(
+            connection_state,
+            OutboundMessage::Error(MustBeAbsolutePath),
+        ));
+    }
+
+    if !paths().all(Path::is_dir) {
+        return Box::new(send_message(
+            connection_state,
+            OutboundMessage::Error(InvalidPath),
+        ));
+    }
+
+    let git_dir = git_dir.map(PathBuf::from);
+    let has_git_dir = git_dir.is_some();
+    Box::new(
+        discover(path.clone(), git_dir.clone())
+            .and_then({
+                let path = path.clone();
+                move |discovery| -> Box<dyn Future<Item = _, Error = _> + Send> {
+                    match discovery {
+                        Some(discovery) => if discovery.is_bare {
+                            Box::new(future::ok(Some((discovery, None))))
+                        } else {
+                            Box::new(
+                                show_toplevel(path, git_dir)
+                                    .map(|workdir| Some((discovery, workdir))),
+                            )
+                        },
+                        None => Box::new(future::ok(None)),
+                    }
                 }
-            }
-            Err(_) => send_message(connection_state, OutboundMessage::Error(InvalidPath)),
-        }
-    })
+            })
+            .then(move |result| match result {
+                Ok(Some((discovery, workdir))) => {
+                    // Commands run from the working tree when there is one and from the git dir
+                    // otherwise, where it needs no pointing at. Repositories are told apart by
+                    // where commands run from, which has to be UTF-8, so one whose directory is
+                    // not runs them from the path the client gave instead. That path lies within
+                    // it, and is the top of the working tree when a git dir is given.
+                    let repo_path = RepoPath {
+                        git_dir: if has_git_dir && workdir.is_some() {
+                            Some(discovery.git_dir.clone())
+                        } else {
+                            None
+                        },
+                        path: workdir
+                            .as_ref()
+                            .unwrap_or(&discovery.git_dir)
+                            .to_str()
+                            .map_or(path, String::from),
+                    };
+                    let repo = connection_state.open_repo(repo_path);
+                    Ok((
+                        OutboundMessage::Success {
+                            common_dir: LosslessString::from(discovery.common_dir.as_os_str()),
+                            git_dir: LosslessString::from(discovery.git_dir.as_os_str()),
+                            is_bare: discovery.is_bare,
+                            repo,
+                            workdir: workdir
+                                .map(|workdir| LosslessString::from(workdir.as_os_str())),
+                        },
+                        connection_state,
+                    ))
+                }
+                Ok(None) => Ok((OutboundMessage::Error(IsNotRepo), connection_state)),
+                Err(err) => Err((err, connection_state)),
+            })
+            .and_then(|(message, connection_state)| send_message(connection_state, message)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::discover;
+    use std::fs;
+    use std::path::PathBuf;
+    use tempfile;
+    use util::test_repo::{block_on, TestRepo};
+
+    #[test]
+    fn discovers_a_git_dir_kept_elsewhere() {
+        let repo = TestRepo::new();
+        let workdir = tempfile::tempdir().unwrap();
+        let workdir_path = String::from(workdir.path().to_str().unwrap());
+        let git_dir = repo.join(".git");
+
+        assert!(block_on(discover(workdir_path.clone(), None)).unwrap().is_none());
+
+        let discovery = block_on(discover(workdir_path, Some(PathBuf::from(&git_dir))))
+            .unwrap()
+            .unwrap();
+        assert!(!discovery.is_bare);
+        assert_eq!(
+            fs::canonicalize(discovery.git_dir).unwrap(),
+            fs::canonicalize(&git_dir).unwrap()
+        );
+    }
 }
@@ -0,0 +1,165 @@
+mod parse;
+
+pub use self::parse::ReflogEntry;
+
+use self::parse::{parse_reflog, parse_reflog_until};
+use error::protocol::Error;
+use futures::{future, Future};
+use state;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::Path;
+use tokio_process::CommandExt;
+use types::DispatchFuture;
+use util::git::{self, RepoPath};
+use util::git_error::classify;
+use util::lossless::os_string_from_bytes;
+use util::transport::send_message;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "ReflogErrorReason")]
+#[serde(tag = "reason")]
+pub enum ErrorReason {
+    InvalidRef,
+    RepoPathNotSet,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "ReflogOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Success { entries: Vec<ReflogEntry> },
+    Error(ErrorReason),
+}
+
+// The output of a command that prints a single line, without the newline.
+fn first_line(stdout: &[u8]) -> &[u8] {
+    stdout.split(|&byte| byte == b'\n').next().unwrap_or(stdout)
+}
+
+// `--symbolic-full-name` would resolve `HEAD` to the branch it points at, but `HEAD` keeps a
+// reflog of its own. Ref names are kept as the bytes git printed, since they need not be UTF-8.
+fn resolve_ref(
+    repo_path: &RepoPath,
+    reference: String,
+) -> Box<dyn Future<Item = Option<OsString>, Error = Error> + Send> {
+    use error::protocol::ProcessError::Failed;
+
+    if reference == "HEAD" {
+        return Box::new(future::ok(Some(OsString::from(reference))));
+    }
+
+    Box::new(
+        git::new_command_with_repo_path(repo_path)
+            .arg("rev-parse")
+            .arg("--verify")
+            .arg("--quiet")
+            .arg("--symbolic-full-name")
+            .arg(reference)
+            .output_async()
+            .map_err(|_| Error::Process(Failed))
+            .map(|output| {
+                if !output.status.success() {
+                    return None;
+                }
+
+                match first_line(&output.stdout) {
+                    b"" => None,
+                    full_ref => Some(os_string_from_bytes(full_ref)),
+                }
+            }),
+    )
+}
+
+// The contents of the reflog of a fully qualified ref. A ref without a reflog has an empty one.
+fn read_reflog_file(
+    repo_path: RepoPath,
+    full_ref: OsString,
+) -> impl Future<Item = Vec<u8>, Error = Error> {
+    use error::protocol::ProcessError::{Failed, Git};
+
+    let mut log_path = OsString::from("logs/");
+    log_path.push(full_ref);
+
+    git::new_command_with_repo_path(&repo_path)
+        .arg("rev-parse")
+        .arg("--git-path")
+        .arg(log_path)
+        .output_async()
+        .map_err(|_| Error::Process(Failed))
+        .and_then(move |output| {
+            if !output.status.success() {
+                return Err(Error::Process(Git(classify(&output))));
+            }
+
+            let reflog_path = os_string_from_bytes(first_line(&output.stdout));
+            match fs::read(Path::new(&repo_path.path).join(reflog_path)) {
+                Ok(contents) => Ok(contents),
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+                Err(_) => Err(Error::Process(Failed)),
+            }
+        })
+}
+
+/// Reads a page of the reflog of a fully qualified ref, newest entry first. A ref without a reflog
+/// has no entries.
+pub fn read_reflog(
+    repo_path: RepoPath,
+    full_ref: OsString,
+    skip: usize,
+    max_count: Option<usize>,
+) -> impl Future<Item = Vec<ReflogEntry>, Error = Error> {
+    read_reflog_file(repo_path, full_ref)
+        .and_then(move |contents| parse_reflog(&contents, skip, max_count))
+}
+
+/// Reads the reflog of a fully qualified ref newest entry first, up to and including the first
+/// entry `is_last` holds for.
+pub fn read_reflog_until<F>(
+    repo_path: RepoPath,
+    full_ref: OsString,
+    is_last: F,
+) -> impl Future<Item = Vec<ReflogEntry>, Error = Error>
+where
+    F: Fn(&ReflogEntry) -> bool,
+{
+    read_reflog_file(repo_path, full_ref)
+        .and_then(move |contents| parse_reflog_until(&contents, is_last))
+}
+
+pub fn dispatch(
+    connection_state: state::Connection,
+    reference: String,
+    skip: Option<usize>,
+    max_count: Option<usize>,
+) -> DispatchFuture {
+    use self::ErrorReason::{InvalidRef, RepoPathNotSet};
+
+    match connection_state.repo_path.clone() {
+        Some(repo_path) => Box::new(
+            resolve_ref(&repo_path, reference)
+                .and_then(move |full_ref| -> Box<dyn Future<Item = _, Error = _> + Send> {
+                    match full_ref {
+                        Some(full_ref) => Box::new(
+                            read_reflog(repo_path, full_ref, skip.unwrap_or(0), max_count)
+                                .map(Some),
+                        ),
+                        None => Box::new(future::ok(None)),
+                    }
+                })
+                .then(move |result| match result {
+                    Ok(Some(entries)) => {
+                        Ok((OutboundMessage::Success { entries }, connection_state))
+                    }
+                    Ok(None) => Ok((OutboundMessage::Error(InvalidRef), connection_state)),
+                    Err(err) => Err((err, connection_state)),
+                })
+                .and_then(|(message, connection_state)| send_message(connection_state, message)),
+        ),
+        None => Box::new(send_message(
+            connection_state,
+            OutboundMessage::Error(RepoPathNotSet),
+        )),
+    }
+}
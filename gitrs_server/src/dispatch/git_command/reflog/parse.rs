@@ -0,0 +1,186 @@
+use error::protocol::{Error, ProcessError::Parsing};
+use nom::digit1;
+use std::iter;
+use std::str;
+use util::lossless::LosslessString;
+use util::parse::sha_bytes;
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ReflogEntry {
+    pub committer: LosslessString,
+    pub email: LosslessString,
+    pub index: usize,
+    pub message: LosslessString,
+    pub new_sha: String,
+    pub old_sha: String,
+    pub timestamp: i64,
+    pub timezone: String,
+}
+
+named!(parse_reflog_line<&[u8], ReflogEntry>,
+    do_parse!(
+        old_sha: sha_bytes >>
+        char!(' ') >>
+        new_sha: sha_bytes >>
+        char!(' ') >>
+        committer: take_until!(" <") >>
+        tag!(" <") >>
+        email: take_until!("> ") >>
+        tag!("> ") >>
+        timestamp: map_res!(digit1, str::from_utf8) >>
+        char!(' ') >>
+        timezone: map_res!(take_till!(|c| c == b'\t' || c == b'\n'), str::from_utf8) >>
+        message: opt!(preceded!(char!('\t'), take_until!("\n"))) >>
+        char!('\n') >>
+        (ReflogEntry {
+            committer: LosslessString::from(committer),
+            email: LosslessString::from(email),
+            index: 0,
+            message: LosslessString::from(message.unwrap_or(b"")),
+            new_sha: String::from(new_sha),
+            old_sha: String::from(old_sha),
+            timestamp: timestamp.parse().unwrap_or(0),
+            timezone: String::from(timezone),
+        })
+    )
+);
+
+// The lines of a reflog, each with its newline, from the last one up.
+fn lines_newest_first(input: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut end = input.len();
+    iter::from_fn(move || {
+        if end == 0 {
+            return None;
+        }
+        let start = input[..end - 1]
+            .iter()
+            .rposition(|&c| c == b'\n')
+            .map_or(0, |newline| newline + 1);
+        let line = &input[start..end];
+        end = start;
+        Some(line)
+    })
+}
+
+// Reflog files are appended to, so the newest entry (`<ref>@{0}`) is the last line. Lines are only
+// parsed once their entry is asked for.
+fn entries_newest_first(input: &[u8]) -> impl Iterator<Item = Result<ReflogEntry, Error>> + '_ {
+    lines_newest_first(input)
+        .enumerate()
+        .map(|(index, line)| match parse_reflog_line(line) {
+            Ok(([], entry)) => Ok(ReflogEntry { index, ..entry }),
+            _ => Err(Error::Process(Parsing)),
+        })
+}
+
+/// Parses a page of a reflog, newest entry first. The lines before the page is reached or after it
+/// is full are never parsed.
+pub fn parse_reflog(
+    input: &[u8],
+    skip: usize,
+    max_count: Option<usize>,
+) -> Result<Vec<ReflogEntry>, Error> {
+    entries_newest_first(input)
+        .skip(skip)
+        .take(max_count.unwrap_or(usize::MAX))
+        .collect()
+}
+
+/// Parses a reflog newest entry first, up to and including the first entry `is_last` holds for.
+pub fn parse_reflog_until<F>(input: &[u8], is_last: F) -> Result<Vec<ReflogEntry>, Error>
+where
+    F: Fn(&ReflogEntry) -> bool,
+{
+    let mut entries = Vec::new();
+    for entry in entries_newest_first(input) {
+        let entry = entry?;
+        let is_last = is_last(&entry);
+        entries.push(entry);
+        if is_last {
+            break;
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_reflog, parse_reflog_until};
+
+    const ZERO: &str = "0000000000000000000000000000000000000000";
+    const A: &str = "1111111111111111111111111111111111111111";
+    const B: &str = "2222222222222222222222222222222222222222";
+    const C: &str = "3333333333333333333333333333333333333333";
+
+    fn reflog() -> Vec<u8> {
+        format!(
+            "{} {} Jane Doe <jane@example.com> 1500000000 +0200\tcommit (initial): first\n\
+             {} {} Jane Doe <jane@example.com> 1500000100 +0200\tcommit: second\n\
+             {} {} Jane Doe <jane@example.com> 1500000200 -0130\n",
+            ZERO, A, A, B, B, C
+        ).into_bytes()
+    }
+
+    #[test]
+    fn parses_newest_entry_first() {
+        let entries = parse_reflog(&reflog(), 0, None).unwrap();
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].index, 0);
+        assert_eq!(entries[0].old_sha, B);
+        assert_eq!(entries[0].new_sha, C);
+        assert_eq!(entries[0].timezone, "-0130");
+        assert_eq!(entries[0].message.as_lossy_str(), "");
+        assert_eq!(entries[2].committer.as_lossy_str(), "Jane Doe");
+        assert_eq!(entries[2].email.as_lossy_str(), "jane@example.com");
+        assert_eq!(entries[2].timestamp, 1_500_000_000);
+        assert_eq!(entries[2].message.as_lossy_str(), "commit (initial): first");
+    }
+
+    #[test]
+    fn parses_only_the_requested_page() {
+        let mut reflog = b"not a reflog line\n".to_vec();
+        reflog.extend(self::reflog());
+
+        let entries = parse_reflog(&reflog, 1, Some(2)).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].index, 1);
+        assert_eq!(entries[0].new_sha, B);
+        assert_eq!(entries[1].index, 2);
+        assert_eq!(entries[1].new_sha, A);
+
+        assert!(parse_reflog(&reflog, 3, None).is_err());
+        assert!(parse_reflog(&reflog, 4, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn stops_after_the_last_entry_asked_for() {
+        let mut reflog = b"not a reflog line\n".to_vec();
+        reflog.extend(self::reflog());
+
+        let entries = parse_reflog_until(&reflog, |entry| entry.message.as_lossy_str() != "")
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].message.as_lossy_str(), "commit: second");
+
+        assert!(parse_reflog_until(&reflog, |_| false).is_err());
+    }
+
+    #[test]
+    fn keeps_names_that_are_not_utf8() {
+        let mut reflog = format!("{} {} ", ZERO, A).into_bytes();
+        reflog.extend(b"J\xf6rg <j\xf6rg@example.com> 1500000000 +0000\tcommit: caf\xe9\n");
+
+        let entries = parse_reflog(&reflog, 0, None).unwrap();
+        assert_eq!(entries[0].committer.as_lossy_str(), "J\u{fffd}rg");
+        assert_eq!(entries[0].message.as_lossy_str(), "commit: caf\u{fffd}");
+    }
+
+    #[test]
+    fn rejects_a_truncated_line() {
+        let mut reflog = reflog();
+        reflog.pop();
+        assert!(parse_reflog(&reflog, 0, None).is_err());
+        assert!(parse_reflog(b"", 0, None).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,57 @@
+use super::parse::{parse_remotes, Remote};
+use super::ErrorReason;
+use futures::{future, Future};
+use state;
+use tokio_process::CommandExt;
+use types::DispatchFuture;
+use util::git;
+use util::git_error::classify;
+use util::transport::send_message;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "RemoteListOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Success { remotes: Vec<Remote> },
+    Error(ErrorReason),
+}
+
+pub fn dispatch(connection_state: state::Connection) -> DispatchFuture {
+    use self::ErrorReason::RepoPathNotSet;
+    use error::protocol::{Error, ProcessError::{Failed, Git}};
+
+    match connection_state.repo_path.clone() {
+        Some(repo_path) => Box::new(
+            git::new_command_with_repo_path(&repo_path)
+                .arg("config")
+                .arg("-z")
+                .arg("--get-regexp")
+                .arg(r"^remote\.")
+                .output_async()
+                .then(|result| match result {
+                    // `git config --get-regexp` exits with 1 when there are no remotes at all.
+                    Ok(output) => match output.status.code() {
+                        Some(0) | Some(1) => future::ok((output.stdout, connection_state)),
+                        _ => future::err((
+                            Error::Process(Git(classify(&output))),
+                            connection_state,
+                        )),
+                    },
+                    Err(_) => future::err((Error::Process(Failed), connection_state)),
+                })
+                .and_then(|(result, connection_state)| -> DispatchFuture {
+                    match parse_remotes(&result) {
+                        Ok(remotes) => Box::new(send_message(
+                            connection_state,
+                            OutboundMessage::Success { remotes },
+                        )),
+                        Err(err) => Box::new(future::err((err, connection_state))),
+                    }
+                }),
+        ),
+        None => Box::new(send_message(
+            connection_state,
+            OutboundMessage::Error(RepoPathNotSet),
+        )),
+    }
+}
@@ -0,0 +1,115 @@
+mod add;
+mod list;
+mod parse;
+mod prune;
+mod remove;
+mod rename;
+mod set_url;
+mod validate;
+
+use message::protocol::git_command::remote;
+use schema::CommandSchema;
+use schemars::gen::SchemaGenerator;
+use state;
+use std::process::Output;
+use types::DispatchFuture;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "RemoteErrorReason")]
+#[serde(tag = "reason")]
+pub enum ErrorReason {
+    EmptyUrl,
+    InvalidRemoteName,
+    MalformedUrl,
+    RemoteAlreadyExists,
+    RemoteDoesNotExist,
+    RepoPathNotSet,
+    UnsupportedUrlScheme,
+}
+
+// Exit codes documented in git-remote(1).
+const EXIT_CODE_NO_SUCH_REMOTE: i32 = 2;
+const EXIT_CODE_REMOTE_EXISTS: i32 = 3;
+
+/// Tells a missing or already existing remote apart from other failures of `git remote`. Git only
+/// exits with a code of its own for them since 2.30. Older versions, like the one bundled with
+/// releases, exit with 128 and can only be told apart by their message.
+pub fn classify_failure(output: &Output) -> Option<ErrorReason> {
+    classify_message(output.status.code(), &output.stderr)
+}
+
+fn classify_message(exit_code: Option<i32>, stderr: &[u8]) -> Option<ErrorReason> {
+    let stderr = String::from_utf8_lossy(stderr);
+    let is_remote_exists = |line: &str| {
+        let message = line.trim_start_matches("fatal: ").trim_start_matches("error: ");
+        message.starts_with("remote ") && message.ends_with(" already exists.")
+    };
+
+    match exit_code {
+        Some(EXIT_CODE_NO_SUCH_REMOTE) => Some(ErrorReason::RemoteDoesNotExist),
+        Some(EXIT_CODE_REMOTE_EXISTS) => Some(ErrorReason::RemoteAlreadyExists),
+        _ if stderr.contains("No such remote") => Some(ErrorReason::RemoteDoesNotExist),
+        _ if stderr.lines().any(is_remote_exists) => Some(ErrorReason::RemoteAlreadyExists),
+        _ => None,
+    }
+}
+
+pub fn dispatch(connection_state: state::Connection, message: remote::Inbound) -> DispatchFuture {
+    use self::remote::Inbound;
+
+    match message {
+        Inbound::Add { name, url } => add::dispatch(connection_state, name, url),
+        Inbound::List => list::dispatch(connection_state),
+        Inbound::Prune { name, dry_run } => prune::dispatch(connection_state, name, dry_run),
+        Inbound::Remove { name } => remove::dispatch(connection_state, name),
+        Inbound::Rename { old_name, new_name } => {
+            rename::dispatch(connection_state, old_name, new_name)
+        }
+        Inbound::SetUrl { name, url, push } => {
+            set_url::dispatch(connection_state, name, url, push)
+        }
+    }
+}
+
+pub fn command_schema(generator: &mut SchemaGenerator) -> CommandSchema {
+    CommandSchema::group(
+        "Remote",
+        vec![
+            CommandSchema::new::<add::OutboundMessage>("Add", generator),
+            CommandSchema::new::<list::OutboundMessage>("List", generator),
+            CommandSchema::new::<prune::OutboundMessage>("Prune", generator),
+            CommandSchema::new::<remove::OutboundMessage>("Remove", generator),
+            CommandSchema::new::<rename::OutboundMessage>("Rename", generator),
+            CommandSchema::new::<set_url::OutboundMessage>("SetUrl", generator),
+        ],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::classify_message;
+    use super::ErrorReason::{RemoteAlreadyExists, RemoteDoesNotExist};
+
+    macro_rules! assert_reason {
+        ($exit_code:expr, $stderr:expr, $reason:pat) => {
+            match classify_message(Some($exit_code), $stderr) {
+                $reason => {}
+                other => panic!("{:?} was classified as {:?}", $stderr, other),
+            }
+        };
+    }
+
+    #[test]
+    fn classifies_remote_failures_of_old_and_new_git() {
+        // git 2.30 and later
+        assert_reason!(2, b"error: No such remote: 'origin'\n", Some(RemoteDoesNotExist));
+        assert_reason!(3, b"error: remote origin already exists.\n", Some(RemoteAlreadyExists));
+
+        // git 2.17, as bundled with releases
+        assert_reason!(128, b"fatal: No such remote: origin\n", Some(RemoteDoesNotExist));
+        assert_reason!(128, b"fatal: No such remote 'origin'\n", Some(RemoteDoesNotExist));
+        assert_reason!(128, b"fatal: remote origin already exists.\n", Some(RemoteAlreadyExists));
+
+        assert_reason!(128, b"fatal: not a git repository\n", None);
+    }
+}
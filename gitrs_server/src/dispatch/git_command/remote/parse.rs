@@ -0,0 +1,211 @@
+use error::protocol::{Error, ProcessError::Parsing};
+use util::lossless::LosslessString;
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Refspec {
+    destination: Option<LosslessString>,
+    force: bool,
+    source: LosslessString,
+}
+
+impl Refspec {
+    fn parse(refspec: &[u8]) -> Refspec {
+        let (force, refspec) = if refspec.starts_with(b"+") {
+            (true, &refspec[1..])
+        } else {
+            (false, refspec)
+        };
+
+        let mut sides = refspec.splitn(2, |&c| c == b':');
+        Refspec {
+            source: LosslessString::from(sides.next().unwrap_or(b"")),
+            destination: sides.next().map(LosslessString::from),
+            force,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Remote {
+    fetch_refspecs: Vec<Refspec>,
+    fetch_url: Option<LosslessString>,
+    name: LosslessString,
+    push_refspecs: Vec<Refspec>,
+    push_urls: Vec<LosslessString>,
+}
+
+#[derive(Default)]
+struct RemoteConfig<'a> {
+    fetch: Vec<&'a [u8]>,
+    push: Vec<&'a [u8]>,
+    pushurl: Vec<&'a [u8]>,
+    url: Vec<&'a [u8]>,
+}
+
+named!(parse_config_entry<&[u8], (&[u8], Option<&[u8]>)>,
+    do_parse!(
+        key: take_till!(|c| c == b'\n' || c == b'\0') >>
+        value: opt!(preceded!(char!('\n'), take_until!("\0"))) >>
+        char!('\0') >>
+        ((key, value))
+    )
+);
+
+named!(parse_config_entries<&[u8], Vec<(&[u8], Option<&[u8]>)>>,
+    many0!(complete!(parse_config_entry))
+);
+
+fn build_remotes(entries: Vec<(&[u8], Option<&[u8]>)>) -> Vec<Remote> {
+    let mut configs: Vec<(&[u8], RemoteConfig)> = Vec::new();
+
+    for (key, value) in entries {
+        let key = match key.iter().position(|&c| c == b'.').map(|dot| &key[dot + 1..]) {
+            Some(key) => key,
+            None => continue,
+        };
+        let (name, variable) = match key.iter().rposition(|&c| c == b'.') {
+            Some(dot) => (&key[..dot], &key[dot + 1..]),
+            None => continue,
+        };
+        let value = value.unwrap_or(b"");
+
+        let index = match configs.iter().position(|&(existing, _)| existing == name) {
+            Some(index) => index,
+            None => {
+                configs.push((name, RemoteConfig::default()));
+                configs.len() - 1
+            }
+        };
+        let config = &mut configs[index].1;
+
+        match variable {
+            b"fetch" => config.fetch.push(value),
+            b"push" => config.push.push(value),
+            b"pushurl" => config.pushurl.push(value),
+            b"url" => config.url.push(value),
+            _ => {}
+        }
+    }
+
+    configs
+        .into_iter()
+        .map(|(name, config)| {
+            // Git pushes to every `url` unless `pushurl` overrides them.
+            let push_urls = if config.pushurl.is_empty() {
+                &config.url
+            } else {
+                &config.pushurl
+            };
+
+            Remote {
+                fetch_refspecs: config
+                    .fetch
+                    .iter()
+                    .map(|refspec| Refspec::parse(refspec))
+                    .collect(),
+                fetch_url: config.url.first().map(|&url| LosslessString::from(url)),
+                name: LosslessString::from(name),
+                push_refspecs: config
+                    .push
+                    .iter()
+                    .map(|refspec| Refspec::parse(refspec))
+                    .collect(),
+                push_urls: push_urls.iter().map(|&url| LosslessString::from(url)).collect(),
+            }
+        })
+        .collect()
+}
+
+pub fn parse_remotes(input: &[u8]) -> Result<Vec<Remote>, Error> {
+    match parse_config_entries(input) {
+        Ok(([], entries)) => Ok(build_remotes(entries)),
+        _ => Err(Error::Process(Parsing)),
+    }
+}
+
+pub fn parse_pruned_refs(input: &str) -> Vec<String> {
+    input
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim_start();
+            if !line.starts_with("* [") {
+                return None;
+            }
+            line.find("] ").map(|end| String::from(&line[end + 2..]))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_pruned_refs, parse_remotes};
+    use util::lossless::LosslessString;
+
+    fn utf8(text: &str) -> LosslessString {
+        LosslessString::Utf8(String::from(text))
+    }
+
+    #[test]
+    fn groups_variables_by_remote() {
+        let input: &[u8] = b"remote.origin.url\nhttps://example.com/repo.git\0\
+            remote.origin.fetch\n+refs/heads/*:refs/remotes/origin/*\0\
+            remote.my.dotted.name.url\n/srv/repo\0\
+            remote.origin.pushurl\nssh://example.com/repo.git\0\
+            remote.origin.push\nrefs/heads/main\0\
+            remote.origin.mirror\0";
+
+        let remotes = parse_remotes(input).unwrap();
+        assert_eq!(remotes.len(), 2);
+
+        let origin = &remotes[0];
+        assert_eq!(origin.name, utf8("origin"));
+        assert_eq!(origin.fetch_url, Some(utf8("https://example.com/repo.git")));
+        assert_eq!(origin.push_urls, vec![utf8("ssh://example.com/repo.git")]);
+        assert!(origin.fetch_refspecs[0].force);
+        assert_eq!(origin.fetch_refspecs[0].source, utf8("refs/heads/*"));
+        assert_eq!(
+            origin.fetch_refspecs[0].destination,
+            Some(utf8("refs/remotes/origin/*"))
+        );
+        assert!(!origin.push_refspecs[0].force);
+        assert_eq!(origin.push_refspecs[0].destination, None);
+
+        // Names may contain dots, and push to their `url` without a `pushurl`.
+        assert_eq!(remotes[1].name, utf8("my.dotted.name"));
+        assert_eq!(remotes[1].push_urls, vec![utf8("/srv/repo")]);
+    }
+
+    #[test]
+    fn keeps_values_with_newlines_and_invalid_utf8() {
+        let input: &[u8] = b"remote.odd.url\n/srv/line\nbreak\0remote.caf\xe9.url\n/srv/caf\xe9\0";
+
+        let remotes = parse_remotes(input).unwrap();
+        assert_eq!(remotes.len(), 2);
+        assert_eq!(remotes[0].fetch_url, Some(utf8("/srv/line\nbreak")));
+        assert!(matches!(
+            remotes[1].name,
+            LosslessString::Bytes { ref bytes, .. } if &bytes[..] == b"caf\xe9"
+        ));
+        assert!(matches!(
+            remotes[1].fetch_url,
+            Some(LosslessString::Bytes { ref bytes, .. }) if &bytes[..] == b"/srv/caf\xe9"
+        ));
+    }
+
+    #[test]
+    fn rejects_a_truncated_entry() {
+        assert!(parse_remotes(b"remote.origin.url\nhttps://example.com").is_err());
+    }
+
+    #[test]
+    fn lists_pruned_refs() {
+        let input = "Pruning origin\n\
+                     URL: https://example.com/repo.git\n \
+                     * [pruned] origin/gone\n \
+                     * [would prune] origin/also gone\n";
+        assert_eq!(
+            parse_pruned_refs(input),
+            vec![String::from("origin/gone"), String::from("origin/also gone")]
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use super::parse::parse_pruned_refs;
+use super::validate::verify_remote_name;
+use super::{classify_failure, ErrorReason};
+use futures::{future, Future};
+use state;
+use tokio_process::CommandExt;
+use types::DispatchFuture;
+use util::git;
+use util::git_error::classify;
+use util::transport::send_message;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "RemotePruneOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Success { pruned: Vec<String> },
+    Error(ErrorReason),
+}
+
+pub fn dispatch(connection_state: state::Connection, name: String, dry_run: bool) -> DispatchFuture {
+    use self::ErrorReason::{RemoteDoesNotExist, RepoPathNotSet};
+    use error::protocol::{Error, ProcessError::{Failed, Git}};
+
+    if let Err(reason) = verify_remote_name(&name) {
+        return Box::new(send_message(connection_state, OutboundMessage::Error(reason)));
+    }
+
+    match connection_state.repo_path.clone() {
+        // `git remote prune` reports an unknown remote as an unreachable repository, so we check
+        // that the remote exists before contacting it.
+        Some(repo_path) => Box::new(
+            git::new_command_with_repo_path(&repo_path)
+                .arg("remote")
+                .arg("get-url")
+                .arg("--")
+                .arg(&name)
+                .output_async()
+                .then(|result| match result {
+                    Ok(output) => if output.status.success() {
+                        future::ok((true, connection_state))
+                    } else if let Some(RemoteDoesNotExist) = classify_failure(&output) {
+                        future::ok((false, connection_state))
+                    } else {
+                        let err = Error::Process(Git(classify(&output)));
+                        future::err((err, connection_state))
+                    },
+                    Err(_) => future::err((Error::Process(Failed), connection_state)),
+                })
+                .and_then(move |(remote_exists, connection_state)| -> DispatchFuture {
+                    if !remote_exists {
+                        return Box::new(send_message(
+                            connection_state,
+                            OutboundMessage::Error(RemoteDoesNotExist),
+                        ));
+                    }
+
+                    let mut command = git::new_command_with_repo_path(&repo_path);
+                    command.arg("remote").arg("prune");
+                    if dry_run {
+                        command.arg("--dry-run");
+                    }
+
+                    Box::new(
+                        command
+                            .arg("--")
+                            .arg(name)
+                            .output_async()
+                            .then(|result| match result {
+                                Ok(output) => if output.status.success() {
+                                    let output = String::from_utf8_lossy(&output.stdout);
+                                    future::ok((parse_pruned_refs(&output), connection_state))
+                                } else {
+                                    let err = Error::Process(Git(classify(&output)));
+                                    future::err((err, connection_state))
+                                },
+                                Err(_) => future::err((Error::Process(Failed), connection_state)),
+                            })
+                            .and_then(|(pruned, connection_state)| {
+                                send_message(connection_state, OutboundMessage::Success { pruned })
+                            }),
+                    )
+                }),
+        ),
+        None => Box::new(send_message(
+            connection_state,
+            OutboundMessage::Error(RepoPathNotSet),
+        )),
+    }
+}
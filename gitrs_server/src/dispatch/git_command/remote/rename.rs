@@ -0,0 +1,59 @@
+use super::validate::verify_remote_name;
+use super::{classify_failure, ErrorReason};
+use futures::{future, Future};
+use state;
+use tokio_process::CommandExt;
+use types::DispatchFuture;
+use util::git;
+use util::git_error::classify;
+use util::transport::send_message;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "RemoteRenameOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Success,
+    Error(ErrorReason),
+}
+
+pub fn dispatch(
+    connection_state: state::Connection,
+    old_name: String,
+    new_name: String,
+) -> DispatchFuture {
+    use self::ErrorReason::RepoPathNotSet;
+    use error::protocol::{Error, ProcessError::{Failed, Git}};
+
+    if let Err(reason) = verify_remote_name(&old_name).and_then(|_| verify_remote_name(&new_name))
+    {
+        return Box::new(send_message(connection_state, OutboundMessage::Error(reason)));
+    }
+
+    match connection_state.repo_path.clone() {
+        Some(repo_path) => Box::new(
+            git::new_command_with_repo_path(&repo_path)
+                .arg("remote")
+                .arg("rename")
+                .arg("--")
+                .arg(old_name)
+                .arg(new_name)
+                .output_async()
+                .then(|result| match result {
+                    Ok(output) => if output.status.success() {
+                        future::ok((OutboundMessage::Success, connection_state))
+                    } else if let Some(reason) = classify_failure(&output) {
+                        future::ok((OutboundMessage::Error(reason), connection_state))
+                    } else {
+                        let err = Error::Process(Git(classify(&output)));
+                        future::err((err, connection_state))
+                    },
+                    Err(_) => future::err((Error::Process(Failed), connection_state)),
+                })
+                .and_then(|(message, connection_state)| send_message(connection_state, message)),
+        ),
+        None => Box::new(send_message(
+            connection_state,
+            OutboundMessage::Error(RepoPathNotSet),
+        )),
+    }
+}
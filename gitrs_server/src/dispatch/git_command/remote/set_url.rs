@@ -0,0 +1,67 @@
+use super::validate::{verify_remote_name, verify_url};
+use super::{classify_failure, ErrorReason};
+use futures::{future, Future};
+use state;
+use tokio_process::CommandExt;
+use types::DispatchFuture;
+use util::git;
+use util::git_error::classify;
+use util::transport::send_message;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "RemoteSetUrlOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Success,
+    Error(ErrorReason),
+}
+
+pub fn dispatch(
+    connection_state: state::Connection,
+    name: String,
+    url: String,
+    push: bool,
+) -> DispatchFuture {
+    use self::ErrorReason::RepoPathNotSet;
+    use error::protocol::{Error, ProcessError::{Failed, Git}};
+
+    if let Err(reason) = verify_remote_name(&name).and_then(|_| verify_url(&url)) {
+        return Box::new(send_message(connection_state, OutboundMessage::Error(reason)));
+    }
+
+    match connection_state.repo_path.clone() {
+        Some(repo_path) => {
+            let mut command = git::new_command_with_repo_path(&repo_path);
+            command.arg("remote").arg("set-url");
+            if push {
+                command.arg("--push");
+            }
+
+            Box::new(
+                command
+                    .arg("--")
+                    .arg(name)
+                    .arg(url)
+                    .output_async()
+                    .then(|result| match result {
+                        Ok(output) => if output.status.success() {
+                            future::ok((OutboundMessage::Success, connection_state))
+                        } else if let Some(reason) = classify_failure(&output) {
+                            future::ok((OutboundMessage::Error(reason), connection_state))
+                        } else {
+                            let err = Error::Process(Git(classify(&output)));
+                            future::err((err, connection_state))
+                        },
+                        Err(_) => future::err((Error::Process(Failed), connection_state)),
+                    })
+                    .and_then(|(message, connection_state)| {
+                        send_message(connection_state, message)
+                    }),
+            )
+        }
+        None => Box::new(send_message(
+            connection_state,
+            OutboundMessage::Error(RepoPathNotSet),
+        )),
+    }
+}
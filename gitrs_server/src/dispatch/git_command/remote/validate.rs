@@ -0,0 +1,93 @@
+use super::ErrorReason;
+
+static SUPPORTED_SCHEMES: &[&str] = &["file", "ftp", "ftps", "git", "http", "https", "ssh"];
+
+// Mirrors the rules git applies to `refs/remotes/<name>` via `check-ref-format`.
+pub fn verify_remote_name(name: &str) -> Result<(), ErrorReason> {
+    use self::ErrorReason::InvalidRemoteName;
+
+    let is_valid = !name.is_empty()
+        && !name.starts_with('-')
+        && !name.starts_with('.')
+        && !name.starts_with('/')
+        && !name.ends_with('/')
+        && !name.ends_with('.')
+        && !name.ends_with(".lock")
+        && !name.contains("..")
+        && !name.contains("//")
+        && !name.contains("@{")
+        && !name.contains("/.")
+        && !name.chars().any(|c| {
+            c.is_whitespace() || c.is_control() || "~^:?*[\\".contains(c)
+        });
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(InvalidRemoteName)
+    }
+}
+
+fn verify_scp_like_url(url: &str) -> Option<Result<(), ErrorReason>> {
+    use self::ErrorReason::MalformedUrl;
+
+    let colon = url.find(':')?;
+    if url[..colon].contains('/') {
+        return None;
+    }
+
+    // A single drive letter (e.g. `C:\repo`) is a local Windows path, not a host.
+    if colon == 1 && url.as_bytes()[0].is_ascii_alphabetic() {
+        return None;
+    }
+
+    let host = match url[..colon].rfind('@') {
+        Some(at) => &url[at + 1..colon],
+        None => &url[..colon],
+    };
+    let path = &url[colon + 1..];
+
+    Some(if host.is_empty() || path.is_empty() {
+        Err(MalformedUrl)
+    } else {
+        Ok(())
+    })
+}
+
+pub fn verify_url(url: &str) -> Result<(), ErrorReason> {
+    use self::ErrorReason::{EmptyUrl, MalformedUrl, UnsupportedUrlScheme};
+
+    if url.is_empty() {
+        return Err(EmptyUrl);
+    }
+
+    if url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(MalformedUrl);
+    }
+
+    // `<transport>::<address>` hands the URL to a remote helper, which validates it itself.
+    if let Some(separator) = url.find("::") {
+        return if separator > 0 && url[..separator].chars().all(|c| c.is_ascii_alphanumeric()) {
+            Ok(())
+        } else {
+            Err(MalformedUrl)
+        };
+    }
+
+    if let Some(separator) = url.find("://") {
+        let scheme = url[..separator].to_lowercase();
+        let rest = &url[separator + 3..];
+
+        if !SUPPORTED_SCHEMES.contains(&scheme.as_str()) {
+            return Err(UnsupportedUrlScheme);
+        }
+
+        return if rest.is_empty() || (scheme != "file" && rest.starts_with('/')) {
+            Err(MalformedUrl)
+        } else {
+            Ok(())
+        };
+    }
+
+    verify_scp_like_url(url).unwrap_or(Ok(()))
+}
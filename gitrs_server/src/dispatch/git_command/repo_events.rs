@@ -0,0 +1,165 @@
+use error::protocol::{Error, ProcessError::{Failed, Git, Parsing}};
+use futures::{future, Future};
+use message::channel;
+use message::protocol::{HeadState, RefUpdate};
+use state;
+use std::collections::BTreeMap;
+use std::process::Command;
+use tokio_process::CommandExt;
+use types::DispatchFuture;
+use util::git::{self, RepoPath};
+use util::git_error::{classify, GitError};
+use util::lossless::LosslessString;
+
+struct RepoSnapshot {
+    head: HeadState,
+    sha_by_ref: BTreeMap<Vec<u8>, String>,
+}
+
+fn read_stdout(
+    mut command: Command,
+) -> impl Future<Item = Result<Vec<u8>, GitError>, Error = Error> {
+    command
+        .output_async()
+        .map_err(|_| Error::Process(Failed))
+        .map(|output| {
+            if output.status.success() {
+                Ok(output.stdout)
+            } else {
+                Err(classify(&output))
+            }
+        })
+}
+
+fn read_snapshot(repo_path: &RepoPath) -> impl Future<Item = RepoSnapshot, Error = Error> {
+    let mut command_refs = git::new_command_with_repo_path(repo_path);
+    command_refs
+        .arg("for-each-ref")
+        .arg("--format=%(objectname)%00%(HEAD)%00%(refname)");
+
+    // `for-each-ref` only marks HEAD when it points at a branch.
+    let mut command_head = git::new_command_with_repo_path(repo_path);
+    command_head
+        .arg("rev-parse")
+        .arg("--verify")
+        .arg("--quiet")
+        .arg("HEAD");
+
+    read_stdout(command_refs)
+        .join(read_stdout(command_head))
+        .and_then(|(refs, head_sha)| {
+            let refs = refs.map_err(|err| Error::Process(Git(err)))?;
+            let mut branch = None;
+            let mut sha_by_ref = BTreeMap::new();
+
+            // Ref names cannot contain newlines, but need not be UTF-8.
+            for line in refs.split(|&c| c == b'\n').filter(|line| !line.is_empty()) {
+                let mut fields = line.split(|&c| c == b'\0');
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some(sha), Some(head_marker), Some(name)) => {
+                        if head_marker == b"*" {
+                            branch = Some(LosslessString::from(name));
+                        }
+                        let sha = String::from_utf8_lossy(sha).into_owned();
+                        sha_by_ref.insert(name.to_vec(), sha);
+                    }
+                    _ => return Err(Error::Process(Parsing)),
+                }
+            }
+
+            Ok(RepoSnapshot {
+                head: HeadState {
+                    branch,
+                    sha: head_sha.ok().map(|sha| String::from(String::from_utf8_lossy(&sha).trim())),
+                },
+                sha_by_ref,
+            })
+        })
+}
+
+fn diff_refs(before: &RepoSnapshot, after: &RepoSnapshot) -> Vec<RefUpdate> {
+    let mut names: Vec<&Vec<u8>> = before
+        .sha_by_ref
+        .keys()
+        .chain(after.sha_by_ref.keys())
+        .collect();
+    names.sort();
+    names.dedup();
+
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let old_sha = before.sha_by_ref.get(name);
+            let new_sha = after.sha_by_ref.get(name);
+            if old_sha == new_sha {
+                None
+            } else {
+                Some(RefUpdate {
+                    name: LosslessString::from(name.as_slice()),
+                    new_sha: new_sha.cloned(),
+                    old_sha: old_sha.cloned(),
+                })
+            }
+        })
+        .collect()
+}
+
+fn broadcast_differences(
+    connection_state: &state::Connection,
+    repo_path: &str,
+    before: RepoSnapshot,
+    after: RepoSnapshot,
+) {
+    let refs = diff_refs(&before, &after);
+    if !refs.is_empty() {
+        connection_state.broadcast(
+            repo_path,
+            channel::Message::RefsUpdated {
+                refs,
+                repo_path: String::from(repo_path),
+            },
+        );
+    }
+
+    if before.head != after.head {
+        connection_state.broadcast(
+            repo_path,
+            channel::Message::HeadMoved {
+                from: before.head,
+                repo_path: String::from(repo_path),
+                to: after.head,
+            },
+        );
+    }
+}
+
+/// Runs a command that may write to the repository and tells the other connections that have it
+/// open which refs it updated and where it moved HEAD. Failing to compare the repository before
+/// and after only costs them the notification, never the command itself.
+pub fn broadcast_changes<F>(
+    connection_state: state::Connection,
+    repo_path: RepoPath,
+    dispatch: F,
+) -> DispatchFuture
+where
+    F: FnOnce(state::Connection) -> DispatchFuture + Send + 'static,
+{
+    Box::new(
+        read_snapshot(&repo_path)
+            .then(move |before| {
+                dispatch(connection_state).map(|connection_state| (before.ok(), connection_state))
+            })
+            .and_then(move |(before, connection_state)| -> DispatchFuture {
+                match before {
+                    Some(before) => Box::new(read_snapshot(&repo_path).then(move |after| {
+                        if let Ok(after) = after {
+                            let repo_path = &repo_path.path;
+                            broadcast_differences(&connection_state, repo_path, before, after);
+                        }
+                        Ok(connection_state)
+                    })),
+                    None => Box::new(future::ok(connection_state)),
+                }
+            }),
+    )
+}
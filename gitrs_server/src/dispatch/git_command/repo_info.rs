@@ -0,0 +1,372 @@
+use super::log::read_log_output_encoding;
+use super::status::BranchHeader;
+use error::protocol::{Error, ProcessError::{Failed, Git, Parsing}};
+use futures::{future, Future};
+use state;
+use std::path::Path;
+use std::process::Command;
+use tokio_process::CommandExt;
+use types::DispatchFuture;
+use util::git::{self, RepoPath};
+use util::git_error::{classify, GitError};
+use util::lossless::LosslessString;
+use util::transport::send_message;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "RepoInfoErrorReason")]
+#[serde(tag = "reason")]
+pub enum ErrorReason {
+    RepoPathNotSet,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(tag = "type")]
+pub enum Head {
+    Branch { name: String, sha: String },
+    Detached { sha: String },
+    Unborn { name: String },
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub enum ObjectFormat {
+    Sha1,
+    Sha256,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub enum Operation {
+    Bisect,
+    CherryPick,
+    Merge,
+    Rebase,
+    Revert,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Upstream {
+    ahead: Option<u32>,
+    behind: Option<u32>,
+    name: String,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "RepoInfoOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Success {
+        head: Head,
+        head_summary: Option<LosslessString>,
+        is_shallow: bool,
+        object_format: ObjectFormat,
+        operations_in_progress: Vec<Operation>,
+        upstream: Option<Upstream>,
+    },
+    Error(ErrorReason),
+}
+
+struct Location {
+    git_dir: String,
+    is_shallow: bool,
+    object_format: ObjectFormat,
+}
+
+// Gives the error git exited with as the inner `Err`, which for most of the commands used here
+// only means that the thing asked about does not exist.
+fn read_stdout(
+    mut command: Command,
+) -> impl Future<Item = Result<String, GitError>, Error = Error> {
+    command
+        .output_async()
+        .map_err(|_| Error::Process(Failed))
+        .map(|output| {
+            if output.status.success() {
+                Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+            } else {
+                Err(classify(&output))
+            }
+        })
+}
+
+fn read_location(repo_path: &RepoPath) -> impl Future<Item = Location, Error = Error> {
+    let mut command = git::new_command_with_repo_path(repo_path);
+    command
+        .arg("rev-parse")
+        .arg("--is-shallow-repository")
+        .arg("--absolute-git-dir");
+
+    read_stdout(command)
+        .and_then(|stdout| {
+            let stdout = stdout.map_err(|err| Error::Process(Git(err)))?;
+            let mut lines = stdout.lines();
+            match (lines.next(), lines.next()) {
+                (Some(is_shallow), Some(git_dir)) => {
+                    Ok((String::from(git_dir), is_shallow == "true"))
+                }
+                _ => Err(Error::Process(Parsing)),
+            }
+        })
+        .join(read_object_format(repo_path))
+        .map(|((git_dir, is_shallow), object_format)| Location {
+            git_dir,
+            is_shallow,
+            object_format,
+        })
+}
+
+// `--show-object-format` arrived with SHA-256 support in git 2.28. Older versions either echo the
+// unknown option back or fail, and only ever use SHA-1.
+fn read_object_format(repo_path: &RepoPath) -> impl Future<Item = ObjectFormat, Error = Error> {
+    let mut command = git::new_command_with_repo_path(repo_path);
+    command.arg("rev-parse").arg("--show-object-format");
+
+    read_stdout(command).map(|stdout| match stdout.as_ref().map(|stdout| stdout.trim()).ok() {
+        Some("sha256") => ObjectFormat::Sha256,
+        _ => ObjectFormat::Sha1,
+    })
+}
+
+// Reads HEAD and its upstream without `git status`, which would refresh the index and look at
+// every file in the working tree just to report the branch.
+fn read_branch_header(repo_path: &RepoPath) -> impl Future<Item = BranchHeader, Error = Error> {
+    let mut command_branch = git::new_command_with_repo_path(repo_path);
+    command_branch
+        .arg("symbolic-ref")
+        .arg("--quiet")
+        .arg("HEAD");
+
+    let mut command_sha = git::new_command_with_repo_path(repo_path);
+    command_sha
+        .arg("rev-parse")
+        .arg("--verify")
+        .arg("--quiet")
+        .arg("HEAD");
+
+    let repo_path = repo_path.clone();
+    read_stdout(command_branch)
+        .join(read_stdout(command_sha))
+        .and_then(move |(branch, sha)| {
+            // The branch is looked up by its full name. The short one git gives is disambiguated
+            // against other refs, as in `heads/name`, and cannot be turned back into it.
+            let branch = branch.ok().map(|branch| String::from(branch.trim()));
+            let header = BranchHeader {
+                head: branch.as_ref().map(|branch| {
+                    String::from(branch.strip_prefix("refs/heads/").unwrap_or(branch))
+                }),
+                oid: sha.ok().map(|sha| String::from(sha.trim())),
+                ..BranchHeader::default()
+            };
+            read_upstream(&repo_path, branch, header)
+        })
+}
+
+// Like `git status`, leaves out the counts when the upstream is gone or HEAD is unborn.
+fn read_upstream(
+    repo_path: &RepoPath,
+    branch: Option<String>,
+    header: BranchHeader,
+) -> Box<dyn Future<Item = BranchHeader, Error = Error> + Send> {
+    let branch = match branch {
+        Some(branch) => branch,
+        None => return Box::new(future::ok(header)),
+    };
+
+    let mut command_upstream = git::new_command_with_repo_path(repo_path);
+    command_upstream
+        .arg("for-each-ref")
+        .arg("--format=%(upstream)%00%(upstream:short)")
+        .arg(branch);
+
+    let repo_path = repo_path.clone();
+    Box::new(read_stdout(command_upstream).and_then(
+        move |stdout| -> Box<dyn Future<Item = BranchHeader, Error = Error> + Send> {
+            let stdout = stdout.unwrap_or_default();
+            let (full_name, name) = match stdout.trim_end().split_once('\0') {
+                Some((full_name, name)) if !full_name.is_empty() => {
+                    (String::from(full_name), String::from(name))
+                }
+                _ => return Box::new(future::ok(header)),
+            };
+
+            let mut command_count = git::new_command_with_repo_path(&repo_path);
+            command_count
+                .arg("rev-list")
+                .arg("--count")
+                .arg("--left-right")
+                .arg(format!("HEAD...{}", full_name))
+                .arg("--");
+
+            Box::new(read_stdout(command_count).map(move |stdout| {
+                let counts = stdout.as_ref().ok().and_then(|stdout| {
+                    let mut counts = stdout.split_whitespace().map(str::parse);
+                    match (counts.next(), counts.next()) {
+                        (Some(Ok(ahead)), Some(Ok(behind))) => Some((ahead, behind)),
+                        _ => None,
+                    }
+                });
+                BranchHeader {
+                    ahead: counts.map(|(ahead, _)| ahead),
+                    behind: counts.map(|(_, behind)| behind),
+                    upstream: Some(name),
+                    ..header
+                }
+            }))
+        },
+    ))
+}
+
+fn read_head_summary(
+    repo_path: &RepoPath,
+    header: &BranchHeader,
+) -> Box<dyn Future<Item = Option<LosslessString>, Error = Error> + Send> {
+    match header.oid {
+        Some(ref sha) => {
+            let mut command = git::new_command_with_repo_path(repo_path);
+            command.arg("log").arg("-1").arg("--format=%s").arg(sha);
+            Box::new(
+                read_log_output_encoding(repo_path)
+                    .join(command.output_async().map_err(|_| Error::Process(Failed)))
+                    .map(|(encoding, output)| {
+                        if !output.status.success() {
+                            return None;
+                        }
+
+                        let summary = match output.stdout.split_last() {
+                            Some((&b'\n', summary)) => summary,
+                            _ => &output.stdout[..],
+                        };
+                        Some(LosslessString::decode(summary, encoding))
+                    }),
+            )
+        }
+        None => Box::new(future::ok(None)),
+    }
+}
+
+// These are the same files git itself checks to describe an operation in `git status`. The bisect
+// handler asks `git bisect log`, which succeeds exactly when `BISECT_LOG` exists.
+fn operations_in_progress(git_dir: &str) -> Vec<Operation> {
+    let git_dir = Path::new(git_dir);
+    let mut operations = Vec::new();
+
+    if git_dir.join("BISECT_LOG").is_file() {
+        operations.push(Operation::Bisect);
+    }
+    if git_dir.join("CHERRY_PICK_HEAD").is_file() {
+        operations.push(Operation::CherryPick);
+    }
+    if git_dir.join("MERGE_HEAD").is_file() {
+        operations.push(Operation::Merge);
+    }
+    if git_dir.join("rebase-merge").is_dir() || git_dir.join("rebase-apply").is_dir() {
+        operations.push(Operation::Rebase);
+    }
+    if git_dir.join("REVERT_HEAD").is_file() {
+        operations.push(Operation::Revert);
+    }
+
+    operations
+}
+
+fn to_head(header: &BranchHeader) -> Result<Head, Error> {
+    match (header.head.clone(), header.oid.clone()) {
+        (Some(name), Some(sha)) => Ok(Head::Branch { name, sha }),
+        (Some(name), None) => Ok(Head::Unborn { name }),
+        (None, Some(sha)) => Ok(Head::Detached { sha }),
+        (None, None) => Err(Error::Process(Parsing)),
+    }
+}
+
+pub fn dispatch(connection_state: state::Connection) -> DispatchFuture {
+    use self::ErrorReason::RepoPathNotSet;
+
+    match connection_state.repo_path.clone() {
+        Some(repo_path) => Box::new(
+            read_location(&repo_path)
+                .and_then({
+                    let repo_path = repo_path.clone();
+                    move |location| {
+                        read_branch_header(&repo_path)
+                            .map(|header| (location, header))
+                    }
+                })
+                .and_then(move |(location, header)| {
+                    read_head_summary(&repo_path, &header)
+                        .map(|head_summary| (location, header, head_summary))
+                })
+                .and_then(|(location, header, head_summary)| {
+                    Ok(OutboundMessage::Success {
+                        head: to_head(&header)?,
+                        head_summary,
+                        is_shallow: location.is_shallow,
+                        object_format: location.object_format,
+                        operations_in_progress: operations_in_progress(&location.git_dir),
+                        upstream: header.upstream.clone().map(|name| Upstream {
+                            ahead: header.ahead,
+                            behind: header.behind,
+                            name,
+                        }),
+                    })
+                })
+                .then(|result| match result {
+                    Ok(message) => Ok((message, connection_state)),
+                    Err(err) => Err((err, connection_state)),
+                })
+                .and_then(|(message, connection_state)| send_message(connection_state, message)),
+        ),
+        None => Box::new(send_message(
+            connection_state,
+            OutboundMessage::Error(RepoPathNotSet),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_branch_header;
+    use util::test_repo::{block_on, TestRepo};
+
+    #[test]
+    fn counts_commits_ahead_of_the_upstream() {
+        let repo = TestRepo::new();
+        repo.commit("initial");
+        repo.git(&["branch", "base"]);
+        repo.git(&["branch", "--set-upstream-to=base"]);
+        repo.commit("ahead");
+
+        let header = block_on(read_branch_header(&repo.repo_path())).unwrap();
+        assert_eq!(header.head.as_deref(), Some("master"));
+        assert_eq!(header.upstream.as_deref(), Some("base"));
+        assert_eq!((header.ahead, header.behind), (Some(1), Some(0)));
+
+        // An upstream that is gone is still reported, without counts.
+        repo.git(&["branch", "-D", "base"]);
+        let header = block_on(read_branch_header(&repo.repo_path())).unwrap();
+        assert_eq!(header.upstream.as_deref(), Some("base"));
+        assert_eq!((header.ahead, header.behind), (None, None));
+    }
+
+    #[test]
+    fn finds_the_upstream_of_a_branch_named_like_a_tag() {
+        let repo = TestRepo::new();
+        repo.commit("initial");
+        repo.git(&["branch", "base"]);
+        repo.git(&["checkout", "-q", "-b", "topic"]);
+        repo.git(&["branch", "--set-upstream-to=base"]);
+        repo.git(&["tag", "topic"]);
+        repo.commit("ahead");
+
+        let header = block_on(read_branch_header(&repo.repo_path())).unwrap();
+        assert_eq!(header.head.as_deref(), Some("topic"));
+        assert_eq!(header.upstream.as_deref(), Some("base"));
+        assert_eq!((header.ahead, header.behind), (Some(1), Some(0)));
+    }
+
+    #[test]
+    fn reads_an_unborn_branch() {
+        let repo = TestRepo::new();
+        let header = block_on(read_branch_header(&repo.repo_path())).unwrap();
+        assert_eq!(header.head.as_deref(), Some("master"));
+        assert!(header.oid.is_none());
+        assert!(header.upstream.is_none());
+    }
+}
@@ -0,0 +1,192 @@
+mod snapshot;
+
+use self::snapshot::{take_snapshot, Snapshot};
+use error::protocol::SubhandlerError;
+use futures::{future, Future};
+use message::protocol::git_command::reset::ResetMode;
+use state;
+use tokio_process::CommandExt;
+use types::DispatchFuture;
+use util::git::{self, RepoPath};
+use util::git_error::{classify, GitErrorKind};
+use util::transport::send_message;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "ResetErrorReason")]
+#[serde(tag = "reason")]
+pub enum ErrorReason {
+    InvalidTarget,
+    LocalChangesWouldBeLost,
+    PathsRequireMixedMode,
+    RepoPathNotSet,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "ResetOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Success { snapshot: Option<Snapshot> },
+    Error(ErrorReason),
+}
+
+type ResetError = SubhandlerError<ErrorReason>;
+
+type OptionalSnapshotFuture = Box<dyn Future<Item = Option<Snapshot>, Error = ResetError> + Send>;
+
+fn verify_target(repo_path: &RepoPath, target: &str) -> impl Future<Item = (), Error = ResetError> {
+    use self::ErrorReason::InvalidTarget;
+    use error::protocol::{Error, ProcessError::Failed};
+
+    git::new_command_with_repo_path(repo_path)
+        .arg("rev-parse")
+        .arg("--verify")
+        .arg("--quiet")
+        .arg(format!("{}^{{commit}}", target))
+        .output_async()
+        .map_err(|_| SubhandlerError::Shared(Error::Process(Failed)))
+        .and_then(|output| {
+            if output.status.success() {
+                Ok(())
+            } else {
+                Err(SubhandlerError::Subhandler(InvalidTarget))
+            }
+        })
+}
+
+fn run_reset(
+    repo_path: &RepoPath,
+    target: &str,
+    mode: ResetMode,
+    paths: &[String],
+) -> impl Future<Item = (), Error = ResetError> {
+    use self::ErrorReason::LocalChangesWouldBeLost;
+    use error::protocol::{Error, ProcessError::{Failed, Git}};
+
+    let mut command = git::new_command_with_repo_path(repo_path);
+    command.arg("reset").arg("--quiet");
+
+    if paths.is_empty() {
+        command.arg(match mode {
+            ResetMode::Hard => "--hard",
+            ResetMode::Keep => "--keep",
+            ResetMode::Mixed => "--mixed",
+            ResetMode::Soft => "--soft",
+        });
+        command.arg(target);
+    } else {
+        command.arg(target).arg("--").args(paths);
+    }
+
+    command
+        .output_async()
+        .map_err(|_| SubhandlerError::Shared(Error::Process(Failed)))
+        .and_then(|output| {
+            if output.status.success() {
+                return Ok(());
+            }
+
+            let git_error = classify(&output);
+            match git_error.kind {
+                GitErrorKind::LocalChangesWouldBeLost => {
+                    Err(SubhandlerError::Subhandler(LocalChangesWouldBeLost))
+                }
+                _ => Err(SubhandlerError::Shared(Error::Process(Git(git_error)))),
+            }
+        })
+}
+
+pub fn dispatch(
+    connection_state: state::Connection,
+    target: String,
+    mode: Option<ResetMode>,
+    paths: Vec<String>,
+) -> DispatchFuture {
+    use self::ErrorReason::{InvalidTarget, PathsRequireMixedMode, RepoPathNotSet};
+
+    // Would be taken for an option by git.
+    if target.starts_with('-') {
+        return Box::new(send_message(connection_state, OutboundMessage::Error(InvalidTarget)));
+    }
+
+    // Like git, a reset that is limited to paths only ever touches the index.
+    let mode = mode.unwrap_or(ResetMode::Mixed);
+    if !paths.is_empty() && mode != ResetMode::Mixed {
+        return Box::new(send_message(
+            connection_state,
+            OutboundMessage::Error(PathsRequireMixedMode),
+        ));
+    }
+
+    match connection_state.repo_path.clone() {
+        Some(repo_path) => Box::new(
+            verify_target(&repo_path, &target)
+                .and_then({
+                    let repo_path = repo_path.clone();
+                    let target = target.clone();
+                    move |_| -> OptionalSnapshotFuture {
+                        if mode == ResetMode::Hard {
+                            Box::new(
+                                take_snapshot(repo_path, target).map_err(SubhandlerError::Shared),
+                            )
+                        } else {
+                            Box::new(future::ok(None))
+                        }
+                    }
+                })
+                .and_then(move |snapshot| {
+                    run_reset(&repo_path, &target, mode, &paths).map(|_| snapshot)
+                })
+                .then(|result| -> DispatchFuture {
+                    match result {
+                        Ok(snapshot) => Box::new(send_message(
+                            connection_state,
+                            OutboundMessage::Success { snapshot },
+                        )),
+                        Err(SubhandlerError::Subhandler(reason)) => Box::new(send_message(
+                            connection_state,
+                            OutboundMessage::Error(reason),
+                        )),
+                        Err(SubhandlerError::Shared(err)) => {
+                            Box::new(future::err((err, connection_state)))
+                        }
+                    }
+                }),
+        ),
+        None => Box::new(send_message(
+            connection_state,
+            OutboundMessage::Error(RepoPathNotSet),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_reset, ErrorReason};
+    use error::protocol::{Error, ProcessError, SubhandlerError};
+    use message::protocol::git_command::reset::ResetMode;
+    use std::fs;
+    use util::git_error::GitErrorKind;
+    use util::test_repo::{block_on, TestRepo};
+
+    #[test]
+    fn only_blames_local_changes_when_git_refused_over_them() {
+        let repo = TestRepo::new();
+        repo.write("file", b"first\n");
+        repo.commit("first");
+        repo.write("file", b"second\n");
+        repo.commit("second");
+
+        repo.write("file", b"modified\n");
+        match block_on(run_reset(&repo.repo_path(), "HEAD~1", ResetMode::Keep, &[])) {
+            Err(SubhandlerError::Subhandler(ErrorReason::LocalChangesWouldBeLost)) => {}
+            other => panic!("reset --keep over local changes resulted in {:?}", other),
+        }
+
+        fs::write(repo.join(".git/index.lock"), b"").unwrap();
+        match block_on(run_reset(&repo.repo_path(), "HEAD", ResetMode::Keep, &[])) {
+            Err(SubhandlerError::Shared(Error::Process(ProcessError::Git(ref git_error))))
+                if git_error.kind == GitErrorKind::IndexLocked => {}
+            other => panic!("reset --keep on a locked index resulted in {:?}", other),
+        }
+    }
+}
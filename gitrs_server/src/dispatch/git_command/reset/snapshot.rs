@@ -0,0 +1,327 @@
+use error::protocol::Error;
+use futures::{future, Future};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use tokio_process::CommandExt;
+use util::git::{self, RepoPath};
+use util::git_error::classify;
+use uuid::Uuid;
+
+/// Every snapshot is recorded in this ref's reflog, the same way `git stash` keeps `refs/stash`.
+static SNAPSHOT_REF: &str = "refs/git-rs/reset-snapshot";
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Snapshot {
+    has_local_changes: bool,
+    /// The commit HEAD pointed at, unless it was unborn.
+    head_sha: Option<String>,
+    reference: String,
+    sha: String,
+}
+
+type SnapshotFuture<T> = Box<dyn Future<Item = T, Error = Error> + Send>;
+
+fn run(mut command: Command) -> impl Future<Item = Output, Error = Error> {
+    use error::protocol::ProcessError::{Failed, Git};
+
+    command
+        .output_async()
+        .map_err(|_| Error::Process(Failed))
+        .and_then(|output| {
+            if output.status.success() {
+                Ok(output)
+            } else {
+                Err(Error::Process(Git(classify(&output))))
+            }
+        })
+}
+
+fn read_line(command: Command) -> impl Future<Item = String, Error = Error> {
+    run(command).map(|output| String::from(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+// `--verify --quiet` fails without a word when HEAD is unborn, and with one on anything else.
+fn read_head(repo_path: &RepoPath) -> impl Future<Item = Option<String>, Error = Error> {
+    use error::protocol::ProcessError::{Failed, Git};
+
+    git::new_command_with_repo_path(repo_path)
+        .arg("rev-parse")
+        .arg("--verify")
+        .arg("--quiet")
+        .arg("HEAD^{commit}")
+        .output_async()
+        .map_err(|_| Error::Process(Failed))
+        .and_then(|output| {
+            if output.status.success() {
+                Ok(Some(String::from(String::from_utf8_lossy(&output.stdout).trim())))
+            } else if output.stderr.is_empty() {
+                Ok(None)
+            } else {
+                Err(Error::Process(Git(classify(&output))))
+            }
+        })
+}
+
+// Whether a hard reset would lose anything, untracked files included. Ignored files are left
+// alone by the reset, so they do not count.
+fn has_local_changes(repo_path: &RepoPath) -> impl Future<Item = bool, Error = Error> {
+    let mut command = git::new_command_with_repo_path(repo_path);
+    command
+        .arg("status")
+        .arg("--porcelain")
+        .arg("-z")
+        .arg("--untracked-files=all")
+        .arg("--ignore-submodules");
+    run(command).map(|output| !output.stdout.is_empty())
+}
+
+fn git_path(repo_path: &RepoPath, path: &str) -> impl Future<Item = PathBuf, Error = Error> {
+    let mut command = git::new_command_with_repo_path(repo_path);
+    command.arg("rev-parse").arg("--git-path").arg(path);
+    let repo_path = PathBuf::from(&repo_path.path);
+    read_line(command).map(move |git_path| repo_path.join(git_path))
+}
+
+// Writes the working tree, untracked files included, as a tree by adding everything to a copy of
+// the index. The copy keeps tracked files that are ignored, which `add -A` would otherwise miss.
+fn write_worktree_tree(repo_path: RepoPath) -> SnapshotFuture<String> {
+    use error::protocol::ProcessError::Failed;
+
+    let index_path = git_path(&repo_path, "index");
+    let temporary_index_path =
+        git_path(&repo_path, &format!("git-rs-snapshot-index-{}", Uuid::new_v4()));
+
+    Box::new(index_path.join(temporary_index_path).and_then(
+        move |(index_path, temporary_index_path)| {
+            match fs::copy(&index_path, &temporary_index_path) {
+                Ok(_) => {}
+                // A repository that never had anything staged has no index yet.
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(_) => return Box::new(future::err(Error::Process(Failed))) as SnapshotFuture<_>,
+            }
+
+            let mut command_add = git::new_command_with_repo_path(&repo_path);
+            command_add
+                .env("GIT_INDEX_FILE", &temporary_index_path)
+                .arg("add")
+                .arg("--all")
+                .arg("--")
+                .arg(":/");
+            let mut command_write_tree = git::new_command_with_repo_path(&repo_path);
+            command_write_tree
+                .env("GIT_INDEX_FILE", &temporary_index_path)
+                .arg("write-tree");
+
+            Box::new(
+                run(command_add)
+                    .and_then(|_| read_line(command_write_tree))
+                    .then(move |result| {
+                        let _ = fs::remove_file(&temporary_index_path);
+                        result
+                    }),
+            )
+        },
+    ))
+}
+
+// `git stash` falls back to an identity made up from the user and host names, which `commit-tree`
+// refuses to do. Snapshots are taken without asking, so they must not fail over a missing one.
+fn has_identity(repo_path: &RepoPath) -> impl Future<Item = bool, Error = Error> {
+    use error::protocol::ProcessError::Failed;
+
+    git::new_command_with_repo_path(repo_path)
+        .arg("var")
+        .arg("GIT_COMMITTER_IDENT")
+        .output_async()
+        .map_err(|_| Error::Process(Failed))
+        .map(|output| output.status.success())
+}
+
+fn commit_tree(
+    repo_path: &RepoPath,
+    has_identity: bool,
+    tree: &str,
+    parents: &[&str],
+    message: &str,
+) -> impl Future<Item = String, Error = Error> {
+    let mut command = git::new_command_with_repo_path(repo_path);
+    if !has_identity {
+        for variable in &["GIT_AUTHOR_NAME", "GIT_COMMITTER_NAME"] {
+            command.env(variable, "git-rs");
+        }
+        for variable in &["GIT_AUTHOR_EMAIL", "GIT_COMMITTER_EMAIL"] {
+            command.env(variable, "git-rs@localhost");
+        }
+    }
+    command.arg("commit-tree").arg(tree);
+    for parent in parents {
+        command.arg("-p").arg(parent);
+    }
+    command.arg("-m").arg(message);
+    read_line(command)
+}
+
+// Commits the index and the working tree the way `git stash` does: the working tree commit has
+// HEAD and the index commit as its parents. Untracked files are part of the working tree commit.
+fn commit_local_changes(
+    repo_path: RepoPath,
+    head_sha: Option<String>,
+    target: &str,
+) -> SnapshotFuture<String> {
+    let mut command_write_tree = git::new_command_with_repo_path(&repo_path);
+    command_write_tree.arg("write-tree");
+    let index_message = format!("index before reset --hard to {}", target);
+    let worktree_message = format!("snapshot before reset --hard to {}", target);
+
+    Box::new(
+        read_line(command_write_tree)
+            .join3(write_worktree_tree(repo_path.clone()), has_identity(&repo_path))
+            .and_then(move |(index_tree, worktree_tree, has_identity)| {
+                let head_parents: Vec<&str> = head_sha.iter().map(String::as_str).collect();
+                commit_tree(
+                    &repo_path,
+                    has_identity,
+                    &index_tree,
+                    &head_parents,
+                    &index_message,
+                )
+                .and_then(move |index_sha| {
+                    let mut parents: Vec<&str> = head_sha.iter().map(String::as_str).collect();
+                    parents.push(&index_sha);
+                    commit_tree(
+                        &repo_path,
+                        has_identity,
+                        &worktree_tree,
+                        &parents,
+                        &worktree_message,
+                    )
+                })
+            }),
+    )
+}
+
+fn record_snapshot(
+    repo_path: &RepoPath,
+    snapshot: Snapshot,
+    target: &str,
+) -> impl Future<Item = Snapshot, Error = Error> {
+    let mut command = git::new_command_with_repo_path(repo_path);
+    command
+        .arg("update-ref")
+        .arg("--create-reflog")
+        .arg("-m")
+        .arg(format!("reset --hard: moving to {}", target))
+        .arg(SNAPSHOT_REF)
+        .arg(&snapshot.sha);
+    run(command).map(|_| snapshot)
+}
+
+/// Records HEAD together with the index and the working tree, untracked files included, as a
+/// stash-like commit, so that a hard reset can be recovered from with `git stash apply <sha>` or
+/// `git reset --hard <head_sha>`. There is nothing to record for a clean repository whose HEAD is
+/// unborn.
+pub fn take_snapshot(
+    repo_path: RepoPath,
+    target: String,
+) -> impl Future<Item = Option<Snapshot>, Error = Error> {
+    read_head(&repo_path)
+        .join(has_local_changes(&repo_path))
+        .and_then(move |(head_sha, has_local_changes)| -> SnapshotFuture<Option<Snapshot>> {
+            let sha: SnapshotFuture<Option<String>> = match (has_local_changes, head_sha.clone()) {
+                (true, _) => Box::new(
+                    commit_local_changes(repo_path.clone(), head_sha.clone(), &target).map(Some),
+                ),
+                (false, head_sha) => Box::new(future::ok(head_sha)),
+            };
+
+            Box::new(sha.and_then(move |sha| -> SnapshotFuture<Option<Snapshot>> {
+                match sha {
+                    Some(sha) => {
+                        let snapshot = Snapshot {
+                            has_local_changes,
+                            head_sha,
+                            reference: String::from(SNAPSHOT_REF),
+                            sha,
+                        };
+                        Box::new(record_snapshot(&repo_path, snapshot, &target).map(Some))
+                    }
+                    None => Box::new(future::ok(None)),
+                }
+            }))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::take_snapshot;
+    use util::test_repo::{block_on, TestRepo};
+
+    fn show(repo: &TestRepo, object: &str) -> String {
+        String::from_utf8(repo.git(&["show", object]).stdout).unwrap()
+    }
+
+    #[test]
+    fn records_untracked_files() {
+        let repo = TestRepo::new();
+        repo.write("tracked", b"committed\n");
+        repo.commit("initial");
+        repo.write("tracked", b"staged\n");
+        repo.git(&["add", "tracked"]);
+        repo.write("tracked", b"modified\n");
+        repo.write("untracked", b"untracked\n");
+        repo.write(".gitignore", b"ignored\n");
+        repo.write("ignored", b"ignored\n");
+
+        let snapshot = block_on(take_snapshot(repo.repo_path(), String::from("HEAD")))
+            .unwrap()
+            .unwrap();
+        assert!(snapshot.has_local_changes);
+        assert!(snapshot.head_sha.is_some());
+
+        assert_eq!(show(&repo, &format!("{}:tracked", snapshot.sha)), "modified\n");
+        assert_eq!(show(&repo, &format!("{}:untracked", snapshot.sha)), "untracked\n");
+        assert_eq!(show(&repo, &format!("{}^2:tracked", snapshot.sha)), "staged\n");
+        assert!(!repo.run(&["cat-file", "-e", &format!("{}:ignored", snapshot.sha)]).status.success());
+        assert_eq!(
+            show(&repo, "refs/git-rs/reset-snapshot:untracked"),
+            "untracked\n"
+        );
+
+        // The real index is left as it was.
+        assert_eq!(show(&repo, ":tracked"), "staged\n");
+        assert!(!repo.run(&["cat-file", "-e", ":untracked"]).status.success());
+    }
+
+    #[test]
+    fn points_at_head_when_clean() {
+        let repo = TestRepo::new();
+        repo.commit("initial");
+        let head = String::from_utf8(repo.git(&["rev-parse", "HEAD"]).stdout).unwrap();
+
+        let snapshot = block_on(take_snapshot(repo.repo_path(), String::from("HEAD")))
+            .unwrap()
+            .unwrap();
+        assert!(!snapshot.has_local_changes);
+        assert_eq!(snapshot.sha, head.trim());
+    }
+
+    #[test]
+    fn handles_an_unborn_head() {
+        let repo = TestRepo::new();
+        assert!(
+            block_on(take_snapshot(repo.repo_path(), String::from("HEAD")))
+                .unwrap()
+                .is_none()
+        );
+
+        repo.write("untracked", b"untracked\n");
+        let snapshot = block_on(take_snapshot(repo.repo_path(), String::from("HEAD")))
+            .unwrap()
+            .unwrap();
+        assert!(snapshot.has_local_changes);
+        assert!(snapshot.head_sha.is_none());
+        assert_eq!(show(&repo, &format!("{}:untracked", snapshot.sha)), "untracked\n");
+    }
+}
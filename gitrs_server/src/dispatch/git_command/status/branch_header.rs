@@ -0,0 +1,72 @@
+use error::protocol::{Error, ProcessError::Parsing};
+
+/// The `# branch.*` lines that `git status --porcelain=v2 --branch -z` prints before any entry.
+#[derive(Clone, Debug, Default, Serialize, JsonSchema)]
+pub struct BranchHeader {
+    pub ahead: Option<u32>,
+    pub behind: Option<u32>,
+    /// `None` when HEAD is detached.
+    pub head: Option<String>,
+    /// `None` when the branch has no commits yet.
+    pub oid: Option<String>,
+    pub upstream: Option<String>,
+}
+
+// Like entries, header lines end with a NUL when `-z` is given.
+named!(parse_header_line<&[u8], (&[u8], &[u8])>,
+    do_parse!(
+        tag!("# ") >>
+        key: take_until!(" ") >>
+        char!(' ') >>
+        value: take_until!("\0") >>
+        char!('\0') >>
+        ((key, value))
+    )
+);
+
+named!(parse_header_lines<&[u8], Vec<(&[u8], &[u8])>>,
+    many0!(complete!(parse_header_line))
+);
+
+// `branch.ab` is only printed when the upstream exists, as `+<ahead> -<behind>`.
+fn parse_ahead_behind(value: &str) -> Option<(u32, u32)> {
+    let mut counts = value.split(' ');
+    match (counts.next(), counts.next(), counts.next()) {
+        (Some(ahead), Some(behind), None) if ahead.starts_with('+') && behind.starts_with('-') => {
+            match (ahead[1..].parse(), behind[1..].parse()) {
+                (Ok(ahead), Ok(behind)) => Some((ahead, behind)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Parses the header lines at the start of the output and returns whatever follows them.
+pub fn parse_branch_header(input: &[u8]) -> Result<(&[u8], BranchHeader), Error> {
+    let (rest, lines) = match parse_header_lines(input) {
+        Ok(result) => result,
+        Err(_) => return Err(Error::Process(Parsing)),
+    };
+
+    let mut header = BranchHeader::default();
+    for (key, value) in lines {
+        let value = String::from_utf8_lossy(value);
+        let value = value.as_ref();
+        match key {
+            b"branch.ab" => match parse_ahead_behind(value) {
+                Some((ahead, behind)) => {
+                    header.ahead = Some(ahead);
+                    header.behind = Some(behind);
+                }
+                None => return Err(Error::Process(Parsing)),
+            },
+            b"branch.head" if value != "(detached)" => header.head = Some(String::from(value)),
+            b"branch.oid" if value != "(initial)" => header.oid = Some(String::from(value)),
+            b"branch.upstream" => header.upstream = Some(String::from(value)),
+            _ => {}
+        }
+    }
+
+    Ok((rest, header))
+}
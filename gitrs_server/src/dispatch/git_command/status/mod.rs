@@ -1,58 +1,110 @@
+mod branch_header;
 mod status_entry;
 
+pub use self::branch_header::{parse_branch_header, BranchHeader};
 use self::status_entry::{parse_git_status, StatusResult};
-use config;
 use futures::{future, Future};
+use message::protocol::git_command::status::{IgnoreSubmodules, IgnoredMode, StatusOptions,
+                                             UntrackedMode};
 use state;
-use std::str;
+use std::process::Command;
 use tokio_process::CommandExt;
 use types::DispatchFuture;
-use util::git;
+use util::git::{self, RepoPath};
+use util::git_error::classify;
 use util::transport::send_message;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "StatusErrorReason")]
 #[serde(tag = "reason")]
 pub enum ErrorReason {
     RepoPathNotSet,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "StatusOutbound")]
 #[serde(tag = "type")]
 pub enum OutboundMessage {
-    Success { status: StatusResult },
+    Success {
+        branch: Option<BranchHeader>,
+        status: StatusResult,
+    },
     Error(ErrorReason),
 }
 
-pub fn dispatch(connection_state: state::Connection) -> DispatchFuture {
+fn new_status_command(repo_path: &RepoPath, options: &StatusOptions) -> Command {
+    let mut command = git::new_command_with_repo_path(repo_path);
+    command.arg("status").arg("--porcelain=v2").arg("-z");
+
+    if options.branch {
+        command.arg("--branch");
+    }
+
+    command.arg(match options.untracked.unwrap_or(UntrackedMode::All) {
+        UntrackedMode::All => "--untracked-files=all",
+        UntrackedMode::No => "--untracked-files=no",
+        UntrackedMode::Normal => "--untracked-files=normal",
+    });
+
+    if let Some(ignored) = options.ignored {
+        command.arg(match ignored {
+            IgnoredMode::Matching => "--ignored=matching",
+            IgnoredMode::No => "--ignored=no",
+            IgnoredMode::Traditional => "--ignored=traditional",
+        });
+    }
+
+    if let Some(ignore_submodules) = options.ignore_submodules {
+        command.arg(match ignore_submodules {
+            IgnoreSubmodules::All => "--ignore-submodules=all",
+            IgnoreSubmodules::Dirty => "--ignore-submodules=dirty",
+            IgnoreSubmodules::None => "--ignore-submodules=none",
+            IgnoreSubmodules::Untracked => "--ignore-submodules=untracked",
+        });
+    }
+
+    if !options.pathspecs.is_empty() {
+        command.arg("--").args(&options.pathspecs);
+    }
+
+    command
+}
+
+pub fn dispatch(connection_state: state::Connection, options: StatusOptions) -> DispatchFuture {
     use self::ErrorReason::RepoPathNotSet;
-    use error::protocol::{Error, ProcessError::{Encoding, Failed}};
+    use error::protocol::{Error, ProcessError::{Failed, Git}};
 
     match connection_state.repo_path.clone() {
         Some(repo_path) => Box::new(
-            git::new_command_with_repo_path(&repo_path)
-                .arg("status")
-                .arg("--porcelain=v2")
-                .arg("--untracked-files")
+            new_status_command(&repo_path, &options)
                 .output_async()
                 .then(|result| match result {
-                    Ok(output) => match str::from_utf8(&output.stdout) {
-                        Ok(output) => future::ok((String::from(output), connection_state)),
-                        Err(_) => future::err((Error::Process(Encoding), connection_state)),
+                    Ok(output) => if !output.status.success() {
+                        future::err((Error::Process(Git(classify(&output))), connection_state))
+                    } else {
+                        future::ok((output.stdout, connection_state))
                     },
                     Err(_) => future::err((Error::Process(Failed), connection_state)),
                 })
-                .and_then(|(result, connection_state)| -> DispatchFuture {
-                    if result.len() == 0 {
+                .and_then(move |(result, connection_state)| -> DispatchFuture {
+                    // The header only comes first when asked for, but is harmless to look for.
+                    let (entries, branch) = match parse_branch_header(&result) {
+                        Ok((entries, branch)) => (entries, branch),
+                        Err(e) => return Box::new(future::err((e, connection_state))),
+                    };
+                    let branch = if options.branch { Some(branch) } else { None };
+
+                    if entries.is_empty() {
                         return Box::new(send_message(
                             connection_state,
-                            OutboundMessage::Success { status: StatusResult::new() }
+                            OutboundMessage::Success { branch, status: StatusResult::new() }
                         ));
                     }
 
-                    match parse_git_status(&result) {
+                    match parse_git_status(entries) {
                         Ok(status) => Box::new(send_message(
                             connection_state,
-                            OutboundMessage::Success { status },
+                            OutboundMessage::Success { branch, status },
                         )),
                         Err(e) => Box::new(future::err((e, connection_state))),
                     }
@@ -1,8 +1,10 @@
 use error::protocol::{Error, ProcessError::Parsing};
 use nom::{digit1, oct_digit1};
-use util::parse::{sha, parse_u32};
+use std::str;
+use util::lossless::LosslessString;
+use util::parse::{sha_bytes, parse_u32};
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, JsonSchema)]
 pub enum Status {
     Added,
     Modified,
@@ -13,40 +15,41 @@ pub enum Status {
     Unmerged,
 }
 
-named!(parse_status<&str, Option<Status>>,
+named!(parse_status<&[u8], Option<Status>>,
     switch!(take!(1),
-        "M" => value!(Some(Status::Modified)) |
-        "A" => value!(Some(Status::Added)) |
-        "D" => value!(Some(Status::Deleted)) |
-        "R" => value!(Some(Status::Renamed)) |
-        "C" => value!(Some(Status::Copied)) |
-        "?" => value!(Some(Status::Untracked)) |
-        "." => value!(None)
+        b"M" => value!(Some(Status::Modified)) |
+        b"A" => value!(Some(Status::Added)) |
+        b"D" => value!(Some(Status::Deleted)) |
+        b"R" => value!(Some(Status::Renamed)) |
+        b"C" => value!(Some(Status::Copied)) |
+        b"?" => value!(Some(Status::Untracked)) |
+        b"." => value!(None)
     )
 );
 
-named!(parse_status_unmerged<&str, Status>,
+named!(parse_status_unmerged<&[u8], Status>,
     switch!(take!(1),
-        "A" => value!(Status::Added) |
-        "D" => value!(Status::Deleted) |
-        "U" => value!(Status::Unmerged)
+        b"A" => value!(Status::Added) |
+        b"D" => value!(Status::Deleted) |
+        b"U" => value!(Status::Unmerged)
     )
 );
 
 #[derive(Debug)]
 pub struct FileModeStatus {
+    #[allow(dead_code)]
     head: u32,
     index: u32,
     worktree: u32,
 }
 
-named!(parse_file_mode<&str, FileModeStatus>,
+named!(parse_file_mode<&[u8], FileModeStatus>,
     do_parse!(
-        head: oct_digit1 >>
+        head: map_res!(oct_digit1, str::from_utf8) >>
         char!(' ') >>
-        index: oct_digit1 >>
+        index: map_res!(oct_digit1, str::from_utf8) >>
         char!(' ') >>
-        worktree: oct_digit1 >>
+        worktree: map_res!(oct_digit1, str::from_utf8) >>
         (FileModeStatus {
             head: parse_u32(head, 8),
             index: parse_u32(index, 8),
@@ -63,15 +66,15 @@ pub struct UnmergedFileModeStatus {
     worktree: u32,
 }
 
-named!(parse_unmerged_file_mode<&str, UnmergedFileModeStatus>,
+named!(parse_unmerged_file_mode<&[u8], UnmergedFileModeStatus>,
     do_parse!(
-        stage_1: oct_digit1 >>
+        stage_1: map_res!(oct_digit1, str::from_utf8) >>
         char!(' ') >>
-        stage_2: oct_digit1 >>
+        stage_2: map_res!(oct_digit1, str::from_utf8) >>
         char!(' ') >>
-        stage_3: oct_digit1 >>
+        stage_3: map_res!(oct_digit1, str::from_utf8) >>
         char!(' ') >>
-        worktree: oct_digit1 >>
+        worktree: map_res!(oct_digit1, str::from_utf8) >>
         (UnmergedFileModeStatus {
             stage_1: parse_u32(stage_1, 8),
             stage_2: parse_u32(stage_2, 8),
@@ -81,60 +84,60 @@ named!(parse_unmerged_file_mode<&str, UnmergedFileModeStatus>,
     )
 );
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, JsonSchema)]
 pub enum ScoreType {
     Renamed,
     Copied,
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, JsonSchema)]
 pub struct Score {
     score_type: ScoreType,
     percentage: u32,
 }
 
-named!(parse_score<&str, Score>,
+named!(parse_score<&[u8], Score>,
     do_parse!(
-        score_type: switch!(take!(1), "R" => value!(ScoreType::Renamed) | "C" => value!(ScoreType::Copied)) >>
-        percentage: digit1 >>
+        score_type: switch!(take!(1), b"R" => value!(ScoreType::Renamed) | b"C" => value!(ScoreType::Copied)) >>
+        percentage: map_res!(digit1, str::from_utf8) >>
         (Score { score_type, percentage: parse_u32(percentage, 10) })
     )
 );
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, JsonSchema)]
 pub struct SubmoduleStatus {
     commit_changed: bool,
     has_tracked_changes: bool,
     has_untracked_changes: bool,
 }
 
-named!(parse_submodule_status<&str, Option<SubmoduleStatus>>,
+named!(parse_submodule_status<&[u8], Option<SubmoduleStatus>>,
     do_parse!(
-        commit_changed: switch!(take!(1), "C" => value!(true) | "." => value!(false)) >>
-        has_tracked_changes: switch!(take!(1), "M" => value!(true) | "." => value!(false)) >>
-        has_untracked_changes: switch!(take!(1), "U" => value!(true) | "." => value!(false)) >>
+        commit_changed: switch!(take!(1), b"C" => value!(true) | b"." => value!(false)) >>
+        has_tracked_changes: switch!(take!(1), b"M" => value!(true) | b"." => value!(false)) >>
+        has_untracked_changes: switch!(take!(1), b"U" => value!(true) | b"." => value!(false)) >>
         (Some(SubmoduleStatus { commit_changed, has_tracked_changes, has_untracked_changes }))
     )
 );
 
-named!(parse_maybe_submodule_status<&str, Option<SubmoduleStatus>>,
+named!(parse_maybe_submodule_status<&[u8], Option<SubmoduleStatus>>,
     switch!(take!(1),
-        "S" => call!(parse_submodule_status) |
-        "N" => do_parse!(take!(3) >> (None))
+        b"S" => call!(parse_submodule_status) |
+        b"N" => do_parse!(take!(3) >> (None))
     )
 );
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, JsonSchema)]
 pub struct StatusOids {
     head: String,
     index: String,
 }
 
-named!(parse_status_oids<&str, StatusOids>,
+named!(parse_status_oids<&[u8], StatusOids>,
     do_parse!(
-        head: sha >>
+        head: sha_bytes >>
         char!(' ') >>
-        index: sha >>
+        index: sha_bytes >>
         (StatusOids { head: head.to_string(), index: index.to_string() })
     )
 );
@@ -146,13 +149,13 @@ pub struct UnmergedStatusOids {
     stage_3: String,
 }
 
-named!(parse_unmerged_status_oids<&str, UnmergedStatusOids>,
+named!(parse_unmerged_status_oids<&[u8], UnmergedStatusOids>,
     do_parse!(
-        stage_1: sha >>
+        stage_1: sha_bytes >>
         char!(' ') >>
-        stage_2: sha >>
+        stage_2: sha_bytes >>
         char!(' ') >>
-        stage_3: sha >>
+        stage_3: sha_bytes >>
         (UnmergedStatusOids {
             stage_1: stage_1.to_string(),
             stage_2: stage_2.to_string(),
@@ -161,6 +164,7 @@ named!(parse_unmerged_status_oids<&str, UnmergedStatusOids>,
     )
 );
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum StatusEntry {
     OrdinaryStatusEntry(OrdinaryStatusEntry),
@@ -177,7 +181,7 @@ pub struct OrdinaryStatusEntry {
     submodule_status: Option<SubmoduleStatus>,
     file_mode: FileModeStatus,
     oids: StatusOids,
-    path: String,
+    path: LosslessString,
 }
 
 #[derive(Debug)]
@@ -188,8 +192,8 @@ pub struct CopiedOrRenamedStatusEntry {
     file_mode: FileModeStatus,
     oids: StatusOids,
     score: Score,
-    path: String,
-    original_path: String,
+    path: LosslessString,
+    original_path: LosslessString,
 }
 
 #[derive(Debug)]
@@ -199,20 +203,29 @@ pub struct UnmergedStatusEntry {
     submodule_status: Option<SubmoduleStatus>,
     file_mode: UnmergedFileModeStatus,
     oids: UnmergedStatusOids,
-    path: String,
+    path: LosslessString,
 }
 
 #[derive(Debug)]
 pub struct UntrackedStatusEntry {
-    path: String,
+    path: LosslessString,
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, JsonSchema)]
 pub struct IgnoredStatusEntry {
-    path: String,
+    path: LosslessString,
 }
 
-named!(parse_ordinary_status_entry<&str, StatusEntry>,
+// With `-z` paths are neither quoted nor escaped and end with a NUL, which cannot be part of one.
+named!(parse_path<&[u8], LosslessString>,
+    do_parse!(
+        path: take_until!("\0") >>
+        char!('\0') >>
+        (LosslessString::from(path))
+    )
+);
+
+named!(parse_ordinary_status_entry<&[u8], StatusEntry>,
     do_parse!(
         staged_status: parse_status >>
         unstaged_status: parse_status >>
@@ -223,19 +236,19 @@ named!(parse_ordinary_status_entry<&str, StatusEntry>,
         char!(' ') >>
         oids: parse_status_oids >>
         char!(' ') >>
-        path: take_until!("\n") >>
+        path: parse_path >>
         (StatusEntry::OrdinaryStatusEntry(OrdinaryStatusEntry {
             staged_status,
             unstaged_status,
             submodule_status,
             file_mode,
             oids,
-            path: path.to_string(),
+            path,
         }))
     )
 );
 
-named!(parse_copied_or_renamed_status_entry<&str, StatusEntry>,
+named!(parse_copied_or_renamed_status_entry<&[u8], StatusEntry>,
     do_parse!(
         staged_status: parse_status >>
         unstaged_status: parse_status >>
@@ -248,9 +261,8 @@ named!(parse_copied_or_renamed_status_entry<&str, StatusEntry>,
         char!(' ') >>
         score: parse_score >>
         char!(' ') >>
-        path: take_until!("\t") >>
-        char!('\t') >>
-        original_path: take_until!("\n") >>
+        path: parse_path >>
+        original_path: parse_path >>
         (StatusEntry::CopiedOrRenamedStatusEntry(CopiedOrRenamedStatusEntry {
             staged_status,
             unstaged_status,
@@ -258,13 +270,13 @@ named!(parse_copied_or_renamed_status_entry<&str, StatusEntry>,
             file_mode,
             oids,
             score,
-            path: path.to_string(),
-            original_path: original_path.to_string(),
+            path,
+            original_path,
         }))
     )
 );
 
-named!(parse_unmerged_status_entry<&str, StatusEntry>,
+named!(parse_unmerged_status_entry<&[u8], StatusEntry>,
     do_parse!(
         staged_status: parse_status_unmerged >>
         unstaged_status: parse_status_unmerged >>
@@ -275,71 +287,64 @@ named!(parse_unmerged_status_entry<&str, StatusEntry>,
         char!(' ') >>
         oids: parse_unmerged_status_oids >>
         char!(' ') >>
-        path: take_until!("\n") >>
+        path: parse_path >>
         (StatusEntry::UnmergedStatusEntry(UnmergedStatusEntry {
             staged_status,
             unstaged_status,
             submodule_status,
             file_mode,
             oids,
-            path: path.to_string(),
+            path,
         }))
     )
 );
 
-named!(parse_untracked_status_entry<&str, StatusEntry>,
+named!(parse_untracked_status_entry<&[u8], StatusEntry>,
     do_parse!(
-        path: take_until!("\n") >>
-        (StatusEntry::UntrackedStatusEntry(UntrackedStatusEntry { path: path.to_string() }))
+        path: parse_path >>
+        (StatusEntry::UntrackedStatusEntry(UntrackedStatusEntry { path }))
     )
 );
 
-named!(parse_ignored_status_entry<&str, StatusEntry>,
+named!(parse_ignored_status_entry<&[u8], StatusEntry>,
     do_parse!(
-        path: take_until!("\n") >>
-        (StatusEntry::IgnoredStatusEntry(IgnoredStatusEntry { path: path.to_string() }))
+        path: parse_path >>
+        (StatusEntry::IgnoredStatusEntry(IgnoredStatusEntry { path }))
     )
 );
 
-named!(parse_status_entry<&str, StatusEntry>,
+named!(parse_status_entry<&[u8], StatusEntry>,
     switch!(take!(2),
-        "1 " => call!(parse_ordinary_status_entry) |
-        "2 " => call!(parse_copied_or_renamed_status_entry) |
-        "u " => call!(parse_unmerged_status_entry) |
-        "! " => call!(parse_untracked_status_entry) |
-        "? " => call!(parse_ignored_status_entry)
+        b"1 " => call!(parse_ordinary_status_entry) |
+        b"2 " => call!(parse_copied_or_renamed_status_entry) |
+        b"u " => call!(parse_unmerged_status_entry) |
+        b"? " => call!(parse_untracked_status_entry) |
+        b"! " => call!(parse_ignored_status_entry)
     )
 );
 
-named!(parse_status_entries<&str, Vec<StatusEntry>>,
-    do_parse!(
-        entries: separated_list!(
-            char!('\n'),
-            complete!(parse_status_entry)
-        ) >>
-        char!('\n') >>
-        (entries)
-    )
+named!(parse_status_entries<&[u8], Vec<StatusEntry>>,
+    many0!(complete!(parse_status_entry))
 );
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct AncestorSide {
     file_mode: u32,
     oid: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct ConflictSide {
     file_mode: u32,
     oid: String,
     status: Status,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct ConflictStatusEntry {
     ancestor: AncestorSide,
     our: ConflictSide,
-    path: String,
+    path: LosslessString,
     submodule_status: Option<SubmoduleStatus>,
     their: ConflictSide,
     worktree_file_mode: u32,
@@ -349,32 +354,32 @@ impl ConflictStatusEntry {
     fn from_unmerged_status_entry(entry: &UnmergedStatusEntry) -> StatusEntryOutput {
         StatusEntryOutput::Conflict(Self {
             ancestor: AncestorSide {
-                file_mode: entry.file_mode.stage_1.clone(),
+                file_mode: entry.file_mode.stage_1,
                 oid: entry.oids.stage_1.clone(),
             },
             our: ConflictSide {
-                file_mode: entry.file_mode.stage_2.clone(),
+                file_mode: entry.file_mode.stage_2,
                 oid: entry.oids.stage_2.clone(),
                 status: entry.staged_status.clone(),
             },
             path: entry.path.clone(),
             submodule_status: entry.submodule_status.clone(),
             their: ConflictSide {
-                file_mode: entry.file_mode.stage_3.clone(),
+                file_mode: entry.file_mode.stage_3,
                 oid: entry.oids.stage_3.clone(),
                 status: entry.unstaged_status.clone(),
             },
-            worktree_file_mode: entry.file_mode.worktree.clone(),
+            worktree_file_mode: entry.file_mode.worktree,
         })
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct StagedStatusEntry {
     file_mode: u32,
     oids: StatusOids,
-    original_path: Option<String>,
-    path: String,
+    original_path: Option<LosslessString>,
+    path: LosslessString,
     score: Option<Score>,
     status: Status,
     submodule_status: Option<SubmoduleStatus>,
@@ -384,7 +389,7 @@ impl StagedStatusEntry {
     fn from_ordinary_status_entry(entry: &OrdinaryStatusEntry) -> Option<StatusEntryOutput> {
         entry.staged_status.as_ref().map(|status| {
             StatusEntryOutput::Staged(Self {
-                file_mode: entry.file_mode.index.clone(),
+                file_mode: entry.file_mode.index,
                 oids: entry.oids.clone(),
                 original_path: None,
                 path: entry.path.clone(),
@@ -404,7 +409,7 @@ impl StagedStatusEntry {
     ) -> Option<StatusEntryOutput> {
         entry.staged_status.as_ref().map(|status| {
             StatusEntryOutput::Staged(Self {
-                file_mode: entry.file_mode.index.clone(),
+                file_mode: entry.file_mode.index,
                 oids: entry.oids.clone(),
                 original_path: Some(entry.original_path.clone()),
                 path: entry.path.clone(),
@@ -420,11 +425,11 @@ impl StagedStatusEntry {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct UnstagedStatusEntry {
     file_mode: Option<u32>,
     oids: Option<StatusOids>,
-    path: String,
+    path: LosslessString,
     status: Status,
     submodule_status: Option<SubmoduleStatus>,
 }
@@ -433,7 +438,7 @@ impl UnstagedStatusEntry {
     fn from_ordinary_status_entry(entry: &OrdinaryStatusEntry) -> Option<StatusEntryOutput> {
         entry.unstaged_status.as_ref().map(|status| {
             StatusEntryOutput::Unstaged(Self {
-                file_mode: Some(entry.file_mode.worktree.clone()),
+                file_mode: Some(entry.file_mode.worktree),
                 oids: Some(entry.oids.clone()),
                 path: entry.path.clone(),
                 status: status.clone(),
@@ -447,7 +452,7 @@ impl UnstagedStatusEntry {
     ) -> Option<StatusEntryOutput> {
         entry.unstaged_status.as_ref().map(|status| {
             StatusEntryOutput::Unstaged(Self {
-                file_mode: Some(entry.file_mode.worktree.clone()),
+                file_mode: Some(entry.file_mode.worktree),
                 oids: Some(entry.oids.clone()),
                 path: entry.path.clone(),
                 status: status.clone(),
@@ -474,7 +479,7 @@ pub enum StatusEntryOutput {
     Unstaged(UnstagedStatusEntry),
 }
 
-#[derive(Debug, Default, Serialize)]
+#[derive(Debug, Default, Serialize, JsonSchema)]
 pub struct StatusResult {
     conflicts: Vec<ConflictStatusEntry>,
     ignored: Vec<IgnoredStatusEntry>,
@@ -491,7 +496,7 @@ impl StatusResult {
 pub fn build_git_status_output(entries: Vec<StatusEntry>) -> StatusResult {
     entries
         .iter()
-        .map(|entry| -> Vec<Option<StatusEntryOutput>> {
+        .flat_map(|entry| -> Vec<Option<StatusEntryOutput>> {
             match entry {
                 StatusEntry::OrdinaryStatusEntry(entry) => vec![
                     StagedStatusEntry::from_ordinary_status_entry(entry),
@@ -513,8 +518,7 @@ pub fn build_git_status_output(entries: Vec<StatusEntry>) -> StatusResult {
                 }
             }
         })
-        .flat_map(|entry| entry)
-        .filter_map(|option| option)
+        .flatten()
         .fold(StatusResult::new(), |mut status_result, entry| {
             match entry {
                 StatusEntryOutput::Conflict(conflict) => {
@@ -534,8 +538,140 @@ pub fn build_git_status_output(entries: Vec<StatusEntry>) -> StatusResult {
         })
 }
 
-pub fn parse_git_status(input: &str) -> Result<StatusResult, Error> {
-    parse_status_entries(input)
-        .map_err(|_| Error::Process(Parsing))
-        .map(|(_, vec)| build_git_status_output(vec))
+pub fn parse_git_status(input: &[u8]) -> Result<StatusResult, Error> {
+    match parse_status_entries(input) {
+        Ok(([], entries)) => Ok(build_git_status_output(entries)),
+        _ => Err(Error::Process(Parsing)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_git_status, Score, ScoreType, Status};
+    use util::lossless::LosslessString;
+
+    const SHA_A: &str = "1111111111111111111111111111111111111111";
+    const SHA_B: &str = "2222222222222222222222222222222222222222";
+    const SHA_C: &str = "3333333333333333333333333333333333333333";
+
+    fn entry(fields: &str, paths: &[&[u8]]) -> Vec<u8> {
+        let mut entry = fields.as_bytes().to_vec();
+        for path in paths {
+            entry.extend_from_slice(path);
+            entry.push(b'\0');
+        }
+        entry
+    }
+
+    fn utf8(path: &str) -> LosslessString {
+        LosslessString::Utf8(String::from(path))
+    }
+
+    #[test]
+    fn parses_renamed_and_copied_pairs() {
+        let mut input = entry(
+            &format!("2 R. N... 100644 100644 100644 {} {} R87 ", SHA_A, SHA_B),
+            &[b"new name", b"old name"],
+        );
+        input.extend(entry(
+            &format!("2 CM N... 100644 100644 100644 {} {} C100 ", SHA_A, SHA_A),
+            &[b"copy", b"original"],
+        ));
+
+        let result = parse_git_status(&input).unwrap();
+        assert_eq!(result.staged.len(), 2);
+
+        let renamed = &result.staged[0];
+        assert!(matches!(renamed.status, Status::Renamed));
+        assert_eq!(renamed.path, utf8("new name"));
+        assert_eq!(renamed.original_path, Some(utf8("old name")));
+        assert!(matches!(
+            renamed.score,
+            Some(Score {
+                score_type: ScoreType::Renamed,
+                percentage: 87
+            })
+        ));
+        assert_eq!(renamed.oids.index, SHA_B);
+
+        let copied = &result.staged[1];
+        assert!(matches!(copied.status, Status::Copied));
+        assert_eq!(copied.original_path, Some(utf8("original")));
+
+        // The copy was modified after it was staged.
+        assert_eq!(result.unstaged.len(), 1);
+        assert_eq!(result.unstaged[0].path, utf8("copy"));
+        assert!(matches!(result.unstaged[0].status, Status::Modified));
+    }
+
+    #[test]
+    fn keeps_newlines_tabs_and_spaces_in_names() {
+        let mut input = entry(
+            &format!("1 .M N... 100644 100644 100644 {} {} ", SHA_A, SHA_A),
+            &[b"tab\tand\nnewline"],
+        );
+        input.extend(entry(
+            &format!("2 R. N... 100644 100644 100644 {} {} R100 ", SHA_A, SHA_A),
+            &[b"2 R. looks like an entry", b"\n"],
+        ));
+        input.extend(entry("? ", &[b" leading space"]));
+
+        let result = parse_git_status(&input).unwrap();
+        assert_eq!(result.unstaged[0].path, utf8("tab\tand\nnewline"));
+        assert_eq!(result.staged[0].path, utf8("2 R. looks like an entry"));
+        assert_eq!(result.staged[0].original_path, Some(utf8("\n")));
+        assert_eq!(result.unstaged[1].path, utf8(" leading space"));
+    }
+
+    #[test]
+    fn keeps_names_that_are_not_utf8() {
+        let mut input = entry("? ", &[b"caf\xe9"]);
+        input.extend(entry("! ", &[b"build\xff/"]));
+        input.extend(entry(
+            &format!(
+                "u UU N... 100644 100644 100644 100644 {} {} {} ",
+                SHA_A, SHA_B, SHA_C
+            ),
+            &[b"conflict\xfe"],
+        ));
+
+        let result = parse_git_status(&input).unwrap();
+        match result.unstaged[0].path {
+            LosslessString::Bytes {
+                ref bytes,
+                ref lossy,
+            } => {
+                assert_eq!(&bytes[..], b"caf\xe9");
+                assert_eq!(lossy, "caf\u{fffd}");
+            }
+            ref path => panic!("Decoded {:?} as UTF-8", path),
+        }
+        assert!(matches!(
+            result.ignored[0].path,
+            LosslessString::Bytes { ref bytes, .. } if &bytes[..] == b"build\xff/"
+        ));
+
+        let conflict = &result.conflicts[0];
+        assert!(matches!(
+            conflict.path,
+            LosslessString::Bytes { ref bytes, .. } if &bytes[..] == b"conflict\xfe"
+        ));
+        assert_eq!(
+            (&conflict.ancestor.oid[..], &conflict.our.oid[..], &conflict.their.oid[..]),
+            (SHA_A, SHA_B, SHA_C)
+        );
+    }
+
+    #[test]
+    fn rejects_a_truncated_entry() {
+        let mut input = entry("? ", &[b"untracked"]);
+        input.extend_from_slice(b"? no terminating NUL");
+        assert!(parse_git_status(&input).is_err());
+
+        let input = entry(
+            &format!("2 R. N... 100644 100644 100644 {} {} R100 ", SHA_A, SHA_A),
+            &[b"missing its original path"],
+        );
+        assert!(parse_git_status(&input).is_err());
+    }
 }
@@ -0,0 +1,38 @@
+use super::{read_last_action, ErrorReason, LastAction};
+use futures::Future;
+use state;
+use types::DispatchFuture;
+use util::transport::send_message;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "UndoLastActionOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Success { action: LastAction },
+    Error(ErrorReason),
+}
+
+pub fn dispatch(connection_state: state::Connection) -> DispatchFuture {
+    use self::ErrorReason::{NothingToUndo, RepoPathNotSet};
+
+    match connection_state.repo_path.clone() {
+        Some(repo_path) => Box::new(
+            read_last_action(repo_path)
+                .then(|result| match result {
+                    Ok(action) => Ok((
+                        match action {
+                            Some(action) => OutboundMessage::Success { action },
+                            None => OutboundMessage::Error(NothingToUndo),
+                        },
+                        connection_state,
+                    )),
+                    Err(err) => Err((err, connection_state)),
+                })
+                .and_then(|(message, connection_state)| send_message(connection_state, message)),
+        ),
+        None => Box::new(send_message(
+            connection_state,
+            OutboundMessage::Error(RepoPathNotSet),
+        )),
+    }
+}
@@ -0,0 +1,137 @@
+mod last_action;
+mod restore;
+
+use super::reflog::{read_reflog_until, ReflogEntry};
+use error::protocol::Error;
+use futures::Future;
+use message::protocol::git_command::undo;
+use schema::CommandSchema;
+use schemars::gen::SchemaGenerator;
+use state;
+use std::ffi::OsString;
+use types::DispatchFuture;
+use util::git::{self, RepoPath};
+use util::lossless::LosslessString;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "UndoErrorReason")]
+#[serde(tag = "reason")]
+pub enum ErrorReason {
+    HeadHasMoved,
+    LocalChangesWouldBeLost,
+    NothingToUndo,
+    RepoPathNotSet,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, JsonSchema)]
+pub enum ActionKind {
+    Checkout,
+    Commit,
+    Merge,
+    Other,
+    Rebase,
+    Reset,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct LastAction {
+    current_sha: String,
+    kind: ActionKind,
+    message: LosslessString,
+    previous_branch: Option<String>,
+    previous_sha: String,
+}
+
+fn is_rebase(message: &str) -> bool {
+    message.starts_with("rebase") || message.starts_with("pull --rebase")
+}
+
+fn classify(message: &str) -> ActionKind {
+    if message.starts_with("checkout:") {
+        ActionKind::Checkout
+    } else if message.starts_with("commit") {
+        ActionKind::Commit
+    } else if is_rebase(message) {
+        ActionKind::Rebase
+    } else if message.starts_with("merge") || message.starts_with("pull") {
+        ActionKind::Merge
+    } else if message.starts_with("reset:") {
+        ActionKind::Reset
+    } else {
+        ActionKind::Other
+    }
+}
+
+/// Works out which HEAD position the newest entry of HEAD's reflog moved away from. A rebase
+/// writes several entries, so it is undone back to where its `(start)` entry began.
+fn find_last_action(entries: &[ReflogEntry]) -> Option<LastAction> {
+    let latest = entries.first()?;
+    let kind = classify(latest.message.as_lossy_str());
+
+    let previous_sha = match kind {
+        ActionKind::Rebase => entries
+            .iter()
+            .take_while(|entry| is_rebase(entry.message.as_lossy_str()))
+            .find(|entry| entry.message.as_lossy_str().contains("(start)"))
+            .unwrap_or(latest)
+            .old_sha
+            .clone(),
+        _ => latest.old_sha.clone(),
+    };
+
+    // The first commit of a branch has nothing to go back to.
+    if previous_sha.chars().all(|c| c == '0') {
+        return None;
+    }
+
+    let previous_branch = match kind {
+        ActionKind::Checkout => latest
+            .message
+            .as_lossy_str()
+            .trim_start_matches("checkout: moving from ")
+            .split(" to ")
+            .next()
+            .filter(|from| !git::verify_string_is_sha(from))
+            .map(String::from),
+        _ => None,
+    };
+
+    Some(LastAction {
+        current_sha: latest.new_sha.clone(),
+        kind,
+        message: latest.message.clone(),
+        previous_branch,
+        previous_sha,
+    })
+}
+
+/// Reads the last action from HEAD's reflog, which only needs the newest entry, or for a rebase the
+/// entries back to its start.
+fn read_last_action(repo_path: RepoPath) -> impl Future<Item = Option<LastAction>, Error = Error> {
+    let is_last = |entry: &ReflogEntry| {
+        let message = entry.message.as_lossy_str();
+        !is_rebase(message) || message.contains("(start)")
+    };
+
+    read_reflog_until(repo_path, OsString::from("HEAD"), is_last)
+        .map(|entries| find_last_action(&entries))
+}
+
+pub fn dispatch(connection_state: state::Connection, message: undo::Inbound) -> DispatchFuture {
+    use self::undo::Inbound;
+
+    match message {
+        Inbound::LastAction => last_action::dispatch(connection_state),
+        Inbound::Restore { expected_sha } => restore::dispatch(connection_state, expected_sha),
+    }
+}
+
+pub fn command_schema(generator: &mut SchemaGenerator) -> CommandSchema {
+    CommandSchema::group(
+        "Undo",
+        vec![
+            CommandSchema::new::<last_action::OutboundMessage>("LastAction", generator),
+            CommandSchema::new::<restore::OutboundMessage>("Restore", generator),
+        ],
+    )
+}
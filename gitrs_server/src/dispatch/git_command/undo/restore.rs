@@ -0,0 +1,108 @@
+use super::{read_last_action, ActionKind, ErrorReason, LastAction};
+use futures::{future, Future};
+use state;
+use std::process::Output;
+use tokio_process::CommandExt;
+use types::DispatchFuture;
+use util::git::{self, RepoPath};
+use util::git_error::{classify, GitErrorKind};
+use util::transport::send_message;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "UndoRestoreOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Success { action: LastAction },
+    Error(ErrorReason),
+}
+
+// Commits are undone with `--soft` so their changes are kept staged; every other action uses
+// `--keep`, which refuses to touch files with local changes instead of discarding them.
+fn restore_action(
+    repo_path: &RepoPath,
+    action: &LastAction,
+) -> impl Future<Item = Output, Error = ::error::protocol::Error> {
+    use error::protocol::{Error, ProcessError::Failed};
+
+    let mut command = git::new_command_with_repo_path(repo_path);
+    match (action.kind, &action.previous_branch) {
+        (ActionKind::Checkout, Some(previous_branch)) => {
+            command.arg("checkout").arg(previous_branch).arg("--")
+        }
+        (ActionKind::Checkout, None) => command
+            .arg("checkout")
+            .arg("--detach")
+            .arg(&action.previous_sha),
+        (ActionKind::Commit, _) => command
+            .arg("reset")
+            .arg("--soft")
+            .arg(&action.previous_sha),
+        _ => command
+            .arg("reset")
+            .arg("--keep")
+            .arg(&action.previous_sha),
+    };
+
+    command.output_async().map_err(|_| Error::Process(Failed))
+}
+
+pub fn dispatch(connection_state: state::Connection, expected_sha: String) -> DispatchFuture {
+    use self::ErrorReason::{HeadHasMoved, LocalChangesWouldBeLost, NothingToUndo,
+                            RepoPathNotSet};
+    use error::protocol::{Error, ProcessError::Git};
+
+    match connection_state.repo_path.clone() {
+        Some(repo_path) => Box::new(
+            read_last_action(repo_path.clone())
+                .then(move |result| -> DispatchFuture {
+                    let action = match result {
+                        Ok(Some(action)) => action,
+                        Err(err) => return Box::new(future::err((err, connection_state))),
+                        Ok(None) => {
+                            return Box::new(send_message(
+                                connection_state,
+                                OutboundMessage::Error(NothingToUndo),
+                            ))
+                        }
+                    };
+
+                    // Protects against undoing something other than what the user was shown.
+                    if action.current_sha != expected_sha {
+                        return Box::new(send_message(
+                            connection_state,
+                            OutboundMessage::Error(HeadHasMoved),
+                        ));
+                    }
+
+                    Box::new(
+                        restore_action(&repo_path, &action)
+                            .then(|result| match result {
+                                Ok(output) => if output.status.success() {
+                                    Ok((OutboundMessage::Success { action }, connection_state))
+                                } else {
+                                    let git_error = classify(&output);
+                                    match git_error.kind {
+                                        GitErrorKind::LocalChangesWouldBeLost => Ok((
+                                            OutboundMessage::Error(LocalChangesWouldBeLost),
+                                            connection_state,
+                                        )),
+                                        _ => {
+                                            let err = Error::Process(Git(git_error));
+                                            Err((err, connection_state))
+                                        }
+                                    }
+                                },
+                                Err(err) => Err((err, connection_state)),
+                            })
+                            .and_then(|(message, connection_state)| {
+                                send_message(connection_state, message)
+                            }),
+                    )
+                }),
+        ),
+        None => Box::new(send_message(
+            connection_state,
+            OutboundMessage::Error(RepoPathNotSet),
+        )),
+    }
+}
@@ -0,0 +1,33 @@
+use state;
+use types::DispatchFuture;
+use util::transport::send_message;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "UnwatchErrorReason")]
+#[serde(tag = "reason")]
+pub enum ErrorReason {
+    RepoPathNotSet,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "UnwatchOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Success,
+    Error(ErrorReason),
+}
+
+pub fn dispatch(connection_state: state::Connection) -> DispatchFuture {
+    use self::ErrorReason::RepoPathNotSet;
+
+    match connection_state.repo_path.clone() {
+        Some(repo_path) => {
+            connection_state.unwatch_repo(&repo_path.path);
+            Box::new(send_message(connection_state, OutboundMessage::Success))
+        }
+        None => Box::new(send_message(
+            connection_state,
+            OutboundMessage::Error(RepoPathNotSet),
+        )),
+    }
+}
@@ -0,0 +1,105 @@
+use super::open_repo::{discover, Discovery};
+use error::protocol::{Error, ProcessError::{Failed, Git}};
+use futures::{future, Future};
+use state;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use tokio_process::CommandExt;
+use types::DispatchFuture;
+use util::git::RepoPath;
+use util::git_error::classify;
+use util::transport::send_message;
+use watch::{new_ignored_dirs_command, parse_ignored_dirs, RepoLayout};
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "WatchErrorReason")]
+#[serde(tag = "reason")]
+pub enum ErrorReason {
+    CannotWatchRepo,
+    RepoPathNotSet,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "WatchOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Success,
+    Error(ErrorReason),
+}
+
+// Only the working tree has anything to ignore.
+fn read_layout(
+    repo_path: RepoPath,
+    discovery: Discovery,
+) -> Box<dyn Future<Item = RepoLayout, Error = Error> + Send> {
+    let is_bare = discovery.is_bare;
+    let layout = move |ignored_dirs, workdir| RepoLayout {
+        common_dir: discovery.common_dir,
+        git_dir: discovery.git_dir,
+        ignored_dirs,
+        workdir,
+    };
+
+    if is_bare {
+        return Box::new(future::ok(layout(HashSet::new(), None)));
+    }
+
+    let workdir = PathBuf::from(&repo_path.path);
+    Box::new(
+        new_ignored_dirs_command(&repo_path, None)
+            .output_async()
+            .map_err(|_| Error::Process(Failed))
+            .and_then(move |output| {
+                if !output.status.success() {
+                    return Err(Error::Process(Git(classify(&output))));
+                }
+
+                Ok(layout(parse_ignored_dirs(&workdir, &output.stdout), Some(workdir)))
+            }),
+    )
+}
+
+pub fn dispatch(connection_state: state::Connection) -> DispatchFuture {
+    use self::ErrorReason::{CannotWatchRepo, RepoPathNotSet};
+
+    match connection_state.repo_path.clone() {
+        Some(repo_path) => Box::new(
+            discover(repo_path.path.clone(), repo_path.git_dir.clone())
+                .and_then({
+                    let repo_path = repo_path.clone();
+                    move |discovery| -> Box<dyn Future<Item = _, Error = _> + Send> {
+                        match discovery {
+                            Some(discovery) => {
+                                Box::new(read_layout(repo_path, discovery).map(Some))
+                            }
+                            None => Box::new(future::ok(None)),
+                        }
+                    }
+                })
+                .then(move |result| -> Box<dyn Future<Item = _, Error = _> + Send> {
+                    match result {
+                        // Typically fails when the system's limit on inotify watches is reached.
+                        Ok(Some(layout)) => Box::new(
+                            connection_state.watch_repo(repo_path, layout).then(|result| {
+                                let message = match result {
+                                    Ok(()) => OutboundMessage::Success,
+                                    Err(_) => OutboundMessage::Error(CannotWatchRepo),
+                                };
+                                Ok((message, connection_state))
+                            }),
+                        ),
+                        Ok(None) => Box::new(future::ok((
+                            OutboundMessage::Error(CannotWatchRepo),
+                            connection_state,
+                        ))),
+                        Err(err) => Box::new(future::err((err, connection_state))),
+                    }
+                })
+                .and_then(|(message, connection_state)| send_message(connection_state, message)),
+        ),
+        None => Box::new(send_message(
+            connection_state,
+            OutboundMessage::Error(RepoPathNotSet),
+        )),
+    }
+}
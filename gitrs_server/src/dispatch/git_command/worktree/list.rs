@@ -0,0 +1,75 @@
+use super::parse::{parse_worktree_list, Worktree};
+use super::ErrorReason;
+use error::protocol::{Error, ProcessError::{Failed, Git}};
+use futures::{future, Future};
+use state;
+use std::process::Output;
+use tokio_process::CommandExt;
+use types::DispatchFuture;
+use util::git::{self, RepoPath};
+use util::git_error::classify;
+use util::transport::send_message;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "WorktreeListOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Success { worktrees: Vec<Worktree> },
+    Error(ErrorReason),
+}
+
+fn run_list(repo_path: &RepoPath, terminator: u8) -> impl Future<Item = Output, Error = Error> {
+    let mut command = git::new_command_with_repo_path(repo_path);
+    command.arg("worktree").arg("list").arg("--porcelain");
+    if terminator == b'\0' {
+        command.arg("-z");
+    }
+    command.output_async().map_err(|_| Error::Process(Failed))
+}
+
+// `-z` arrived in git 2.36, and older versions reject it as a usage error. They are asked again
+// without it, which leaves the rare path with a newline in it garbled.
+fn list_worktrees(repo_path: RepoPath) -> impl Future<Item = (Vec<u8>, u8), Error = Error> {
+    run_list(&repo_path, b'\0')
+        .and_then(move |output| -> Box<dyn Future<Item = _, Error = _> + Send> {
+            if output.status.code() == Some(129) {
+                Box::new(run_list(&repo_path, b'\n').map(|output| (output, b'\n')))
+            } else {
+                Box::new(future::ok((output, b'\0')))
+            }
+        })
+        .and_then(|(output, terminator)| {
+            if output.status.success() {
+                Ok((output.stdout, terminator))
+            } else {
+                Err(Error::Process(Git(classify(&output))))
+            }
+        })
+}
+
+pub fn dispatch(connection_state: state::Connection) -> DispatchFuture {
+    use self::ErrorReason::RepoPathNotSet;
+
+    match connection_state.repo_path.clone() {
+        Some(repo_path) => Box::new(
+            list_worktrees(repo_path)
+                .then(|result| match result {
+                    Ok(result) => Ok((result, connection_state)),
+                    Err(err) => Err((err, connection_state)),
+                })
+                .and_then(|((result, terminator), connection_state)| -> DispatchFuture {
+                    match parse_worktree_list(&result, terminator) {
+                        Ok(worktrees) => Box::new(send_message(
+                            connection_state,
+                            OutboundMessage::Success { worktrees },
+                        )),
+                        Err(err) => Box::new(future::err((err, connection_state))),
+                    }
+                }),
+        ),
+        None => Box::new(send_message(
+            connection_state,
+            OutboundMessage::Error(RepoPathNotSet),
+        )),
+    }
+}
@@ -0,0 +1,243 @@
+mod list;
+mod parse;
+mod prune;
+
+use futures::Future;
+use message::protocol::git_command::worktree;
+use schema::CommandSchema;
+use schemars::gen::SchemaGenerator;
+use state;
+use std::path::Path;
+use std::process::Command;
+use tokio_process::CommandExt;
+use types::DispatchFuture;
+use util::git;
+use util::git_error::classify;
+use util::transport::send_message;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "WorktreeErrorReason")]
+#[serde(tag = "reason")]
+pub enum ErrorReason {
+    BranchAlreadyCheckedOut,
+    BranchAlreadyExists,
+    InvalidBranch,
+    MustBeAbsolutePath,
+    NotAWorktree,
+    PathAlreadyExists,
+    RepoPathNotSet,
+    WorktreeHasChanges,
+    WorktreeIsLocked,
+    WorktreeIsNotLocked,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "WorktreeOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Success,
+    Error(ErrorReason),
+}
+
+// `git worktree` exits with 128 for every failure, so the reason has to come from its message.
+fn classify_failure(stderr: &[u8]) -> Option<ErrorReason> {
+    use self::ErrorReason::{BranchAlreadyCheckedOut, BranchAlreadyExists, InvalidBranch,
+                            NotAWorktree, PathAlreadyExists, WorktreeHasChanges,
+                            WorktreeIsLocked, WorktreeIsNotLocked};
+
+    let stderr = String::from_utf8_lossy(stderr);
+    if stderr.contains("is already checked out") || stderr.contains("is already used by worktree")
+    {
+        Some(BranchAlreadyCheckedOut)
+    } else if stderr.contains("a branch named") {
+        Some(BranchAlreadyExists)
+    } else if stderr.contains("invalid reference") {
+        Some(InvalidBranch)
+    } else if stderr.contains("is not a working tree") {
+        Some(NotAWorktree)
+    } else if stderr.contains("already exists") {
+        Some(PathAlreadyExists)
+    } else if stderr.contains("contains modified or untracked files") {
+        Some(WorktreeHasChanges)
+    } else if stderr.contains("locked working tree") || stderr.contains("is already locked") {
+        Some(WorktreeIsLocked)
+    } else if stderr.contains("is not locked") {
+        Some(WorktreeIsNotLocked)
+    } else {
+        None
+    }
+}
+
+fn verify_absolute_path(path: &str) -> Result<(), ErrorReason> {
+    if Path::new(path).is_relative() {
+        Err(ErrorReason::MustBeAbsolutePath)
+    } else {
+        Ok(())
+    }
+}
+
+// Runs a `git worktree` subcommand that has nothing to report besides whether it succeeded.
+fn dispatch_command<F>(
+    connection_state: state::Connection,
+    path: String,
+    build: F,
+) -> DispatchFuture
+where
+    F: FnOnce(&mut Command, String),
+{
+    use self::ErrorReason::RepoPathNotSet;
+    use error::protocol::{Error, ProcessError::{Failed, Git}};
+
+    if let Err(reason) = verify_absolute_path(&path) {
+        return Box::new(send_message(connection_state, OutboundMessage::Error(reason)));
+    }
+
+    match connection_state.repo_path.clone() {
+        Some(repo_path) => {
+            let mut command = git::new_command_with_repo_path(&repo_path);
+            command.arg("worktree");
+            build(&mut command, path);
+
+            Box::new(
+                command
+                    .output_async()
+                    .then(|result| match result {
+                        Ok(output) => if output.status.success() {
+                            Ok((OutboundMessage::Success, connection_state))
+                        } else {
+                            match classify_failure(&output.stderr) {
+                                Some(reason) => {
+                                    Ok((OutboundMessage::Error(reason), connection_state))
+                                }
+                                None => Err((
+                                    Error::Process(Git(classify(&output))),
+                                    connection_state,
+                                )),
+                            }
+                        },
+                        Err(_) => Err((Error::Process(Failed), connection_state)),
+                    })
+                    .and_then(|(message, connection_state)| {
+                        send_message(connection_state, message)
+                    }),
+            )
+        }
+        None => Box::new(send_message(
+            connection_state,
+            OutboundMessage::Error(RepoPathNotSet),
+        )),
+    }
+}
+
+pub fn dispatch(connection_state: state::Connection, message: worktree::Inbound) -> DispatchFuture {
+    use self::worktree::Inbound;
+
+    match message {
+        Inbound::Add {
+            path,
+            branch,
+            new_branch,
+        } => dispatch_command(connection_state, path, |command, path| {
+            command.arg("add");
+            if let Some(new_branch) = new_branch {
+                command.arg("-b").arg(new_branch);
+            }
+            command.arg("--").arg(path);
+            if let Some(branch) = branch {
+                command.arg(branch);
+            }
+        }),
+        Inbound::List => list::dispatch(connection_state),
+        Inbound::Lock { path, reason } => {
+            dispatch_command(connection_state, path, |command, path| {
+                command.arg("lock");
+                if let Some(reason) = reason {
+                    command.arg("--reason").arg(reason);
+                }
+                command.arg("--").arg(path);
+            })
+        }
+        Inbound::Prune { dry_run } => prune::dispatch(connection_state, dry_run),
+        Inbound::Remove { path, force } => {
+            dispatch_command(connection_state, path, |command, path| {
+                command.arg("remove");
+                if force {
+                    command.arg("--force");
+                }
+                command.arg("--").arg(path);
+            })
+        }
+        Inbound::Unlock { path } => {
+            dispatch_command(connection_state, path, |command, path| {
+                command.arg("unlock").arg("--").arg(path);
+            })
+        }
+    }
+}
+
+pub fn command_schema(generator: &mut SchemaGenerator) -> CommandSchema {
+    CommandSchema::group(
+        "Worktree",
+        vec![
+            CommandSchema::new::<OutboundMessage>("Add", generator),
+            CommandSchema::new::<list::OutboundMessage>("List", generator),
+            CommandSchema::new::<OutboundMessage>("Lock", generator),
+            CommandSchema::new::<prune::OutboundMessage>("Prune", generator),
+            CommandSchema::new::<OutboundMessage>("Remove", generator),
+            CommandSchema::new::<OutboundMessage>("Unlock", generator),
+        ],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::classify_failure;
+    use super::ErrorReason::{self, BranchAlreadyCheckedOut, BranchAlreadyExists, InvalidBranch,
+                             NotAWorktree, PathAlreadyExists, WorktreeHasChanges,
+                             WorktreeIsLocked, WorktreeIsNotLocked};
+    use tempfile;
+    use util::test_repo::TestRepo;
+
+    fn reason(repo: &TestRepo, args: &[&str]) -> Option<ErrorReason> {
+        let output = repo.run(args);
+        assert!(!output.status.success(), "git {:?} succeeded", args);
+        classify_failure(&output.stderr)
+    }
+
+    macro_rules! assert_reason {
+        ($repo:expr, $args:expr, $reason:pat) => {
+            match reason(&$repo, $args) {
+                Some($reason) => {}
+                other => panic!("git {:?} was classified as {:?}", $args, other),
+            }
+        };
+    }
+
+    #[test]
+    fn classifies_worktree_failures() {
+        let repo = TestRepo::new();
+        repo.commit("initial");
+        repo.git(&["branch", "other"]);
+        let parent = tempfile::tempdir().unwrap();
+        let worktree = parent.path().join("worktree");
+        let worktree = worktree.to_str().unwrap();
+        let unknown = parent.path().join("unknown");
+        let unknown = unknown.to_str().unwrap();
+
+        assert_reason!(repo, &["worktree", "add", unknown, "master"], BranchAlreadyCheckedOut);
+        assert_reason!(repo, &["worktree", "add", "-b", "other", unknown], BranchAlreadyExists);
+        assert_reason!(repo, &["worktree", "add", unknown, "no-such-branch"], InvalidBranch);
+        assert_reason!(repo, &["worktree", "lock", unknown], NotAWorktree);
+
+        repo.git(&["worktree", "add", worktree, "other"]);
+        assert_reason!(repo, &["worktree", "add", "--detach", worktree], PathAlreadyExists);
+        assert_reason!(repo, &["worktree", "unlock", worktree], WorktreeIsNotLocked);
+
+        ::std::fs::write(parent.path().join("worktree/untracked"), "").unwrap();
+        assert_reason!(repo, &["worktree", "remove", worktree], WorktreeHasChanges);
+
+        repo.git(&["worktree", "lock", worktree]);
+        assert_reason!(repo, &["worktree", "lock", worktree], WorktreeIsLocked);
+        assert_reason!(repo, &["worktree", "remove", "--force", worktree], WorktreeIsLocked);
+    }
+}
@@ -0,0 +1,266 @@
+use error::protocol::{Error, ProcessError::Parsing};
+use util::lossless::LosslessString;
+use util::parse::sha_bytes;
+
+#[derive(Debug, Default, Serialize, JsonSchema)]
+pub struct Worktree {
+    branch: Option<LosslessString>,
+    head: Option<String>,
+    is_bare: bool,
+    is_detached: bool,
+    is_locked: bool,
+    is_prunable: bool,
+    lock_reason: Option<LosslessString>,
+    path: LosslessString,
+    prune_reason: Option<LosslessString>,
+}
+
+enum Attribute<'a> {
+    Bare,
+    Branch(&'a [u8]),
+    Detached,
+    Head(&'a str),
+    Locked(Option<&'a [u8]>),
+    Prunable(Option<&'a [u8]>),
+    Unknown,
+}
+
+// Each line ends with the terminator, which is a NUL with `-z`. Without it a path that contains a
+// newline cannot be told apart from the attributes that follow it.
+named_args!(parse_line(terminator: u8)<&[u8], &[u8]>,
+    do_parse!(
+        line: take_till!(|c| c == terminator) >>
+        char!(terminator as char) >>
+        (line)
+    )
+);
+
+named_args!(parse_reason(terminator: u8)<&[u8], Option<&[u8]>>,
+    alt!(
+        do_parse!(char!(terminator as char) >> (None)) |
+        do_parse!(char!(' ') >> reason: call!(parse_line, terminator) >> (Some(reason)))
+    )
+);
+
+named_args!(parse_attribute(terminator: u8)<&[u8], Attribute<'_>>,
+    alt!(
+        do_parse!(
+            tag!("HEAD ") >>
+            head: sha_bytes >>
+            char!(terminator as char) >>
+            (Attribute::Head(head))
+        ) |
+        do_parse!(
+            tag!("branch ") >>
+            branch: call!(parse_line, terminator) >>
+            (Attribute::Branch(branch))
+        ) |
+        do_parse!(tag!("detached") >> char!(terminator as char) >> (Attribute::Detached)) |
+        do_parse!(tag!("bare") >> char!(terminator as char) >> (Attribute::Bare)) |
+        do_parse!(
+            tag!("locked") >>
+            reason: call!(parse_reason, terminator) >>
+            (Attribute::Locked(reason))
+        ) |
+        do_parse!(
+            tag!("prunable") >>
+            reason: call!(parse_reason, terminator) >>
+            (Attribute::Prunable(reason))
+        ) |
+        // Attributes added by newer versions of git are skipped.
+        do_parse!(
+            verify!(call!(parse_line, terminator), |line: &[u8]| !line.is_empty()) >>
+            (Attribute::Unknown)
+        )
+    )
+);
+
+named_args!(parse_worktree(terminator: u8)<&[u8], Worktree>,
+    do_parse!(
+        tag!("worktree ") >>
+        path: call!(parse_line, terminator) >>
+        attributes: many0!(complete!(call!(parse_attribute, terminator))) >>
+        opt!(complete!(char!(terminator as char))) >>
+        ({
+            let mut worktree = Worktree {
+                path: LosslessString::from(path),
+                ..Default::default()
+            };
+            for attribute in attributes {
+                match attribute {
+                    Attribute::Bare => worktree.is_bare = true,
+                    Attribute::Branch(branch) => {
+                        worktree.branch = Some(LosslessString::from(branch))
+                    }
+                    Attribute::Detached => worktree.is_detached = true,
+                    Attribute::Head(head) => worktree.head = Some(String::from(head)),
+                    Attribute::Locked(reason) => {
+                        worktree.is_locked = true;
+                        worktree.lock_reason = reason.map(LosslessString::from);
+                    }
+                    Attribute::Prunable(reason) => {
+                        worktree.is_prunable = true;
+                        worktree.prune_reason = reason.map(LosslessString::from);
+                    }
+                    Attribute::Unknown => {}
+                }
+            }
+            worktree
+        })
+    )
+);
+
+named_args!(parse_worktrees(terminator: u8)<&[u8], Vec<Worktree>>,
+    many0!(complete!(call!(parse_worktree, terminator)))
+);
+
+/// Parses `git worktree list --porcelain`, whose lines end with `terminator`.
+pub fn parse_worktree_list(input: &[u8], terminator: u8) -> Result<Vec<Worktree>, Error> {
+    match parse_worktrees(input, terminator) {
+        Ok(([], worktrees)) => Ok(worktrees),
+        _ => Err(Error::Process(Parsing)),
+    }
+}
+
+pub fn parse_pruned_worktrees(input: &str) -> Vec<String> {
+    input
+        .lines()
+        .filter(|line| line.starts_with("Removing "))
+        .filter_map(|line| {
+            line["Removing ".len()..]
+                .split(": ")
+                .next()
+                .map(|name| String::from(name.trim_start_matches("worktrees/")))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_pruned_worktrees, parse_worktree_list};
+    use util::lossless::LosslessString;
+
+    const SHA: &str = "7852dc982b188a831702b1896d544943ccba2fde";
+
+    fn utf8(text: &str) -> LosslessString {
+        LosslessString::Utf8(String::from(text))
+    }
+
+    fn record(lines: &[&[u8]], terminator: u8) -> Vec<u8> {
+        let mut record = Vec::new();
+        for line in lines {
+            record.extend_from_slice(line);
+            record.push(terminator);
+        }
+        record.push(terminator);
+        record
+    }
+
+    #[test]
+    fn parses_every_attribute() {
+        let head = format!("HEAD {}", SHA);
+        let mut input = record(
+            &[b"worktree /repo", head.as_bytes(), b"branch refs/heads/main"],
+            b'\0',
+        );
+        input.extend(record(
+            &[
+                b"worktree /linked",
+                head.as_bytes(),
+                b"detached",
+                b"locked",
+                b"prunable gitdir file points to non-existent location",
+                b"added-in-a-later-version yes",
+            ],
+            b'\0',
+        ));
+        input.extend(record(&[b"worktree /bare.git", b"bare"], b'\0'));
+
+        let worktrees = parse_worktree_list(&input, b'\0').unwrap();
+        assert_eq!(worktrees.len(), 3);
+
+        assert_eq!(worktrees[0].path, utf8("/repo"));
+        assert_eq!(worktrees[0].head.as_deref(), Some(SHA));
+        assert_eq!(worktrees[0].branch, Some(utf8("refs/heads/main")));
+
+        let linked = &worktrees[1];
+        assert!(linked.is_detached && linked.is_locked && linked.is_prunable);
+        assert_eq!(linked.lock_reason, None);
+        assert_eq!(
+            linked.prune_reason,
+            Some(utf8("gitdir file points to non-existent location"))
+        );
+
+        assert!(worktrees[2].is_bare);
+        assert_eq!(worktrees[2].head, None);
+    }
+
+    #[test]
+    fn keeps_newlines_and_tabs_in_nul_terminated_output() {
+        let head = format!("HEAD {}", SHA);
+        let input = record(
+            &[
+                b"worktree /tmp/line\nbreak\tand tab",
+                head.as_bytes(),
+                b"detached",
+                b"locked moved to\nanother disk",
+            ],
+            b'\0',
+        );
+
+        let worktrees = parse_worktree_list(&input, b'\0').unwrap();
+        assert_eq!(worktrees[0].path, utf8("/tmp/line\nbreak\tand tab"));
+        assert_eq!(worktrees[0].lock_reason, Some(utf8("moved to\nanother disk")));
+        assert!(worktrees[0].is_detached);
+    }
+
+    #[test]
+    fn parses_newline_terminated_output() {
+        let head = format!("HEAD {}", SHA);
+        let mut input = record(
+            &[b"worktree /repo", head.as_bytes(), b"branch refs/heads/main"],
+            b'\n',
+        );
+        input.extend(record(&[b"worktree /linked", head.as_bytes(), b"locked by me"], b'\n'));
+
+        let worktrees = parse_worktree_list(&input, b'\n').unwrap();
+        assert_eq!(worktrees.len(), 2);
+        assert_eq!(worktrees[1].path, utf8("/linked"));
+        assert_eq!(worktrees[1].lock_reason, Some(utf8("by me")));
+    }
+
+    #[test]
+    fn keeps_names_that_are_not_utf8() {
+        let head = format!("HEAD {}", SHA);
+        let input = record(
+            &[b"worktree /tmp/caf\xe9", head.as_bytes(), b"branch refs/heads/caf\xe9"],
+            b'\0',
+        );
+
+        let worktrees = parse_worktree_list(&input, b'\0').unwrap();
+        assert!(matches!(
+            worktrees[0].path,
+            LosslessString::Bytes { ref bytes, .. } if &bytes[..] == b"/tmp/caf\xe9"
+        ));
+        assert!(matches!(
+            worktrees[0].branch,
+            Some(LosslessString::Bytes { ref bytes, .. }) if &bytes[..] == b"refs/heads/caf\xe9"
+        ));
+    }
+
+    #[test]
+    fn rejects_a_truncated_record() {
+        let input = format!("worktree /repo\0HEAD {}", SHA);
+        assert!(parse_worktree_list(input.as_bytes(), b'\0').is_err());
+    }
+
+    #[test]
+    fn lists_pruned_worktrees() {
+        let input = "Removing worktrees/gone: gitdir file points to non-existent location\n\
+                     Removing worktrees/also: not a valid directory\n";
+        assert_eq!(
+            parse_pruned_worktrees(input),
+            vec![String::from("gone"), String::from("also")]
+        );
+    }
+}
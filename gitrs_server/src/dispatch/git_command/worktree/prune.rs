@@ -0,0 +1,54 @@
+use super::parse::parse_pruned_worktrees;
+use super::ErrorReason;
+use futures::{future, Future};
+use state;
+use tokio_process::CommandExt;
+use types::DispatchFuture;
+use util::git;
+use util::git_error::classify;
+use util::transport::send_message;
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[schemars(rename = "WorktreePruneOutbound")]
+#[serde(tag = "type")]
+pub enum OutboundMessage {
+    Success { pruned: Vec<String> },
+    Error(ErrorReason),
+}
+
+pub fn dispatch(connection_state: state::Connection, dry_run: bool) -> DispatchFuture {
+    use self::ErrorReason::RepoPathNotSet;
+    use error::protocol::{Error, ProcessError::{Failed, Git}};
+
+    match connection_state.repo_path.clone() {
+        Some(repo_path) => {
+            let mut command = git::new_command_with_repo_path(&repo_path);
+            command.arg("worktree").arg("prune").arg("--verbose");
+            if dry_run {
+                command.arg("--dry-run");
+            }
+
+            // The worktrees being removed are only reported on stderr.
+            Box::new(
+                command
+                    .output_async()
+                    .then(|result| match result {
+                        Ok(output) => if output.status.success() {
+                            let output = String::from_utf8_lossy(&output.stderr);
+                            future::ok((parse_pruned_worktrees(&output), connection_state))
+                        } else {
+                            future::err((Error::Process(Git(classify(&output))), connection_state))
+                        },
+                        Err(_) => future::err((Error::Process(Failed), connection_state)),
+                    })
+                    .and_then(|(pruned, connection_state)| {
+                        send_message(connection_state, OutboundMessage::Success { pruned })
+                    }),
+            )
+        }
+        None => Box::new(send_message(
+            connection_state,
+            OutboundMessage::Error(RepoPathNotSet),
+        )),
+    }
+}
@@ -0,0 +1,152 @@
+use config;
+use error::protocol::{Error, InboundMessageError};
+use futures::future::{self, Either, Future};
+use message::protocol::{Capability, Inbound, Outbound};
+use semver::Version;
+use state;
+use tokio_process::CommandExt;
+use types::DispatchFuture;
+use util::git;
+use util::transport::{read_message, send_error, send_message};
+
+/// The protocol versions the server speaks, oldest first.
+const PROTOCOL_VERSIONS: &[(u64, u64, u64)] = &[(0, 1, 0)];
+
+/// The capabilities the server supports, whether or not the client asks for them.
+const CAPABILITIES: &[Capability] = &[Capability::RequestIds];
+
+fn protocol_versions() -> Vec<Version> {
+    PROTOCOL_VERSIONS
+        .iter()
+        .map(|&(major, minor, patch)| Version::new(major, minor, patch))
+        .collect()
+}
+
+// The newest version both sides speak.
+fn negotiate_version(min_version: &Version, max_version: &Version) -> Result<Version, Error> {
+    let versions = protocol_versions();
+    versions
+        .iter()
+        .rev()
+        .find(|version| min_version <= *version && *version <= max_version)
+        .cloned()
+        .ok_or_else(|| {
+            Error::InboundMessage(InboundMessageError::IncompatibleVersion(
+                versions[0].clone(),
+                versions[versions.len() - 1].clone(),
+            ))
+        })
+}
+
+fn negotiate_capabilities(requested: &[Capability]) -> Vec<Capability> {
+    CAPABILITIES
+        .iter()
+        .filter(|capability| requested.contains(capability))
+        .cloned()
+        .collect()
+}
+
+// Reported for the client's benefit only, so failing to run git does not fail the handshake.
+fn read_git_version() -> impl Future<Item = Option<String>, Error = ()> {
+    git::new_command()
+        .arg("--version")
+        .output_async()
+        .map(|output| {
+            if output.status.success() {
+                let version = String::from_utf8_lossy(&output.stdout);
+                let version = version.trim();
+                Some(String::from(version.trim_start_matches("git version ")))
+            } else {
+                None
+            }
+        })
+        .or_else(|_| Ok(None))
+}
+
+/// Greets the client and agrees on the protocol version and capabilities to use for the rest of
+/// the connection. When the server has a token, the client has to present it first. A failed
+/// handshake is explained to the client before the error is passed on, except for a client that
+/// failed to authenticate, which is only told goodbye.
+pub fn handshake(connection_state: state::Connection) -> DispatchFuture {
+    let versions = protocol_versions();
+
+    Box::new(
+        send_message(
+            connection_state,
+            Outbound::Hello {
+                min_version: versions[0].clone(),
+                version: versions[versions.len() - 1].clone(),
+            },
+        ).and_then(|connection_state| {
+            debug!({
+                println!("wrote hello message");
+            });
+            read_message(connection_state)
+        })
+            .and_then(|(message, connection_state)| -> Box<dyn Future<Item = _, Error = _> + Send> {
+                match message {
+                    Inbound::Hello {
+                        capabilities,
+                        max_version,
+                        min_version,
+                        token,
+                    } => {
+                        let expected_token = config::CONFIG.read().unwrap().token.clone();
+                        let is_authenticated = match expected_token {
+                            Some(expected_token) => Either::A(
+                                connection_state.authenticate(&expected_token, token.as_deref()),
+                            ),
+                            None => Either::B(future::ok(true)),
+                        };
+
+                        Box::new(is_authenticated.then(move |is_authenticated| {
+                            if is_authenticated != Ok(true) {
+                                return Err((Error::Unauthorized, connection_state));
+                            }
+
+                            match negotiate_version(&min_version, &max_version) {
+                                Ok(version) => Ok((
+                                    version,
+                                    negotiate_capabilities(&capabilities),
+                                    connection_state,
+                                )),
+                                Err(err) => Err((err, connection_state)),
+                            }
+                        }))
+                    }
+                    _ => Box::new(future::err((
+                        Error::InboundMessage(InboundMessageError::Unexpected),
+                        connection_state,
+                    ))),
+                }
+            })
+            .and_then(|(version, capabilities, mut connection_state)| {
+                connection_state.capabilities = capabilities.clone();
+                connection_state.protocol_version = Some(version.clone());
+                let connection = connection_state.id();
+                read_git_version()
+                    .then(move |git_version| {
+                        send_message(
+                            connection_state,
+                            Outbound::GladToMeetYou {
+                                capabilities,
+                                connection,
+                                git_version: git_version.unwrap_or(None),
+                                server_version: String::from(env!("CARGO_PKG_VERSION")),
+                                version,
+                            },
+                        )
+                    })
+            })
+            .or_else(|(err, connection_state)| -> DispatchFuture {
+                if err.is_recoverable() {
+                    Box::new(
+                        send_error(connection_state, &err, None)
+                            .and_then(move |connection_state| Err((err, connection_state))),
+                    )
+                } else {
+                    Box::new(future::err((err, connection_state)))
+                }
+            }),
+    )
+}
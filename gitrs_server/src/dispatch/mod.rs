@@ -1,76 +1,203 @@
+mod capabilities;
+#[allow(clippy::module_inception)]
 mod dispatch;
 mod git_command;
+mod handshake;
+
+pub use self::git_command::{command_schemas, OutboundMessage as GitCommandOutbound};
 
 use self::dispatch::dispatch;
+use self::git_command::reads_from_client;
+use self::handshake::handshake;
 use config;
 use futures::future;
-use futures::future::{loop_fn, Future, Loop};
-use semver::Version;
+use futures::future::{loop_fn, Either, Future, Loop};
 use state;
+use std::net::IpAddr;
 use std::sync::{Arc, Mutex};
 use tokio;
-use tokio_io::codec::length_delimited::Builder;
-use tokio::net::TcpStream;
-use util::transport::{read_message, send_message, Transport};
-
-macro_rules! read_validated_message {
-    ($messagePattern:pat, $connection_state:expr) => {
-        read_message($connection_state).and_then(|(response, connection_state)| {
-            use error::protocol::{Error, InboundMessageError};
-
-            match response {
-                $messagePattern => Ok((response, connection_state)),
-                _ => Err((
-                    Error::InboundMessage(InboundMessageError::Unexpected),
-                    connection_state,
-                )),
-            }
-        })
-    };
-}
+use types::DispatchFuture;
+use util::transport::{hang_up, read_message, send_error, send_message, Transport};
 
-pub fn init_dispatch(state: Arc<Mutex<state::Shared>>, socket: TcpStream) {
+/// Serves a client over whichever transport it connected with, until either side hangs up.
+pub fn init_dispatch(state: Arc<Mutex<state::Shared>>, transport: Transport, peer: Option<IpAddr>) {
     use message::protocol::{Inbound, Outbound};
-    let transport: Transport = Builder::new()
-        // Frame header size + max size addressable size of unsigned 32 bit int
-        .max_frame_length(4 + (u32::max_value() as usize))
-        .new_framed(socket);
-    let connection_state = state::Connection::new(state, transport);
-
-    let connection = send_message(
-        connection_state,
-        Outbound::Hello {
-            version: Version::new(0, 1, 0),
-        },
-    ).and_then(|connection_state| {
-        debug!({
-            println!("wrote hello message");
-        });
-        read_validated_message!(Inbound::Hello, connection_state)
-    })
-        .and_then(|(_, connection_state)| send_message(connection_state, Outbound::GladToMeetYou))
+    let connection_state = state::Connection::new(state, transport, peer);
+
+    let connection = handshake(connection_state)
         .and_then(|connection_state| {
             loop_fn(connection_state, |connection_state| {
                 read_message(connection_state).and_then(
                     |(response, connection_state)| -> Box<
-                        Future<
-                                Item = Loop<state::Connection, state::Connection>,
+                        dyn Future<
+                                Item = Loop<Option<state::Connection>, state::Connection>,
                                 Error = (::error::protocol::Error, state::Connection),
                             >
                             + Send,
                     > {
-                        if let Inbound::Goodbye = response {
-                            Box::new(future::ok(Loop::Break(connection_state)))
-                        } else {
-                            Box::new(dispatch(connection_state, response).map(Loop::Continue))
+                        let (request_id, hung_up) = match response {
+                            Inbound::GitCommand(ref request) => (
+                                request.request_id.clone(),
+                                if reads_from_client(&request.command) {
+                                    None
+                                } else {
+                                    Some(hang_up(connection_state.receiver.clone()))
+                                },
+                            ),
+                            Inbound::Goodbye => {
+                                return Box::new(future::ok(Loop::Break(Some(connection_state))))
+                            }
+                            Inbound::Hello { .. } | Inbound::ListCapabilities => (None, None),
+                        };
+
+                        let response = dispatch(connection_state, response).or_else(
+                            move |(err, connection_state)| -> DispatchFuture {
+                                if err.is_recoverable() {
+                                    send_error(connection_state, &err, request_id)
+                                } else {
+                                    Box::new(future::err((err, connection_state)))
+                                }
+                            },
+                        );
+                        match hung_up {
+                            // Nobody is left to answer once the client hung up, so the command is
+                            // dropped along with the connection, which kills the git it runs.
+                            Some(hung_up) => Box::new(response.select2(hung_up).then(
+                                |result| match result {
+                                    Ok(Either::A((connection_state, _))) => {
+                                        Ok(Loop::Continue(connection_state))
+                                    }
+                                    Err(Either::A(((err, connection_state), _))) => {
+                                        Err((err, connection_state))
+                                    }
+                                    Ok(Either::B(_)) | Err(Either::B(_)) => Ok(Loop::Break(None)),
+                                },
+                            )),
+                            None => Box::new(response.map(Loop::Continue)),
                         }
                     },
                 )
             })
         })
-        .and_then(|transport| send_message(transport, Outbound::Goodbye { error_code: None }))
-        .and_then(|_| Ok(()))
+        .then(|result| -> Box<dyn Future<Item = (), Error = _> + Send> {
+            use error::protocol::{Error, TcpReceiveError};
+
+            match result {
+                Ok(None) => Box::new(future::ok(())),
+                // A client that only shut down its writing half is still owed a goodbye, one that
+                // hung up altogether will not get it.
+                Ok(Some(connection_state))
+                | Err((Error::TcpReceive(TcpReceiveError::Eof), connection_state)) => Box::new(
+                    send_message(connection_state, Outbound::Goodbye { error_code: None })
+                        .map(|_| ()),
+                ),
+                // The transport may be too broken to say goodbye on, in which case the connection
+                // just ends.
+                Err((err, connection_state)) => {
+                    debug!({ println!("error; err={:?}", err) });
+                    Box::new(
+                        send_message(
+                            connection_state,
+                            Outbound::Goodbye {
+                                error_code: Some(err.code()),
+                            },
+                        ).map(|_| ()),
+                    )
+                }
+            }
+        })
         .map_err(|(err, _connection_state)| debug!({ println!("error; err={:?}", err) }));
 
+    // However the connection ends, dropping it tears down its state.
     tokio::spawn(connection);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::init_dispatch;
+    use futures::{Future, Stream};
+    use serde_json::{self, json, Value};
+    use state::Shared;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tokio::net::TcpListener;
+    use tokio::runtime::Runtime;
+    use util::test_repo::TestRepo;
+    use util::transport::length_delimited;
+
+    fn send(client: &mut TcpStream, message: &Value) {
+        let message = serde_json::to_vec(message).unwrap();
+        client.write_all(&(message.len() as u32).to_be_bytes()).unwrap();
+        client.write_all(&message).unwrap();
+    }
+
+    fn receive(client: &mut TcpStream) -> Value {
+        let mut length = [0; 4];
+        client.read_exact(&mut length).unwrap();
+        let mut message = vec![0; u32::from_be_bytes(length) as usize];
+        client.read_exact(&mut message).unwrap();
+        serde_json::from_slice(&message).unwrap()
+    }
+
+    fn wait_until<F: Fn() -> bool>(timeout: Duration, is_done: F) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !is_done() {
+            if Instant::now() > deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        true
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn kills_git_when_the_client_hangs_up_during_a_command() {
+        use libc;
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        // `git status` waits for the file system monitor, which takes far longer than the test.
+        let repo = TestRepo::new();
+        let pid_file = repo.join(".git/fsmonitor.pid");
+        let hook = repo.join(".git/fsmonitor");
+        fs::write(&hook, format!("#!/bin/sh\necho $PPID > '{}'\nexec sleep 10\n", pid_file))
+            .unwrap();
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+        repo.git(&["config", "core.fsmonitor", &hook]);
+
+        let mut runtime = Runtime::new().unwrap();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(Shared::new()));
+        runtime.spawn(listener.incoming().take(1).map_err(|_| ()).for_each(move |socket| {
+            init_dispatch(state.clone(), length_delimited(socket), None);
+            Ok(())
+        }));
+
+        let mut client = TcpStream::connect(address).unwrap();
+        receive(&mut client);
+        send(&mut client, &json!({ "type": "Hello" }));
+        assert_eq!(receive(&mut client)["type"], "GladToMeetYou");
+        send(&mut client, &json!({ "type": "GitCommand", "OpenRepo": { "path": repo.path() } }));
+        assert_eq!(receive(&mut client)["type"], "Success");
+        send(&mut client, &json!({ "type": "GitCommand", "Status": null }));
+
+        let read_pid = || {
+            let pid = fs::read_to_string(&pid_file).ok()?;
+            pid.trim().parse().ok()
+        };
+        assert!(wait_until(Duration::from_secs(5), || read_pid().is_some()));
+        let pid: libc::pid_t = read_pid().unwrap();
+        drop(client);
+
+        // Git runs as a child of the test, so whether it has exited can be asked of it directly.
+        // Should the server have reaped it already, there is nothing left to ask about.
+        let is_gone = || unsafe { libc::waitpid(pid, &mut 0, libc::WNOHANG) } != 0;
+        assert!(wait_until(Duration::from_secs(5), is_gone), "git kept running");
+        runtime.shutdown_now().wait().unwrap();
+    }
+}
@@ -1,10 +1,20 @@
 pub mod protocol {
+    use semver::Version;
     use std::str;
+    use util::git_error::GitError;
 
-    #[derive(Debug, Serialize)]
-    #[serde(tag = "type", content = "message")]
+    /// What went wrong, as told to the client in an `Error` frame or a `Goodbye`.
+    #[derive(Clone, Copy, Debug, Serialize, JsonSchema)]
     pub enum ErrorCode {
-        BadRequest(String),
+        BadRequest,
+        GitFailed,
+        IncompatibleVersion,
+        InvalidJson,
+        ProcessFailed,
+        TransportFailed,
+        Unauthorized,
+        UnexpectedMessage,
+        UnparsableOutput,
     }
 
     #[derive(Debug)]
@@ -23,18 +33,23 @@ pub mod protocol {
 
     #[derive(Debug)]
     pub enum TcpReceiveError {
+        /// The client closed its end of the connection, whether or not it is still reading.
+        Eof,
         Io,
     }
 
     #[derive(Debug)]
     pub enum InboundMessageError {
+        /// The client speaks none of the protocol versions the server does, which range from the
+        /// first version to the second.
+        IncompatibleVersion(Version, Version),
         Unexpected,
     }
 
     #[derive(Debug)]
     pub enum ProcessError {
-        Encoding,
         Failed,
+        Git(GitError),
         Parsing,
     }
 
@@ -50,18 +65,83 @@ pub mod protocol {
         }
     }
 
+    /// Deserialization errors keep the message serde gave, since it points at what was wrong with
+    /// the request.
     #[derive(Debug)]
     pub enum Error {
-        Deserialization(DeserializationError),
+        Deserialization(DeserializationError, String),
         InboundMessage(InboundMessageError),
         Process(ProcessError),
         TcpReceive(TcpReceiveError),
         TcpSend(TcpSendError),
+        Unauthorized,
+    }
+
+    impl Error {
+        /// Only a broken transport or a client that failed to authenticate ends the connection.
+        /// Everything else is reported and the connection carries on.
+        pub fn is_recoverable(&self) -> bool {
+            !matches!(
+                *self,
+                Error::TcpReceive(_) | Error::TcpSend(_) | Error::Unauthorized
+            )
+        }
+
+        pub fn code(&self) -> ErrorCode {
+            match *self {
+                Error::Deserialization(DeserializationError::Data, _) => ErrorCode::BadRequest,
+                Error::Deserialization(_, _) => ErrorCode::InvalidJson,
+                Error::InboundMessage(InboundMessageError::IncompatibleVersion(_, _)) => {
+                    ErrorCode::IncompatibleVersion
+                }
+                Error::InboundMessage(InboundMessageError::Unexpected) => {
+                    ErrorCode::UnexpectedMessage
+                }
+                Error::Process(ProcessError::Failed) => ErrorCode::ProcessFailed,
+                Error::Process(ProcessError::Git(_)) => ErrorCode::GitFailed,
+                Error::Process(ProcessError::Parsing) => ErrorCode::UnparsableOutput,
+                Error::TcpReceive(_) | Error::TcpSend(_) => ErrorCode::TransportFailed,
+                Error::Unauthorized => ErrorCode::Unauthorized,
+            }
+        }
+
+        pub fn git_error(&self) -> Option<GitError> {
+            match *self {
+                Error::Process(ProcessError::Git(ref git_error)) => Some(git_error.clone()),
+                _ => None,
+            }
+        }
+
+        pub fn message(&self) -> String {
+            match *self {
+                Error::Deserialization(_, ref message) => message.clone(),
+                Error::InboundMessage(InboundMessageError::IncompatibleVersion(
+                    ref min_version,
+                    ref max_version,
+                )) => format!(
+                    "The server speaks protocol versions {} through {}",
+                    min_version, max_version
+                ),
+                Error::InboundMessage(InboundMessageError::Unexpected) => {
+                    String::from("The message was not expected at this point")
+                }
+                Error::Process(ProcessError::Failed) => String::from("Could not run git"),
+                Error::Process(ProcessError::Git(ref git_error)) => {
+                    String::from(git_error.stderr.as_lossy_str().trim())
+                }
+                Error::Process(ProcessError::Parsing) => {
+                    String::from("Could not make sense of the output of git")
+                }
+                Error::TcpReceive(_) => String::from("Could not read from the connection"),
+                Error::Unauthorized => String::from("The token is missing or wrong"),
+                Error::TcpSend(_) => String::from("Could not write to the connection"),
+            }
+        }
     }
 
     impl From<str::Utf8Error> for Error {
-        fn from(_error: str::Utf8Error) -> Self {
-            Error::Deserialization(DeserializationError::Encoding)
+        fn from(error: str::Utf8Error) -> Self {
+            Error::Deserialization(DeserializationError::Encoding, error.to_string())
         }
     }
 
@@ -69,12 +149,13 @@ pub mod protocol {
         fn from(error: ::serde_json::error::Error) -> Self {
             use serde_json::error::Category;
 
-            Error::Deserialization(match error.classify() {
+            let kind = match error.classify() {
                 Category::Io => DeserializationError::Io,
                 Category::Syntax => DeserializationError::Syntax,
                 Category::Data => DeserializationError::Data,
                 Category::Eof => DeserializationError::Eof,
-            })
+            };
+            Error::Deserialization(kind, error.to_string())
         }
     }
 }
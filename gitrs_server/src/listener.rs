@@ -0,0 +1,235 @@
+use auth;
+use config;
+use constants;
+use dispatch::init_dispatch;
+use futures::future;
+use rustls::ServerConfig;
+use state::Shared;
+use std::net::{IpAddr, SocketAddr};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio;
+use tokio::net::TcpListener;
+use tokio::prelude::*;
+use tokio::timer::Interval;
+use util::transport::{self, ClientStream};
+use util::{tls, websocket};
+
+/// Accepts connections until the process is killed.
+pub type Server = Box<dyn Future<Item = (), Error = ()> + Send>;
+
+// The TLS configuration, if the server was given a certificate to terminate TLS with.
+fn tls_config() -> Option<Arc<ServerConfig>> {
+    let config = config::CONFIG.read().unwrap();
+    let cert_path = config.tls_cert_path.as_ref()?;
+    let key_path = config.tls_key_path.as_ref()?;
+
+    match tls::server_config(
+        cert_path,
+        key_path,
+        config.tls_client_ca_path.as_deref(),
+    ) {
+        Ok(tls_config) => Some(tls_config),
+        // The server would otherwise exit without a word on why.
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(constants::exit_code::EINVAL);
+        }
+    }
+}
+
+/// How messages are framed on the sockets a listener accepts.
+#[derive(Clone, Copy)]
+enum Framing {
+    LengthDelimited,
+    WebSocket,
+}
+
+// Binds to the address, printing the port the listener ended up on.
+fn bind_tcp(address: &SocketAddr) -> TcpListener {
+    let listener = TcpListener::bind(address).unwrap_or_else(|_| {
+        debug!({
+            println!("TCP listener could not be bound to {}!", address);
+        });
+        process::exit(constants::exit_code::EADDRINUSE);
+    });
+
+    let port = listener
+        .local_addr()
+        .map(|address| address.port())
+        .unwrap_or_else(|_| process::exit(constants::exit_code::EFAULT));
+    println!("{:?}", port);
+
+    listener
+}
+
+// Failed attempts to authenticate are kept per peer, which only TCP clients are told apart by.
+fn expire_failed_attempts(state: Arc<Mutex<Shared>>) -> Server {
+    Box::new(
+        Interval::new_interval(Duration::from_secs(auth::EXPIRY_INTERVAL_SECS))
+            .for_each(move |_| {
+                state
+                    .lock()
+                    .expect("Could not lock the shared state!")
+                    .expire_failed_attempts();
+                Ok(())
+            })
+            .map_err(|err| {
+                debug!({ eprintln!("timer error = {:?}", err) });
+            }),
+    )
+}
+
+/// Listens on every address in `config::CONFIG.bind_addresses`, printing the port each listener
+/// ended up on in the same order. When the server was given a WebSocket port, a WebSocket listener
+/// follows for every address. Every listener terminates TLS when the server was given a
+/// certificate.
+pub fn listen_tcp(state: Arc<Mutex<Shared>>) -> Server {
+    let (bind_addresses, websocket_port) = {
+        let config = config::CONFIG.read().unwrap();
+        (config.bind_addresses.clone(), config.websocket_port)
+    };
+    let tls_config = tls_config();
+
+    let mut servers: Vec<Server> = bind_addresses
+        .iter()
+        .map(|address| {
+            accept_tcp(
+                state.clone(),
+                bind_tcp(address),
+                tls_config.clone(),
+                Framing::LengthDelimited,
+            )
+        })
+        .collect();
+
+    if let Some(websocket_port) = websocket_port {
+        servers.extend(bind_addresses.iter().map(|address| {
+            accept_tcp(
+                state.clone(),
+                bind_tcp(&SocketAddr::new(address.ip(), websocket_port)),
+                tls_config.clone(),
+                Framing::WebSocket,
+            )
+        }));
+    }
+
+    servers.push(expire_failed_attempts(state));
+    Box::new(future::join_all(servers).map(|_| ()))
+}
+
+fn dispatch_socket<S>(
+    state: Arc<Mutex<Shared>>,
+    socket: S,
+    peer: Option<IpAddr>,
+    framing: Framing,
+) where
+    S: ClientStream + 'static,
+{
+    match framing {
+        Framing::LengthDelimited => init_dispatch(state, transport::length_delimited(socket), peer),
+        Framing::WebSocket => {
+            tokio::spawn(
+                websocket::accept(socket)
+                    .map(move |transport| init_dispatch(state, Box::new(transport), peer))
+                    .map_err(|err| {
+                        debug!({ eprintln!("WebSocket handshake failed = {:?}", err) });
+                    }),
+            );
+        }
+    }
+}
+
+fn accept_tcp(
+    state: Arc<Mutex<Shared>>,
+    listener: TcpListener,
+    tls_config: Option<Arc<ServerConfig>>,
+    framing: Framing,
+) -> Server {
+    Box::new(
+        listener
+            .incoming()
+            .for_each(move |socket| {
+                debug!({
+                    println!("accepted socket; addr={:?}", socket.peer_addr().unwrap());
+                });
+                let peer = socket.peer_addr().ok().map(|address| address.ip());
+                match tls_config {
+                    Some(ref tls_config) => {
+                        let state = state.clone();
+                        tokio::spawn(
+                            tls::accept(tls_config, socket)
+                                .map(move |socket| dispatch_socket(state, socket, peer, framing))
+                                .map_err(|err| {
+                                    debug!({ eprintln!("TLS handshake failed = {:?}", err) });
+                                }),
+                        );
+                    }
+                    None => dispatch_socket(state.clone(), socket, peer, framing),
+                }
+                Ok(())
+            })
+            .map_err(|err| {
+                debug!({ eprintln!("accept error = {:?}", err) });
+            }),
+    )
+}
+
+// A socket left behind by a server that did not shut down cleanly keeps a new one from binding to
+// the path. One that can still be connected to belongs to a running server and is left alone, as
+// is anything at the path that is not a socket.
+#[cfg(unix)]
+fn remove_stale_socket(socket_path: &str) {
+    use std::fs;
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixStream;
+
+    let is_socket = fs::symlink_metadata(socket_path)
+        .map(|metadata| metadata.file_type().is_socket())
+        .unwrap_or(false);
+
+    if is_socket && UnixStream::connect(socket_path).is_err() {
+        let _ = fs::remove_file(socket_path);
+    }
+}
+
+/// Listens on a Unix domain socket that only the user running the server can connect to.
+#[cfg(unix)]
+pub fn listen_unix(state: Arc<Mutex<Shared>>, socket_path: String) -> Server {
+    use libc;
+    use tokio::net::UnixListener;
+
+    remove_stale_socket(&socket_path);
+
+    // The socket is created with its final permissions rather than changed after binding, which
+    // would leave a window for others to connect in.
+    let listener = unsafe {
+        let umask = libc::umask(0o177);
+        let listener = UnixListener::bind(&socket_path);
+        libc::umask(umask);
+        listener
+    };
+    let listener = listener.unwrap_or_else(|_| {
+        debug!({
+            println!("Unix listener could not be bound to {}!", socket_path);
+        });
+        process::exit(constants::exit_code::EADDRINUSE);
+    });
+
+    println!("{}", socket_path);
+    Box::new(
+        listener
+            .incoming()
+            .for_each(move |socket| {
+                debug!({
+                    println!("accepted socket; addr={:?}", socket.peer_addr().unwrap());
+                });
+                init_dispatch(state.clone(), transport::length_delimited(socket), None);
+                Ok(())
+            })
+            .map_err(|err| {
+                debug!({ eprintln!("accept error = {:?}", err) });
+            }),
+    )
+}
@@ -1,20 +1,40 @@
+// Futures fail along with the connection they ran on, which is handed back so that the client can
+// still be told what went wrong. Their errors are large for it, but only ever moved.
+#![allow(clippy::result_large_err)]
+
 extern crate bytes;
 #[macro_use]
 extern crate clap;
+extern crate encoding_rs;
+#[macro_use]
 extern crate futures;
 #[macro_use]
 extern crate lazy_static;
+#[cfg(unix)]
+extern crate libc;
 #[macro_use]
 extern crate nom;
+extern crate notify;
+#[cfg(test)]
+extern crate rcgen;
+extern crate ring;
+extern crate rustls;
+#[macro_use]
+extern crate schemars;
 extern crate semver;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+#[cfg(test)]
+extern crate tempfile;
 extern crate tokio;
 extern crate tokio_io;
 extern crate tokio_process;
+extern crate tungstenite;
 extern crate uuid;
+#[cfg(test)]
+extern crate webpki;
 
 #[macro_export]
 macro_rules! debug {
@@ -25,25 +45,49 @@ macro_rules! debug {
     };
 }
 
+mod auth;
 mod config;
 mod constants;
 mod dispatch;
 mod error;
+mod listener;
 mod message;
+mod repo_lock;
+mod schema;
 mod state;
 mod types;
 mod util;
+mod watch;
 
 use clap::{App, Arg};
-use dispatch::init_dispatch;
+use std::env;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::Path;
 use std::process;
 use std::sync::{Arc, Mutex};
-use tokio::net::TcpListener;
-use tokio::prelude::*;
+
+/// Holds the token clients have to present, when `--token-file` is not given.
+const TOKEN_ENV_VAR: &str = "GITRS_TOKEN";
+
+fn read_token_file(token_file: &str) -> String {
+    fs::read_to_string(token_file).unwrap_or_else(|_| {
+        process::exit(constants::exit_code::ENOENT);
+    })
+}
+
+// An address given without a port listens on the one given with `--port`.
+fn parse_bind_address(address: &str, default_port: u16) -> Option<SocketAddr> {
+    address.parse::<SocketAddr>().ok().or_else(|| {
+        address
+            .parse::<IpAddr>()
+            .ok()
+            .map(|ip| SocketAddr::new(ip, default_port))
+    })
+}
 
 pub fn main() {
-    let matches = App::new("Git-RS")
+    let app = App::new("Git-RS")
         .version(env!("CARGO_PKG_VERSION"))
         .author("Axosoft")
         .about("Run Git commands over a TCP interface")
@@ -52,16 +96,55 @@ pub fn main() {
                 .short("p")
                 .long("port")
                 .value_name("PORT")
-                .help("The listen port of the server.")
+                .help("The listen port of the server. Port 0 has the system pick a free port, which is printed once bound.")
+                .validator(|maybe_port| match maybe_port.parse::<u32>() {
+                    Ok(port) => if port == 0 || (1024..=49151).contains(&port) {
+                        Ok(())
+                    } else {
+                        Err(String::from("Must be 0 or a number between 1024 and 49151!"))
+                    },
+                    Err(_) => Err(String::from("Must be 0 or a number between 1024 and 49151!")),
+                }),
+        )
+        .arg(
+            Arg::with_name("bind")
+                .short("b")
+                .long("bind")
+                .value_name("ADDRESS")
+                .help("An IPv4 or IPv6 address to listen on, optionally with a port of its own (e.g. 127.0.0.1, ::1 or [::1]:5134). May be given more than once, the port of each listener is printed in order. Defaults to 127.0.0.1.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|maybe_address| match parse_bind_address(&maybe_address, 0) {
+                    Some(_) => Ok(()),
+                    None => Err(String::from("Must be an IP address, optionally with a port!")),
+                }),
+        )
+        .arg(
+            Arg::with_name("websocket-port")
+                .short("w")
+                .long("websocket-port")
+                .value_name("PORT")
+                .help("Also listens for WebSocket connections on the given port of every address given with --bind, carrying one message per text frame. Its ports are printed after the ones of the TCP listeners. Requires a token, given with --token-file or the GITRS_TOKEN environment variable.")
                 .validator(|maybe_port| match maybe_port.parse::<u32>() {
-                    Ok(port) => if 1024 <= port && port <= 49151 {
+                    Ok(port) => if port == 0 || (1024..=49151).contains(&port) {
                         Ok(())
                     } else {
-                        Err(String::from("Must be a number between 1024 and 49151!"))
+                        Err(String::from("Must be 0 or a number between 1024 and 49151!"))
                     },
-                    Err(_) => Err(String::from("Must be a number between 1024 and 49151!")),
+                    Err(_) => Err(String::from("Must be 0 or a number between 1024 and 49151!")),
                 }),
         )
+        .arg(
+            Arg::with_name("websocket-origin")
+                .long("websocket-origin")
+                .value_name("ORIGIN")
+                .help("Allows web pages on the given origin (e.g. https://example.com) to open WebSocket connections. May be given more than once. Connections from any other page are refused, ones from clients other than browsers are not affected.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("websocket-port"),
+        )
         .arg(
             Arg::with_name("git-path")
                 .short("g")
@@ -92,58 +175,149 @@ pub fn main() {
                     }
                 }),
         )
+        .arg(
+            Arg::with_name("bisect-on-disconnect")
+                .long("bisect-on-disconnect")
+                .value_name("POLICY")
+                .help("What to do with a bisect session whose client disconnects before it is finished. Defaults to abort.")
+                .takes_value(true)
+                .possible_values(&["abort", "keep"]),
+        )
+        .arg(
+            Arg::with_name("tls-cert")
+                .long("tls-cert")
+                .value_name("CERT_FILE")
+                .help("Terminates TLS on every TCP listener, presenting the PEM certificate chain in the given file. Requires --tls-key.")
+                .takes_value(true)
+                .requires("tls-key"),
+        )
+        .arg(
+            Arg::with_name("tls-key")
+                .long("tls-key")
+                .value_name("KEY_FILE")
+                .help("The PEM private key of the certificate given with --tls-cert.")
+                .takes_value(true)
+                .requires("tls-cert"),
+        )
+        .arg(
+            Arg::with_name("tls-client-ca")
+                .long("tls-client-ca")
+                .value_name("CA_FILE")
+                .help("Requires clients to present a certificate issued by one of the PEM CA certificates in the given file.")
+                .takes_value(true)
+                .requires("tls-cert"),
+        )
+        .arg(
+            Arg::with_name("token-file")
+                .long("token-file")
+                .value_name("TOKEN_FILE")
+                .help("Reads a token from the given file that clients have to present in their Hello. Takes precedence over the GITRS_TOKEN environment variable.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("print-schema")
+                .long("print-schema")
+                .help("Prints a JSON Schema of every message of the protocol and exits."),
+        )
         .arg(
             Arg::with_name("debug")
                 .short("d")
                 .long("debug")
                 .hidden(true),
-        )
-        .get_matches();
+        );
+
+    #[cfg(unix)]
+    let app = app.arg(
+        Arg::with_name("socket")
+            .short("s")
+            .long("socket")
+            .value_name("SOCKET_PATH")
+            .help("Listens on a Unix domain socket at the given path instead of a TCP port. Only the user running the server may connect to it.")
+            .takes_value(true)
+            .conflicts_with_all(&["bind", "port", "tls-cert", "websocket-port"]),
+    );
+
+    let matches = app.get_matches();
+
+    if matches.is_present("print-schema") {
+        println!("{:#}", schema::protocol_schema());
+        return;
+    }
 
     {
         let mut config = config::CONFIG.write().unwrap();
-        matches.value_of("git-path").map(|maybe_path| {
+        if let Some(maybe_path) = matches.value_of("git-path") {
             config.git_path = Some(String::from(maybe_path));
-        });
-        matches.value_of("exec-path").map(|maybe_path| {
+        }
+        if let Some(maybe_path) = matches.value_of("exec-path") {
             if Path::new(&maybe_path).is_dir() {
                 config.exec_path = Some(String::from(maybe_path));
             } else {
                 process::exit(constants::exit_code::ENOENT);
             }
-        });
+        }
         if matches.is_present("port") {
             // failure case should never happen because we have already validated the port.
             config.port = value_t!(matches.value_of("port"), u32).unwrap_or_else(|e| e.exit());
         }
+        let port = config.port as u16;
+        config.bind_addresses = match matches.values_of("bind") {
+            // failure case should never happen because we have already validated the addresses.
+            Some(addresses) => addresses
+                .filter_map(|address| parse_bind_address(address, port))
+                .collect(),
+            None => vec![SocketAddr::from((Ipv4Addr::LOCALHOST, port))],
+        };
+        if matches.is_present("websocket-port") {
+            // failure case should never happen because we have already validated the port.
+            let websocket_port =
+                value_t!(matches.value_of("websocket-port"), u16).unwrap_or_else(|e| e.exit());
+            config.websocket_port = Some(websocket_port);
+        }
+        config.websocket_origins = matches
+            .values_of("websocket-origin")
+            .map_or_else(Vec::new, |origins| origins.map(String::from).collect());
+        if let Some(policy) = matches.value_of("bisect-on-disconnect") {
+            config.bisect_on_disconnect = match policy {
+                "keep" => config::BisectOnDisconnect::Keep,
+                _ => config::BisectOnDisconnect::Abort,
+            };
+        }
+        config.tls_cert_path = matches.value_of("tls-cert").map(String::from);
+        config.tls_key_path = matches.value_of("tls-key").map(String::from);
+        config.tls_client_ca_path = matches.value_of("tls-client-ca").map(String::from);
+        config.token = match matches.value_of("token-file") {
+            Some(token_file) => Some(read_token_file(token_file)),
+            None => env::var(TOKEN_ENV_VAR).ok(),
+        }.map(|token| String::from(token.trim()));
+        if config.token.as_ref().is_some_and(|token| token.is_empty()) {
+            process::exit(constants::exit_code::EINVAL);
+        }
+        // Without a token, any client that can reach the WebSocket listener could use the server.
+        if config.websocket_port.is_some() && config.token.is_none() {
+            eprintln!(
+                "--websocket-port requires a token, given with --token-file or {}!",
+                TOKEN_ENV_VAR
+            );
+            process::exit(constants::exit_code::EINVAL);
+        }
+        #[cfg(unix)]
+        if let Some(socket_path) = matches.value_of("socket") {
+            config.socket_path = Some(String::from(socket_path));
+        }
         config.debug = matches.is_present("debug");
     }
 
     let state = Arc::new(Mutex::new(state::Shared::new()));
-    let server_address = String::from(format!("0.0.0.0:{:?}", config::CONFIG.read().unwrap().port))
-        .parse()
-        .unwrap_or_else(|_| process::exit(constants::exit_code::EFAULT));
-
-    let listener = TcpListener::bind(&server_address).unwrap_or_else(|_| {
-        debug!({
-            println!("TCP listener could not be bound to address!");
-        });
-        process::exit(constants::exit_code::EADDRINUSE);
-    });
 
-    let server = listener
-        .incoming()
-        .for_each(move |socket| {
-            debug!({
-                println!("accepted socket; addr={:?}", socket.peer_addr().unwrap());
-            });
-            init_dispatch(state.clone(), socket);
-            Ok(())
-        })
-        .map_err(|err| {
-            debug!({ eprintln!("accept error = {:?}", err) });
-        });
+    #[cfg(unix)]
+    {
+        let socket_path = config::CONFIG.read().unwrap().socket_path.clone();
+        if let Some(socket_path) = socket_path {
+            tokio::run(listener::listen_unix(state, socket_path));
+            return;
+        }
+    }
 
-    println!("{:?}", config::CONFIG.read().unwrap().port);
-    tokio::run(server);
+    tokio::run(listener::listen_tcp(state));
 }
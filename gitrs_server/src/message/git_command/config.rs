@@ -0,0 +1,39 @@
+pub mod protocol {
+    #[derive(Clone, Copy, Debug, Deserialize, JsonSchema)]
+    pub enum Scope {
+        Global,
+        Local,
+        System,
+        Worktree,
+    }
+
+    #[derive(Clone, Copy, Debug, Deserialize, JsonSchema)]
+    pub enum ValueType {
+        Bool,
+        Int,
+        Path,
+    }
+
+    #[derive(Debug, Deserialize, JsonSchema)]
+    #[schemars(rename = "ConfigInbound")]
+    pub enum Inbound {
+        Get {
+            key: String,
+            scope: Option<Scope>,
+            value_type: Option<ValueType>,
+        },
+        List {
+            scope: Option<Scope>,
+        },
+        Set {
+            key: String,
+            value: String,
+            scope: Option<Scope>,
+            value_type: Option<ValueType>,
+        },
+        Unset {
+            key: String,
+            scope: Option<Scope>,
+        },
+    }
+}
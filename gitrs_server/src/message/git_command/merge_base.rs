@@ -1,5 +1,6 @@
 pub mod protocol {
-    #[derive(Debug, Deserialize)]
+    #[derive(Debug, Deserialize, JsonSchema)]
+    #[schemars(rename = "MergeBaseInbound")]
     pub enum Inbound {
         IsAncestor {
             ancestor_sha: String,
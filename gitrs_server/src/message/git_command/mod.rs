@@ -1,14 +1,71 @@
+mod config;
 mod merge_base;
+mod remote;
+mod reset;
+mod status;
+mod undo;
+mod worktree;
 
 pub mod protocol {
+    pub use super::config::protocol as config;
     pub use super::merge_base::protocol as merge_base;
+    pub use super::remote::protocol as remote;
+    pub use super::reset::protocol as reset;
+    pub use super::status::protocol as status;
+    pub use super::undo::protocol as undo;
+    pub use super::worktree::protocol as worktree;
 
-    #[derive(Debug, Deserialize)]
+    use uuid::Uuid;
+
+    /// A git command, optionally aimed at one of the repositories opened on the connection by its
+    /// handle. Commands without a handle operate on the most recently opened repository.
+    #[derive(Debug, Deserialize, JsonSchema)]
+    pub struct Request {
+        /// How long, in milliseconds, a command that writes to the repository waits for one
+        /// running on another connection to finish. Without it the command fails right away.
+        #[serde(default)]
+        pub lock_timeout_ms: Option<u64>,
+        #[serde(default)]
+        #[schemars(with = "Option<String>")]
+        pub repo: Option<Uuid>,
+        /// Echoed back in an `Error` frame should the command fail.
+        #[serde(default)]
+        pub request_id: Option<String>,
+        #[serde(flatten)]
+        pub command: Inbound,
+    }
+
+    #[derive(Debug, Deserialize, JsonSchema)]
+    #[schemars(rename = "GitCommandInbound")]
     pub enum Inbound {
         Bisect { bad: String, good: String },
+        CloseRepo,
+        Config(config::Inbound),
         Log,
         MergeBase(merge_base::Inbound),
-        OpenRepo { path: String },
-        Status,
+        OpenRepo {
+            path: String,
+            /// The repository's git dir, when it is not found from `path`.
+            git_dir: Option<String>,
+        },
+        Reflog {
+            #[serde(rename = "ref")]
+            reference: String,
+            skip: Option<usize>,
+            max_count: Option<usize>,
+        },
+        Remote(remote::Inbound),
+        RepoInfo,
+        Reset {
+            target: String,
+            mode: Option<reset::ResetMode>,
+            #[serde(default)]
+            paths: Vec<String>,
+        },
+        Status(Option<status::StatusOptions>),
+        Undo(undo::Inbound),
+        Unwatch,
+        Watch,
+        Worktree(worktree::Inbound),
     }
 }
@@ -0,0 +1,29 @@
+pub mod protocol {
+    #[derive(Debug, Deserialize, JsonSchema)]
+    #[schemars(rename = "RemoteInbound")]
+    pub enum Inbound {
+        Add {
+            name: String,
+            url: String,
+        },
+        List,
+        Prune {
+            name: String,
+            #[serde(default)]
+            dry_run: bool,
+        },
+        Remove {
+            name: String,
+        },
+        Rename {
+            old_name: String,
+            new_name: String,
+        },
+        SetUrl {
+            name: String,
+            url: String,
+            #[serde(default)]
+            push: bool,
+        },
+    }
+}
@@ -0,0 +1,9 @@
+pub mod protocol {
+    #[derive(Clone, Copy, Debug, Deserialize, PartialEq, JsonSchema)]
+    pub enum ResetMode {
+        Hard,
+        Keep,
+        Mixed,
+        Soft,
+    }
+}
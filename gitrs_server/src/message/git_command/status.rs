@@ -0,0 +1,36 @@
+pub mod protocol {
+    #[derive(Clone, Copy, Debug, Deserialize, JsonSchema)]
+    pub enum IgnoredMode {
+        Matching,
+        No,
+        Traditional,
+    }
+
+    #[derive(Clone, Copy, Debug, Deserialize, JsonSchema)]
+    pub enum IgnoreSubmodules {
+        All,
+        Dirty,
+        None,
+        Untracked,
+    }
+
+    #[derive(Clone, Copy, Debug, Deserialize, JsonSchema)]
+    pub enum UntrackedMode {
+        All,
+        No,
+        Normal,
+    }
+
+    /// Leaving out an option keeps git's default for it, except for untracked files, which are
+    /// listed individually unless asked otherwise.
+    #[derive(Debug, Default, Deserialize, JsonSchema)]
+    pub struct StatusOptions {
+        #[serde(default)]
+        pub branch: bool,
+        pub ignored: Option<IgnoredMode>,
+        pub ignore_submodules: Option<IgnoreSubmodules>,
+        #[serde(default)]
+        pub pathspecs: Vec<String>,
+        pub untracked: Option<UntrackedMode>,
+    }
+}
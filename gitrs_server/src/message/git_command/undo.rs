@@ -0,0 +1,8 @@
+pub mod protocol {
+    #[derive(Debug, Deserialize, JsonSchema)]
+    #[schemars(rename = "UndoInbound")]
+    pub enum Inbound {
+        LastAction,
+        Restore { expected_sha: String },
+    }
+}
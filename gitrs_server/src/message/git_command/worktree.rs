@@ -0,0 +1,28 @@
+pub mod protocol {
+    #[derive(Debug, Deserialize, JsonSchema)]
+    #[schemars(rename = "WorktreeInbound")]
+    pub enum Inbound {
+        Add {
+            path: String,
+            branch: Option<String>,
+            new_branch: Option<String>,
+        },
+        List,
+        Lock {
+            path: String,
+            reason: Option<String>,
+        },
+        Prune {
+            #[serde(default)]
+            dry_run: bool,
+        },
+        Remove {
+            path: String,
+            #[serde(default)]
+            force: bool,
+        },
+        Unlock {
+            path: String,
+        },
+    }
+}
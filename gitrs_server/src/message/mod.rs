@@ -5,26 +5,169 @@ pub mod protocol {
 
     use error::protocol::ErrorCode;
     use semver::Version;
+    use util::git_error::GitError;
+    use util::lossless::LosslessString;
+    use uuid::Uuid;
 
-    #[derive(Debug, Deserialize)]
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, JsonSchema)]
+    pub enum ChangeKind {
+        Head,
+        Index,
+        Refs,
+        Workdir,
+    }
+
+    /// Where HEAD points. `branch` is `None` when HEAD is detached and `sha` is `None` when the
+    /// branch has no commits yet.
+    #[derive(Clone, Debug, PartialEq, Serialize, JsonSchema)]
+    pub struct HeadState {
+        pub branch: Option<LosslessString>,
+        pub sha: Option<String>,
+    }
+
+    /// A ref that was created (no `old_sha`), deleted (no `new_sha`) or moved.
+    #[derive(Clone, Debug, Serialize, JsonSchema)]
+    pub struct RefUpdate {
+        pub name: LosslessString,
+        pub new_sha: Option<String>,
+        pub old_sha: Option<String>,
+    }
+
+    /// Optional parts of the protocol. A capability is only used once both sides have declared it,
+    /// and ones this server has never heard of are ignored.
+    #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, JsonSchema)]
+    pub enum Capability {
+        /// Errors carry the `request_id` of the request that caused them.
+        RequestIds,
+        #[serde(other)]
+        Unknown,
+    }
+
+    // Clients from before the handshake was negotiated send a bare `Hello`.
+    fn legacy_protocol_version() -> Version {
+        Version::new(0, 1, 0)
+    }
+
+    /// An option a command takes. `values` lists what it may be set to when that is one of a fixed
+    /// set, and is empty otherwise.
+    #[derive(Debug, Serialize, JsonSchema)]
+    pub struct CommandOption {
+        pub name: String,
+        pub values: Vec<String>,
+    }
+
+    /// A `GitCommand` the server supports. Interactive commands carry on a session with the client
+    /// after the request, streaming ones keep sending it messages.
+    #[derive(Debug, Serialize, JsonSchema)]
+    pub struct CommandCapability {
+        pub interactive: bool,
+        pub name: &'static str,
+        pub options: Vec<CommandOption>,
+        pub streaming: bool,
+        pub sub_commands: Vec<CommandCapability>,
+    }
+
+    #[derive(Debug, Deserialize, JsonSchema)]
     #[serde(tag = "type")]
     pub enum Inbound {
-        Hello,
-        GitCommand(git_command::Inbound),
+        /// The range of protocol versions the client speaks, both ends included. `token` is
+        /// required when the server was started with one.
+        Hello {
+            #[serde(default)]
+            capabilities: Vec<Capability>,
+            #[serde(default = "legacy_protocol_version")]
+            #[schemars(with = "String")]
+            max_version: Version,
+            #[serde(default = "legacy_protocol_version")]
+            #[schemars(with = "String")]
+            min_version: Version,
+            #[serde(default)]
+            token: Option<String>,
+        },
+        GitCommand(git_command::Request),
         Goodbye,
+        ListCapabilities,
     }
 
-    #[derive(Debug, Serialize)]
+    #[derive(Debug, Serialize, JsonSchema)]
     #[serde(tag = "type")]
     pub enum Outbound {
-        Hello { version: Version },
-        GladToMeetYou,
+        Capabilities { commands: Vec<CommandCapability> },
+        /// A request failed, or could not be understood in the first place. `request_id` is the
+        /// id the request was sent with, when there was one, it could be read and the
+        /// `RequestIds` capability was agreed on.
+        Error {
+            code: ErrorCode,
+            git_error: Option<GitError>,
+            message: String,
+            request_id: Option<String>,
+        },
+        /// `version` is the newest protocol version the server speaks and `min_version` the oldest.
+        Hello {
+            #[schemars(with = "String")]
+            min_version: Version,
+            #[schemars(with = "String")]
+            version: Version,
+        },
+        /// The handshake succeeded. `version` is the protocol version picked for the connection
+        /// and `capabilities` the ones both sides support. `connection` identifies the connection
+        /// to other connections, such as in `RepoBusy` errors. `git_version` is `None` when git
+        /// could not be run.
+        GladToMeetYou {
+            capabilities: Vec<Capability>,
+            #[schemars(with = "String")]
+            connection: Uuid,
+            git_version: Option<String>,
+            server_version: String,
+            #[schemars(with = "String")]
+            version: Version,
+        },
         Goodbye { error_code: Option<ErrorCode> },
+        HeadMoved {
+            from: HeadState,
+            #[schemars(with = "String")]
+            repo: Uuid,
+            to: HeadState,
+        },
+        RefsUpdated {
+            refs: Vec<RefUpdate>,
+            #[schemars(with = "String")]
+            repo: Uuid,
+        },
+        RepoChanged {
+            kind: ChangeKind,
+            #[schemars(with = "String")]
+            repo: Uuid,
+        },
+        /// The repository is no longer being watched, since a directory created in it could not
+        /// be. Sending `Watch` again starts over.
+        WatchFailed {
+            #[schemars(with = "String")]
+            repo: Uuid,
+        },
     }
 }
 
 pub mod channel {
+    use super::protocol::{ChangeKind, HeadState, RefUpdate};
+
+    #[derive(Clone)]
     pub enum Message {
-        Noop,
+        HeadMoved {
+            from: HeadState,
+            repo_path: String,
+            to: HeadState,
+        },
+        RefsUpdated {
+            refs: Vec<RefUpdate>,
+            repo_path: String,
+        },
+        RepoChanged {
+            kind: ChangeKind,
+            repo_path: String,
+        },
+        WatchFailed {
+            repo_path: String,
+        },
     }
 }
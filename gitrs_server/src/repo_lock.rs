@@ -0,0 +1,98 @@
+use futures::future::{self, loop_fn, Either, Future, Loop};
+use futures::sync::oneshot;
+use state::Shared;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+use uuid::Uuid;
+
+/// The connection holding a repository's lock and the command it is running.
+#[derive(Clone, Debug)]
+pub struct RepoLockHolder {
+    pub held_by: Uuid,
+    pub operation: String,
+}
+
+pub struct RepoLock {
+    holder: RepoLockHolder,
+    waiters: Vec<oneshot::Sender<()>>,
+}
+
+impl RepoLock {
+    pub fn new(holder: RepoLockHolder) -> Self {
+        RepoLock {
+            holder,
+            waiters: Vec::new(),
+        }
+    }
+
+    pub fn holder(&self) -> RepoLockHolder {
+        self.holder.clone()
+    }
+
+    /// Queues up to be told when the lock is released.
+    pub fn wait(&mut self) -> (RepoLockHolder, oneshot::Receiver<()>) {
+        let (sender, receiver) = oneshot::channel();
+        self.waiters.push(sender);
+        (self.holder.clone(), receiver)
+    }
+
+    // Every waiter gets to try again, and whoever loses the race queues up anew.
+    pub fn release(self) {
+        for waiter in self.waiters {
+            let _ = waiter.send(());
+        }
+    }
+}
+
+/// Releases the lock on a repository when dropped, so that it is given up however the command
+/// holding it ends.
+pub struct RepoLockGuard {
+    repo_path: String,
+    state: Arc<Mutex<Shared>>,
+}
+
+impl Drop for RepoLockGuard {
+    fn drop(&mut self) {
+        if let Ok(mut shared) = self.state.lock() {
+            shared.unlock_repo(&self.repo_path);
+        }
+    }
+}
+
+/// Takes the lock on a repository. Without a timeout a lock held by another connection fails
+/// right away, otherwise the lock is waited for until the timeout runs out.
+pub fn lock_repo(
+    state: Arc<Mutex<Shared>>,
+    repo_path: String,
+    holder: RepoLockHolder,
+    timeout: Option<Duration>,
+) -> Box<dyn Future<Item = RepoLockGuard, Error = RepoLockHolder> + Send> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+    Box::new(loop_fn((), move |_| -> Box<
+        dyn Future<Item = Loop<RepoLockGuard, ()>, Error = RepoLockHolder> + Send,
+    > {
+        let deadline = deadline.filter(|&deadline| Instant::now() < deadline);
+        let attempt = state
+            .lock()
+            .expect("Could not lock the shared state!")
+            .try_lock_repo(&repo_path, holder.clone(), deadline.is_some());
+
+        match (attempt, deadline) {
+            (Ok(()), _) => Box::new(future::ok(Loop::Break(RepoLockGuard {
+                repo_path: repo_path.clone(),
+                state: state.clone(),
+            }))),
+            (Err((current_holder, Some(released))), Some(deadline)) => Box::new(
+                released
+                    .select2(Delay::new(deadline))
+                    .then(move |result| match result {
+                        Ok(Either::B(_)) | Err(Either::B(_)) => Err(current_holder),
+                        _ => Ok(Loop::Continue(())),
+                    }),
+            ),
+            (Err((current_holder, _)), _) => Box::new(future::err(current_holder)),
+        }
+    }))
+}
@@ -0,0 +1,136 @@
+use dispatch;
+use message::protocol::git_command::Request;
+use message::protocol::{Inbound, Outbound};
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde_json::{self, Map, Value};
+
+/// The messages a command answers with, and for interactive commands the ones it reads from the
+/// client while it runs. Commands made up of sub-commands leave the answers to those. Streaming
+/// commands go on sending messages of their own accord for as long as they are in effect.
+pub struct CommandSchema {
+    inbound: Option<Schema>,
+    name: &'static str,
+    outbound: Option<Schema>,
+    streaming: bool,
+    sub_commands: Vec<CommandSchema>,
+}
+
+impl CommandSchema {
+    pub fn new<T: JsonSchema>(name: &'static str, generator: &mut SchemaGenerator) -> Self {
+        CommandSchema {
+            inbound: None,
+            name,
+            outbound: Some(generator.subschema_for::<T>()),
+            streaming: false,
+            sub_commands: Vec::new(),
+        }
+    }
+
+    pub fn interactive<T, U>(name: &'static str, generator: &mut SchemaGenerator) -> Self
+    where
+        T: JsonSchema,
+        U: JsonSchema,
+    {
+        CommandSchema {
+            inbound: Some(generator.subschema_for::<T>()),
+            ..CommandSchema::new::<U>(name, generator)
+        }
+    }
+
+    pub fn streaming<T: JsonSchema>(name: &'static str, generator: &mut SchemaGenerator) -> Self {
+        CommandSchema {
+            streaming: true,
+            ..CommandSchema::new::<T>(name, generator)
+        }
+    }
+
+    pub fn group(name: &'static str, sub_commands: Vec<CommandSchema>) -> Self {
+        CommandSchema {
+            inbound: None,
+            name,
+            outbound: None,
+            streaming: false,
+            sub_commands,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn is_interactive(&self) -> bool {
+        self.inbound.is_some()
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.streaming
+    }
+
+    pub fn sub_commands(&self) -> &[CommandSchema] {
+        &self.sub_commands
+    }
+
+    fn to_json(&self) -> Value {
+        let mut object = Map::new();
+        if let Some(ref inbound) = self.inbound {
+            object.insert(String::from("inbound"), json(inbound));
+        }
+        if let Some(ref outbound) = self.outbound {
+            object.insert(String::from("outbound"), json(outbound));
+        }
+        if self.streaming {
+            object.insert(String::from("streaming"), Value::Bool(true));
+        }
+        if !self.sub_commands.is_empty() {
+            object.insert(String::from("sub_commands"), commands_to_json(&self.sub_commands));
+        }
+        Value::Object(object)
+    }
+}
+
+fn json(schema: &Schema) -> Value {
+    serde_json::to_value(schema).expect("Could not serialize schema!")
+}
+
+fn commands_to_json(commands: &[CommandSchema]) -> Value {
+    Value::Object(
+        commands
+            .iter()
+            .map(|command| (String::from(command.name), command.to_json()))
+            .collect(),
+    )
+}
+
+/// Describes every message of the protocol as JSON Schema. `inbound` and `outbound` are the
+/// frames exchanged outside of any command, and `git_command` the ones each `GitCommand` answers
+/// with. Every `GitCommand` may also be answered with an `Error` frame or with the messages of
+/// `git_command.outbound`.
+pub fn protocol_schema() -> Value {
+    let mut generator = SchemaSettings::draft07().into_generator();
+
+    let inbound = generator.subschema_for::<Inbound>();
+    let outbound = generator.subschema_for::<Outbound>();
+    let git_command = CommandSchema {
+        inbound: Some(generator.subschema_for::<Request>()),
+        name: "GitCommand",
+        outbound: Some(generator.subschema_for::<dispatch::GitCommandOutbound>()),
+        streaming: false,
+        sub_commands: dispatch::command_schemas(&mut generator),
+    };
+
+    let mut schema = Map::new();
+    if let Some(ref meta_schema) = generator.settings().meta_schema {
+        schema.insert(String::from("$schema"), Value::String(meta_schema.clone()));
+    }
+    schema.insert(String::from("title"), Value::String(String::from("Git-RS protocol")));
+    schema.insert(String::from("inbound"), json(&inbound));
+    schema.insert(String::from("outbound"), json(&outbound));
+    schema.insert(String::from("git_command"), git_command.to_json());
+    schema.insert(
+        String::from("definitions"),
+        serde_json::to_value(generator.take_definitions()).expect("Could not serialize schema!"),
+    );
+    Value::Object(schema)
+}
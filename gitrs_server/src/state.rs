@@ -1,34 +1,175 @@
+use auth::{self, FailedAttempts, LocalAttempts};
+use futures::future::{self, Either};
 use futures::sync::mpsc::UnboundedSender as Sender;
+use futures::sync::oneshot;
+use futures::{Future, Poll, Stream};
 use message::channel;
-use std::collections::HashMap;
+use message::protocol::{Capability, ChangeKind};
+use notify;
+use repo_lock::{self, RepoLock, RepoLockGuard, RepoLockHolder};
+use semver::Version;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::timer::Delay;
 use util::channel::Channel;
-use util::transport::Transport;
+use util::git::RepoPath;
+use util::transport::{Receiver, Transport, TransportSink};
 use uuid::Uuid;
+use watch::{RepoLayout, RepoWatcher};
 
 #[derive(Default)]
 pub struct Shared {
     channel_by_id: HashMap<Uuid, Sender<channel::Message>>,
+    failed_attempts_by_peer: HashMap<IpAddr, FailedAttempts>,
+    local_attempts: LocalAttempts,
+    lock_by_repo_path: HashMap<String, RepoLock>,
+    repo_paths_by_id: HashMap<Uuid, HashSet<String>>,
+    watcher_by_repo_path: HashMap<String, RepoWatcher>,
 }
 
 impl Shared {
     pub fn new() -> Self {
         Default::default()
     }
+
+    /// Forgets the failed attempts that no longer count against their peer.
+    pub fn expire_failed_attempts(&mut self) {
+        self.failed_attempts_by_peer
+            .retain(|_, failed_attempts| !failed_attempts.is_expired());
+    }
+
+    /// Sends a message to every other connection that has the repository open.
+    pub fn broadcast(&self, from: &Uuid, repo_path: &str, message: &channel::Message) {
+        for (uuid, repo_paths) in &self.repo_paths_by_id {
+            if uuid == from || !repo_paths.contains(repo_path) {
+                continue;
+            }
+
+            if let Some(sender) = self.channel_by_id.get(uuid) {
+                // The connection may have gone away, in which case there is nobody left to tell.
+                let _ = sender.unbounded_send(message.clone());
+            }
+        }
+    }
+
+    /// Takes the lock on a repository if it is free. Otherwise reports who holds it, along with a
+    /// receiver that resolves once they let go of it if the caller is going to wait for that.
+    pub fn try_lock_repo(
+        &mut self,
+        repo_path: &str,
+        holder: RepoLockHolder,
+        will_wait: bool,
+    ) -> Result<(), (RepoLockHolder, Option<oneshot::Receiver<()>>)> {
+        if let Some(lock) = self.lock_by_repo_path.get_mut(repo_path) {
+            return Err(if will_wait {
+                let (holder, released) = lock.wait();
+                (holder, Some(released))
+            } else {
+                (lock.holder(), None)
+            });
+        }
+
+        self.lock_by_repo_path
+            .insert(String::from(repo_path), RepoLock::new(holder));
+        Ok(())
+    }
+
+    pub fn unlock_repo(&mut self, repo_path: &str) {
+        if let Some(lock) = self.lock_by_repo_path.remove(repo_path) {
+            lock.release();
+        }
+    }
+
+    pub fn notify_repo_changed(&mut self, repo_path: &str, kinds: &HashSet<ChangeKind>) {
+        let channel_by_id = &self.channel_by_id;
+        let has_subscribers = match self.watcher_by_repo_path.get_mut(repo_path) {
+            Some(watcher) => {
+                // Connections that have gone away can no longer be sent to, so they are
+                // unsubscribed here.
+                watcher.subscribers.retain(|uuid| match channel_by_id.get(uuid) {
+                    Some(sender) => kinds.iter().all(|&kind| {
+                        sender
+                            .unbounded_send(channel::Message::RepoChanged {
+                                kind,
+                                repo_path: String::from(repo_path),
+                            })
+                            .is_ok()
+                    }),
+                    None => false,
+                });
+                !watcher.subscribers.is_empty()
+            }
+            None => true,
+        };
+
+        if !has_subscribers {
+            self.watcher_by_repo_path.remove(repo_path);
+        }
+    }
+
+    /// Gives up watching a repository whose watcher can no longer see every change, telling its
+    /// subscribers. A watcher that has already been replaced is left alone.
+    pub fn stop_watching(&mut self, repo_path: &str, watcher_id: &Uuid) {
+        let is_current = self.watcher_by_repo_path
+            .get(repo_path)
+            .is_some_and(|watcher| watcher.id == *watcher_id);
+        if !is_current {
+            return;
+        }
+
+        if let Some(watcher) = self.watcher_by_repo_path.remove(repo_path) {
+            for uuid in &watcher.subscribers {
+                if let Some(sender) = self.channel_by_id.get(uuid) {
+                    let _ = sender.unbounded_send(channel::Message::WatchFailed {
+                        repo_path: String::from(repo_path),
+                    });
+                }
+            }
+        }
+    }
+
+    // Forgets everything the server knows about a connection that has gone away, and stops
+    // watching the repositories only it was subscribed to.
+    fn remove_connection(&mut self, uuid: &Uuid) {
+        self.channel_by_id.remove(uuid);
+        self.repo_paths_by_id.remove(uuid);
+        self.watcher_by_repo_path.retain(|_, watcher| {
+            watcher.subscribers.remove(uuid);
+            !watcher.subscribers.is_empty()
+        });
+    }
 }
 
 pub struct Connection {
+    /// The capabilities both sides agreed on in the handshake.
+    pub capabilities: Vec<Capability>,
     channel: Channel,
-    pub repo_path: Option<String>,
+    /// The address of the client, unless it connected over a Unix domain socket.
+    peer: Option<IpAddr>,
+    /// The protocol version agreed on in the handshake, `None` until it succeeded.
+    pub protocol_version: Option<Version>,
+    /// The repository the last `OpenRepo` opened, used by commands that do not name one.
+    pub default_repo: Option<Uuid>,
+    /// Where the commands of the repository the command being dispatched operates on run.
+    pub repo_path: Option<RepoPath>,
+    pub repo_path_by_handle: HashMap<Uuid, RepoPath>,
+    /// The half of the transport messages are read from, shared with whatever watches for the
+    /// client hanging up.
+    pub receiver: Arc<Mutex<Receiver>>,
     state: Arc<Mutex<Shared>>,
-    pub transport: Option<Transport>,
+    /// The half of the transport messages are sent on, taken while one is being sent.
+    pub transport: Option<TransportSink>,
     uuid: Uuid,
 }
 
 impl Connection {
-    pub fn new(state: Arc<Mutex<Shared>>, transport: Transport) -> Self {
+    pub fn new(state: Arc<Mutex<Shared>>, transport: Transport, peer: Option<IpAddr>) -> Self {
         let uuid = Uuid::new_v4();
         let channel = Channel::new();
+        let (transport, stream) = transport.split();
 
         state
             .lock()
@@ -37,11 +178,220 @@ impl Connection {
             .insert(uuid, channel.sender.clone());
 
         Connection {
+            capabilities: Vec::new(),
             channel,
+            peer,
+            protocol_version: None,
+            default_repo: None,
             repo_path: None,
+            repo_path_by_handle: HashMap::new(),
+            receiver: Arc::new(Mutex::new(Receiver::new(stream))),
             state,
             transport: Some(transport),
             uuid,
         }
     }
+
+    /// Identifies the connection to the client, see `Outbound::GladToMeetYou`.
+    pub fn id(&self) -> Uuid {
+        self.uuid
+    }
+
+    pub fn has_capability(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// Checks the token the client presented. A peer that presents too many wrong tokens is
+    /// locked out for a while. Local peers cannot be told apart, whether on a Unix domain socket
+    /// or on loopback, so rather than being locked out they take turns, see `LocalAttempts`.
+    pub fn authenticate(
+        &self,
+        expected: &str,
+        presented: Option<&str>,
+    ) -> impl Future<Item = bool, Error = ()> + Send {
+        let is_valid = auth::verify_token(expected, presented);
+        let mut shared = self.state.lock().expect("Could not lock the shared state!");
+
+        let peer = match self.peer {
+            Some(peer) if !peer.to_canonical().is_loopback() => peer,
+            _ => {
+                let turn_at = shared.local_attempts.take_turn(is_valid);
+                return Either::A(Delay::new(turn_at).then(move |_| Ok(is_valid)));
+            }
+        };
+
+        let is_locked_out = shared
+            .failed_attempts_by_peer
+            .get(&peer)
+            .is_some_and(|failed_attempts| failed_attempts.is_locked_out());
+
+        Either::B(future::ok(if is_locked_out {
+            false
+        } else if is_valid {
+            shared.failed_attempts_by_peer.remove(&peer);
+            true
+        } else {
+            shared
+                .failed_attempts_by_peer
+                .entry(peer)
+                .or_default()
+                .record_failure();
+            false
+        }))
+    }
+
+    /// Sends a message to every other connection that has the repository open.
+    pub fn broadcast(&self, repo_path: &str, message: channel::Message) {
+        self.state
+            .lock()
+            .expect("Could not lock the shared state!")
+            .broadcast(&self.uuid, repo_path, &message);
+    }
+
+    /// Takes the lock on a repository for a command that writes to it, see `repo_lock::lock_repo`.
+    pub fn lock_repo(
+        &self,
+        repo_path: String,
+        operation: &str,
+        timeout: Option<Duration>,
+    ) -> impl Future<Item = RepoLockGuard, Error = RepoLockHolder> {
+        let holder = RepoLockHolder {
+            held_by: self.uuid,
+            operation: String::from(operation),
+        };
+        repo_lock::lock_repo(self.state.clone(), repo_path, holder, timeout)
+    }
+
+    /// Opens a repository on the connection and makes it the default, returning its handle.
+    /// Opening a repository that is already open hands back its existing handle.
+    pub fn open_repo(&mut self, repo_path: RepoPath) -> Uuid {
+        let existing_handle = self.repo_path_by_handle
+            .iter()
+            .find(|&(_, open_repo_path)| *open_repo_path == repo_path)
+            .map(|(handle, _)| *handle);
+
+        let handle = match existing_handle {
+            Some(handle) => handle,
+            None => {
+                let handle = Uuid::new_v4();
+                self.repo_path_by_handle.insert(handle, repo_path.clone());
+                self.state
+                    .lock()
+                    .expect("Could not lock the shared state!")
+                    .repo_paths_by_id
+                    .entry(self.uuid)
+                    .or_default()
+                    .insert(repo_path.path.clone());
+                handle
+            }
+        };
+
+        self.default_repo = Some(handle);
+        self.repo_path = Some(repo_path);
+        handle
+    }
+
+    /// Closes a repository on the connection. Its working tree is only let go of once no other
+    /// handle, opened with a different git dir, still refers to it.
+    pub fn close_repo(&mut self, handle: &Uuid) {
+        if let Some(repo_path) = self.repo_path_by_handle.remove(handle) {
+            let is_still_open = self.repo_path_by_handle
+                .values()
+                .any(|open_repo_path| open_repo_path.path == repo_path.path);
+            if !is_still_open {
+                self.unwatch_repo(&repo_path.path);
+                if let Some(repo_paths) = self.state
+                    .lock()
+                    .expect("Could not lock the shared state!")
+                    .repo_paths_by_id
+                    .get_mut(&self.uuid)
+                {
+                    repo_paths.remove(&repo_path.path);
+                }
+            }
+        }
+
+        if self.default_repo.as_ref() == Some(handle) {
+            self.default_repo = None;
+        }
+        self.repo_path = None;
+    }
+
+    pub fn poll_channel(&mut self) -> Poll<Option<channel::Message>, ()> {
+        self.channel.receiver.poll()
+    }
+
+    /// Subscribes the connection to changes in a repository, starting to watch it if no other
+    /// connection is already. Setting up a watcher walks the working tree, so that is done on a
+    /// thread of its own rather than with the shared state locked.
+    pub fn watch_repo(
+        &self,
+        repo_path: RepoPath,
+        layout: RepoLayout,
+    ) -> impl Future<Item = (), Error = notify::Error> {
+        let path = repo_path.path.clone();
+        let uuid = self.uuid;
+        if let Some(watcher) = self.state
+            .lock()
+            .expect("Could not lock the shared state!")
+            .watcher_by_repo_path
+            .get_mut(&path)
+        {
+            watcher.subscribers.insert(uuid);
+            return Either::A(future::ok(()));
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        let state = self.state.clone();
+        thread::spawn(move || {
+            let _ = sender.send(RepoWatcher::new(state, repo_path, layout));
+        });
+
+        let state = self.state.clone();
+        Either::B(
+            receiver
+                .map_err(|_| notify::Error::Generic(String::from("Could not set up the watcher!")))
+                .and_then(move |watcher| {
+                    let watcher = watcher?;
+                    // Another connection may have started watching the repository meanwhile, in
+                    // which case its watcher is kept.
+                    state
+                        .lock()
+                        .expect("Could not lock the shared state!")
+                        .watcher_by_repo_path
+                        .entry(path)
+                        .or_insert(watcher)
+                        .subscribers
+                        .insert(uuid);
+                    Ok(())
+                }),
+        )
+    }
+
+    /// Unsubscribes the connection from a repository, which stops being watched once nobody is
+    /// subscribed to it.
+    pub fn unwatch_repo(&self, repo_path: &str) {
+        let mut shared = self.state.lock().expect("Could not lock the shared state!");
+
+        let has_subscribers = match shared.watcher_by_repo_path.get_mut(repo_path) {
+            Some(watcher) => {
+                watcher.subscribers.remove(&self.uuid);
+                !watcher.subscribers.is_empty()
+            }
+            None => true,
+        };
+
+        if !has_subscribers {
+            shared.watcher_by_repo_path.remove(repo_path);
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // A poisoned lock means the server is going down anyway.
+        if let Ok(mut shared) = self.state.lock() {
+            shared.remove_connection(&self.uuid);
+        }
+    }
 }
@@ -3,5 +3,6 @@ use futures::future::Future;
 use state;
 
 pub type DispatchFuture = Box<
-    Future<Item = state::Connection, Error = (error::protocol::Error, state::Connection)> + Send,
+    dyn Future<Item = state::Connection, Error = (error::protocol::Error, state::Connection)>
+        + Send,
 >;
@@ -1,12 +1,22 @@
 use config;
 use constants;
 use std::env;
+use std::path::PathBuf;
 use std::process::Command;
 
+/// Where the commands for a repository open on a connection run: from its working tree, or from its
+/// git dir when it has none. A repository whose git dir is not the working tree's `.git` keeps the
+/// git dir it was opened with, which its commands are pointed at.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RepoPath {
+    pub git_dir: Option<PathBuf>,
+    pub path: String,
+}
+
 pub fn new_command() -> Command {
     let path = match config::CONFIG.read().unwrap().git_path {
         Some(ref git_path) => {
-            let mut git_path = String::from(git_path.clone());
+            let mut git_path = git_path.clone();
             git_path.push_str(constants::platform::ENV_PATH_SEPARATOR);
             match env::var("PATH") {
                 Ok(env_path) => {
@@ -19,27 +29,32 @@ pub fn new_command() -> Command {
         None => None,
     };
 
-    let exec_path = match config::CONFIG.read().unwrap().exec_path {
-        Some(ref git_exec_path) => {
-            Some(String::from(git_exec_path.clone()))
-        }
-        None => None,
-    };
+    let exec_path = config::CONFIG.read().unwrap().exec_path.clone();
 
     let mut command = Command::new("git");
-    path.map(|path| {
-        command.env("PATH", &String::from(path));
-    });
-    exec_path.map(|exec_path| {
-        command.env("GIT_EXEC_PATH", &String::from(exec_path));
-    });
+    if let Some(path) = path {
+        command.env("PATH", path);
+    }
+    if let Some(exec_path) = exec_path {
+        command.env("GIT_EXEC_PATH", exec_path);
+    }
+    // Failures are told apart by their message, which would otherwise be translated. Every command
+    // that looks at stderr has to be built here for this to hold.
+    command.env("LC_ALL", "C");
     command.arg("--no-pager");
     command
 }
 
-pub fn new_command_with_repo_path(repo_path: &str) -> Command {
+pub fn new_command_with_repo_path(repo_path: &RepoPath) -> Command {
     let mut command = new_command();
-    command.current_dir(repo_path);
+    command.current_dir(&repo_path.path);
+    if let Some(ref git_dir) = repo_path.git_dir {
+        command
+            .arg("--git-dir")
+            .arg(git_dir)
+            .arg("--work-tree")
+            .arg(&repo_path.path);
+    }
     command
 }
 
@@ -52,8 +67,34 @@ pub fn verify_string_is_sha(maybe_sha: &str) -> bool {
         return false;
     }
 
-    maybe_sha
-        .to_lowercase()
-        .chars()
-        .fold(true, |is_sha, next_char| is_sha && next_char.is_digit(16))
+    maybe_sha.chars().all(|next_char| next_char.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{new_command_with_repo_path, RepoPath};
+    use std::path::PathBuf;
+    use tempfile;
+    use util::test_repo::TestRepo;
+
+    #[test]
+    fn runs_in_the_git_dir_the_repo_was_opened_with() {
+        let repo = TestRepo::new();
+        repo.write("file", b"contents\n");
+        repo.commit("initial");
+        let workdir = tempfile::tempdir().unwrap();
+        let repo_path = RepoPath {
+            git_dir: Some(PathBuf::from(repo.join(".git"))),
+            path: String::from(workdir.path().to_str().unwrap()),
+        };
+
+        let output = new_command_with_repo_path(&repo_path)
+            .arg("status")
+            .arg("--porcelain")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        // The commit is there, but none of its files are in the other working tree.
+        assert_eq!(String::from_utf8_lossy(&output.stdout), " D file\n");
+    }
 }
@@ -0,0 +1,243 @@
+use std::process::Output;
+use util::lossless::LosslessString;
+
+/// The failures git reports often enough to be worth telling apart. Git exits with 128 for nearly
+/// all of them, so they can only be recognized by their message.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, JsonSchema)]
+pub enum GitErrorKind {
+    BadRevision,
+    DetachedHead,
+    IndexLocked,
+    LocalChangesWouldBeLost,
+    MergeConflict,
+    NotARepository,
+    PathspecDidNotMatch,
+    PermissionDenied,
+    Unknown,
+}
+
+/// A git command that exited unsuccessfully, along with what it had to say about it.
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct GitError {
+    pub exit_code: Option<i32>,
+    pub kind: GitErrorKind,
+    pub stderr: LosslessString,
+}
+
+// Checked in order, the first kind with a matching pattern wins.
+static PATTERNS: &[(GitErrorKind, &[&str])] = &[
+    (GitErrorKind::NotARepository, &["not a git repository"]),
+    (GitErrorKind::IndexLocked, &[".lock': File exists", "index.lock"]),
+    (GitErrorKind::PermissionDenied, &["Permission denied"]),
+    (
+        GitErrorKind::LocalChangesWouldBeLost,
+        &["would be overwritten by", "not uptodate. Cannot merge"],
+    ),
+    (
+        GitErrorKind::MergeConflict,
+        &[
+            "CONFLICT (",
+            "Automatic merge failed",
+            "Merge conflict in",
+            "needs merge",
+            "you need to resolve your current index first",
+            "unmerged files",
+        ],
+    ),
+    (
+        GitErrorKind::PathspecDidNotMatch,
+        &["did not match any file(s) known to git", "did not match any files"],
+    ),
+    (
+        GitErrorKind::DetachedHead,
+        &[
+            "You are not currently on a branch",
+            "HEAD detached",
+            "ref HEAD is not a symbolic ref",
+        ],
+    ),
+    (
+        GitErrorKind::BadRevision,
+        &[
+            "bad revision",
+            "bad object",
+            "unknown revision",
+            "ambiguous argument",
+            "invalid reference",
+            "Not a valid object name",
+            "Not a valid commit name",
+            "Needed a single revision",
+            "is not a commit",
+            "does not appear to be a valid revision",
+        ],
+    ),
+];
+
+fn classify_message(message: &str) -> GitErrorKind {
+    PATTERNS
+        .iter()
+        .find(|&&(_, patterns)| patterns.iter().any(|pattern| message.contains(pattern)))
+        .map_or(GitErrorKind::Unknown, |&(kind, _)| kind)
+}
+
+/// Works out why a git command failed. Merge conflicts are only announced on stdout, so both
+/// streams are looked at, but only stderr is passed on.
+pub fn classify(output: &Output) -> GitError {
+    let stderr = LosslessString::from(output.stderr.as_slice());
+    let kind = match classify_message(stderr.as_lossy_str()) {
+        GitErrorKind::Unknown => classify_message(&String::from_utf8_lossy(&output.stdout)),
+        kind => kind,
+    };
+
+    GitError {
+        exit_code: output.status.code(),
+        kind,
+        stderr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{classify, GitErrorKind, PATTERNS};
+    use std::process::{ExitStatus, Output};
+
+    #[cfg(unix)]
+    fn exit_status(code: i32) -> ExitStatus {
+        use std::os::unix::process::ExitStatusExt;
+        ExitStatus::from_raw(code << 8)
+    }
+
+    #[cfg(windows)]
+    fn exit_status(code: i32) -> ExitStatus {
+        use std::os::windows::process::ExitStatusExt;
+        ExitStatus::from_raw(code as u32)
+    }
+
+    fn output(stdout: &[u8], stderr: &[u8]) -> Output {
+        Output {
+            status: exit_status(128),
+            stdout: stdout.to_vec(),
+            stderr: stderr.to_vec(),
+        }
+    }
+
+    // What git says when it fails for each of the patterns, as written to stderr.
+    static FIXTURES: &[(GitErrorKind, &[u8])] = &[
+        (
+            GitErrorKind::NotARepository,
+            b"fatal: not a git repository (or any of the parent directories): .git\n",
+        ),
+        (
+            GitErrorKind::IndexLocked,
+            b"fatal: Unable to create '/repo/.git/index.lock': File exists.\n",
+        ),
+        (
+            GitErrorKind::IndexLocked,
+            b"fatal: cannot lock ref 'HEAD': Unable to create '/repo/.git/HEAD.lock': File \
+              exists.\n",
+        ),
+        (
+            GitErrorKind::PermissionDenied,
+            b"error: could not lock config file /repo/.git/config: Permission denied\n",
+        ),
+        (
+            GitErrorKind::LocalChangesWouldBeLost,
+            b"error: Your local changes to the following files would be overwritten by \
+              checkout:\n\tfile\nPlease commit your changes or stash them before you switch \
+              branches.\nAborting\n",
+        ),
+        (
+            GitErrorKind::LocalChangesWouldBeLost,
+            b"error: Entry 'file' not uptodate. Cannot merge.\nfatal: Could not reset index file \
+              to revision 'HEAD~1'.\n",
+        ),
+        (GitErrorKind::MergeConflict, b"CONFLICT (content): Merge conflict in file\n"),
+        (
+            GitErrorKind::MergeConflict,
+            b"Automatic merge failed; fix conflicts and then commit the result.\n",
+        ),
+        (GitErrorKind::MergeConflict, b"error: Merge conflict in file\n"),
+        (GitErrorKind::MergeConflict, b"file: needs merge\n"),
+        (
+            GitErrorKind::MergeConflict,
+            b"file: needs update\nerror: you need to resolve your current index first\n",
+        ),
+        (
+            GitErrorKind::MergeConflict,
+            b"error: Committing is not possible because you have unmerged files.\n",
+        ),
+        (
+            GitErrorKind::PathspecDidNotMatch,
+            b"error: pathspec 'missing' did not match any file(s) known to git\n",
+        ),
+        (
+            GitErrorKind::PathspecDidNotMatch,
+            b"fatal: pathspec 'missing' did not match any files\n",
+        ),
+        (
+            GitErrorKind::DetachedHead,
+            b"You are not currently on a branch.\nPlease specify which branch you want to merge \
+              with.\n",
+        ),
+        (GitErrorKind::DetachedHead, b"fatal: HEAD detached at 1a2b3c4\n"),
+        (GitErrorKind::DetachedHead, b"fatal: ref HEAD is not a symbolic ref\n"),
+        (GitErrorKind::BadRevision, b"fatal: bad revision 'missing'\n"),
+        (GitErrorKind::BadRevision, b"fatal: bad object missing\n"),
+        (
+            GitErrorKind::BadRevision,
+            b"fatal: ambiguous argument 'missing': unknown revision or path not in the working \
+              tree.\n",
+        ),
+        (GitErrorKind::BadRevision, b"fatal: unknown revision 'missing'\n"),
+        (GitErrorKind::BadRevision, b"fatal: invalid reference: missing\n"),
+        (GitErrorKind::BadRevision, b"fatal: Not a valid object name missing\n"),
+        (GitErrorKind::BadRevision, b"fatal: Not a valid commit name missing\n"),
+        (GitErrorKind::BadRevision, b"fatal: Needed a single revision\n"),
+        (
+            GitErrorKind::BadRevision,
+            b"fatal: '1a2b3c4' is not a commit and a branch 'topic' cannot be created from it\n",
+        ),
+        (
+            GitErrorKind::BadRevision,
+            b"fatal: 'missing' does not appear to be a valid revision\n",
+        ),
+    ];
+
+    #[test]
+    fn classifies_every_pattern() {
+        for &(kind, stderr) in FIXTURES {
+            let git_error = classify(&output(b"", stderr));
+            assert_eq!(git_error.kind, kind, "{}", String::from_utf8_lossy(stderr));
+            assert_eq!(git_error.exit_code, Some(128));
+            assert_eq!(git_error.stderr.as_lossy_str(), &*String::from_utf8_lossy(stderr));
+        }
+
+        for &(_, patterns) in PATTERNS {
+            for pattern in patterns {
+                let is_covered = FIXTURES.iter().any(|&(_, stderr)| {
+                    String::from_utf8_lossy(stderr).contains(pattern)
+                });
+                assert!(is_covered, "no fixture for {:?}", pattern);
+            }
+        }
+    }
+
+    #[test]
+    fn falls_back_to_stdout_when_stderr_is_not_recognized() {
+        let conflict = b"Auto-merging file\nCONFLICT (content): Merge conflict in file\n";
+
+        let git_error = classify(&output(conflict, b""));
+        assert_eq!(git_error.kind, GitErrorKind::MergeConflict);
+
+        let git_error = classify(&output(conflict, b"error: something else went wrong\n"));
+        assert_eq!(git_error.kind, GitErrorKind::MergeConflict);
+        assert_eq!(git_error.stderr.as_lossy_str(), "error: something else went wrong\n");
+
+        // Stderr is looked at first, and only passed on.
+        let git_error = classify(&output(conflict, b"fatal: Needed a single revision\n"));
+        assert_eq!(git_error.kind, GitErrorKind::BadRevision);
+
+        let git_error = classify(&output(b"nothing to see\n", b"\xff\xfe unexpected\n"));
+        assert_eq!(git_error.kind, GitErrorKind::Unknown);
+    }
+}
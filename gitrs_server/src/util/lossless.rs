@@ -0,0 +1,72 @@
+use encoding_rs::{Encoding, UTF_8};
+use std::ffi::{OsStr, OsString};
+
+/// Text from git that is almost always, but not necessarily, UTF-8, such as a file name or a
+/// commit message. Text that decodes cleanly is sent as a plain string, anything else as its exact
+/// bytes along with a lossy rendering for display, which also flags it as not having decoded.
+#[derive(Clone, Debug, PartialEq, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum LosslessString {
+    Utf8(String),
+    Bytes { bytes: Vec<u8>, lossy: String },
+}
+
+impl LosslessString {
+    /// Decodes text that git printed in `encoding` rather than UTF-8, which it does for commit
+    /// messages when `i18n.logOutputEncoding` says so.
+    pub fn decode(bytes: &[u8], encoding: &'static Encoding) -> Self {
+        match encoding.decode_without_bom_handling_and_without_replacement(bytes) {
+            Some(text) => LosslessString::Utf8(text.into_owned()),
+            None => LosslessString::Bytes {
+                bytes: bytes.to_vec(),
+                lossy: encoding.decode_without_bom_handling(bytes).0.into_owned(),
+            },
+        }
+    }
+
+    /// The text itself, or its lossy rendering when it did not decode.
+    pub fn as_lossy_str(&self) -> &str {
+        match *self {
+            LosslessString::Utf8(ref text) => text,
+            LosslessString::Bytes { ref lossy, .. } => lossy,
+        }
+    }
+}
+
+impl Default for LosslessString {
+    fn default() -> Self {
+        LosslessString::Utf8(String::new())
+    }
+}
+
+impl<'a> From<&'a [u8]> for LosslessString {
+    fn from(bytes: &'a [u8]) -> Self {
+        LosslessString::decode(bytes, UTF_8)
+    }
+}
+
+impl<'a> From<&'a OsStr> for LosslessString {
+    #[cfg(unix)]
+    fn from(os_str: &'a OsStr) -> Self {
+        use std::os::unix::ffi::OsStrExt;
+        LosslessString::from(os_str.as_bytes())
+    }
+
+    #[cfg(not(unix))]
+    fn from(os_str: &'a OsStr) -> Self {
+        LosslessString::Utf8(os_str.to_string_lossy().into_owned())
+    }
+}
+
+/// A path or ref name exactly as git printed it, which on Unix may be any bytes but a NUL.
+#[cfg(unix)]
+pub fn os_string_from_bytes(bytes: &[u8]) -> OsString {
+    use std::os::unix::ffi::OsStrExt;
+    OsStr::from_bytes(bytes).to_os_string()
+}
+
+/// A path or ref name exactly as git printed it, which git for Windows prints as UTF-8.
+#[cfg(not(unix))]
+pub fn os_string_from_bytes(bytes: &[u8]) -> OsString {
+    OsString::from(String::from_utf8_lossy(bytes).into_owned())
+}
@@ -1,4 +1,10 @@
 pub mod channel;
 pub mod git;
+pub mod git_error;
+pub mod lossless;
 pub mod parse;
+#[cfg(test)]
+pub mod test_repo;
+pub mod tls;
 pub mod transport;
+pub mod websocket;
@@ -1,7 +1,11 @@
+use std::str;
+
 pub fn parse_u32(input: &str, radix: u32) -> u32 {
     u32::from_str_radix(input, radix).unwrap()
 }
 
 named!(pub sha<&str, &str>, take!(40));
 
+named!(pub sha_bytes<&[u8], &str>, map_res!(take!(40), str::from_utf8));
+
 named!(pub short_sha<&str, &str>, take!(7));
@@ -0,0 +1,83 @@
+use futures::Future;
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+use tempfile::{self, TempDir};
+use tokio::runtime::current_thread::Runtime;
+use util::git::{self, RepoPath};
+
+/// Runs a future to completion, on a runtime of its own so git can be run asynchronously.
+pub fn block_on<F: Future>(future: F) -> Result<F::Item, F::Error> {
+    Runtime::new()
+        .expect("Could not start a runtime!")
+        .block_on(future)
+}
+
+/// A repository in a temporary directory, removed again when it is dropped. Git is run the way the
+/// server runs it.
+pub struct TestRepo {
+    dir: TempDir,
+}
+
+impl TestRepo {
+    pub fn new() -> Self {
+        let repo = TestRepo {
+            dir: tempfile::tempdir().expect("Could not create a temporary directory!"),
+        };
+        repo.git(&["init", "-q"]);
+        repo.git(&["symbolic-ref", "HEAD", "refs/heads/master"]);
+        repo.git(&["config", "user.name", "Test"]);
+        repo.git(&["config", "user.email", "test@example.com"]);
+        repo.git(&["config", "commit.gpgsign", "false"]);
+        repo
+    }
+
+    pub fn path(&self) -> &str {
+        self.dir.path().to_str().expect("Temporary directory is not UTF-8!")
+    }
+
+    pub fn repo_path(&self) -> RepoPath {
+        RepoPath {
+            git_dir: None,
+            path: String::from(self.path()),
+        }
+    }
+
+    /// Runs git in the repository, whether or not it succeeds.
+    pub fn run(&self, args: &[&str]) -> Output {
+        git::new_command_with_repo_path(&self.repo_path())
+            .args(args)
+            .output()
+            .expect("Could not run git!")
+    }
+
+    /// Runs git in the repository, which has to succeed.
+    pub fn git(&self, args: &[&str]) -> Output {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "git {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        output
+    }
+
+    pub fn write(&self, path: &str, contents: &[u8]) {
+        let path = self.dir.path().join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(path, contents).unwrap();
+    }
+
+    /// Commits everything in the working tree.
+    pub fn commit(&self, message: &str) {
+        self.git(&["add", "-A"]);
+        self.git(&["commit", "-q", "--allow-empty", "-m", message]);
+    }
+
+    pub fn join(&self, path: &str) -> String {
+        Path::new(self.path()).join(path).to_string_lossy().into_owned()
+    }
+}
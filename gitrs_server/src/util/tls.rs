@@ -0,0 +1,417 @@
+use futures::{Async, Future, Poll};
+use rustls::internal::pemfile;
+use rustls::{
+    AllowAnyAuthenticatedClient, Certificate, NoClientAuth, PrivateKey, RootCertStore,
+    ServerConfig, ServerSession, Session,
+};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::sync::Arc;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+fn open(path: &str) -> Result<BufReader<File>, String> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| format!("Could not open {}: {}", path, err))
+}
+
+fn load_certificates(path: &str) -> Result<Vec<Certificate>, String> {
+    match pemfile::certs(&mut open(path)?) {
+        Ok(ref certificates) if certificates.is_empty() => {
+            Err(format!("No certificates found in {}", path))
+        }
+        Ok(certificates) => Ok(certificates),
+        Err(_) => Err(format!("Could not parse the certificates in {}", path)),
+    }
+}
+
+// Keys may be either PKCS #8 or PKCS #1 RSA keys; the first one found is used.
+fn load_private_key(path: &str) -> Result<PrivateKey, String> {
+    let pkcs8_keys = pemfile::pkcs8_private_keys(&mut open(path)?).unwrap_or_default();
+    let rsa_keys = pemfile::rsa_private_keys(&mut open(path)?).unwrap_or_default();
+    pkcs8_keys
+        .into_iter()
+        .chain(rsa_keys)
+        .next()
+        .ok_or_else(|| format!("No private key found in {}", path))
+}
+
+/// Builds the configuration TLS connections are accepted with. When `client_ca_path` is given,
+/// clients have to present a certificate issued by one of the CAs in it.
+pub fn server_config(
+    cert_path: &str,
+    key_path: &str,
+    client_ca_path: Option<&str>,
+) -> Result<Arc<ServerConfig>, String> {
+    let client_verifier = match client_ca_path {
+        Some(client_ca_path) => {
+            let mut client_cas = RootCertStore::empty();
+            for certificate in load_certificates(client_ca_path)? {
+                client_cas.add(&certificate).map_err(|err| {
+                    format!("Could not use a CA in {}: {:?}", client_ca_path, err)
+                })?;
+            }
+            AllowAnyAuthenticatedClient::new(client_cas)
+        }
+        None => NoClientAuth::new(),
+    };
+
+    let mut config = ServerConfig::new(client_verifier);
+    config
+        .set_single_cert(load_certificates(cert_path)?, load_private_key(key_path)?)
+        .map_err(|err| format!("Could not use the certificate in {}: {}", cert_path, err))?;
+    Ok(Arc::new(config))
+}
+
+fn poll_io<T>(result: io::Result<T>) -> Poll<T, io::Error> {
+    match result {
+        Ok(value) => Ok(Async::Ready(value)),
+        Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+        Err(err) => Err(err),
+    }
+}
+
+/// A socket that TLS is terminated on. Reads and writes are of plaintext, so it can be framed
+/// like any other `ClientStream`.
+pub struct TlsStream<S> {
+    is_closing: bool,
+    session: ServerSession,
+    socket: S,
+}
+
+impl<S: Read + Write> TlsStream<S> {
+    // Sends every TLS record the session has queued up.
+    fn write_tls(&mut self) -> io::Result<()> {
+        while self.session.wants_write() {
+            self.session.write_tls(&mut self.socket)?;
+        }
+        Ok(())
+    }
+
+    // Hands whatever the socket has to the session, returning 0 once the client has hung up. The
+    // client is sent an alert explaining why its records were rejected, if it is still listening.
+    fn read_tls(&mut self) -> io::Result<usize> {
+        let read = self.session.read_tls(&mut self.socket)?;
+        if let Err(err) = self.session.process_new_packets() {
+            let _ = self.write_tls();
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+        }
+        Ok(read)
+    }
+}
+
+impl<S: Read + Write> Read for TlsStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            // Anything the socket cannot take yet is sent on the next write.
+            match self.write_tls() {
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                result => result?,
+            }
+
+            match self.session.read(buf) {
+                Ok(0) if !buf.is_empty() => {}
+                Ok(read) => return Ok(read),
+                // The client sent a close_notify alert, which ends the stream like a hang up.
+                Err(ref err) if err.kind() == io::ErrorKind::ConnectionAborted => return Ok(0),
+                Err(err) => return Err(err),
+            }
+
+            if self.read_tls()? == 0 {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+impl<S: Read + Write> Write for TlsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // The session buffers plaintext without limit, so nothing more is taken until what is
+        // already queued has been handed to the socket.
+        self.write_tls()?;
+        let written = self.session.write(buf)?;
+        match self.write_tls() {
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            result => result?,
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.session.flush()?;
+        self.write_tls()?;
+        self.socket.flush()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncRead for TlsStream<S> {}
+
+impl<S: AsyncRead + AsyncWrite> AsyncWrite for TlsStream<S> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        if !self.is_closing {
+            self.session.send_close_notify();
+            self.is_closing = true;
+        }
+        try_ready!(poll_io(self.flush()));
+        self.socket.shutdown()
+    }
+}
+
+/// Resolves to the stream once the TLS handshake with the client has completed.
+pub struct Handshake<S>(Option<TlsStream<S>>);
+
+impl<S: AsyncRead + AsyncWrite> Future for Handshake<S> {
+    type Item = TlsStream<S>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        {
+            let stream = self.0.as_mut().expect("Handshake polled after it completed!");
+            loop {
+                try_ready!(poll_io(stream.write_tls()));
+                if !stream.session.is_handshaking() {
+                    break;
+                }
+                if try_ready!(poll_io(stream.read_tls())) == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "The client hung up during the TLS handshake",
+                    ));
+                }
+            }
+        }
+        Ok(Async::Ready(self.0.take().unwrap()))
+    }
+}
+
+/// Starts terminating TLS on a socket a client has just connected on.
+pub fn accept<S: AsyncRead + AsyncWrite>(config: &Arc<ServerConfig>, socket: S) -> Handshake<S> {
+    Handshake(Some(TlsStream {
+        is_closing: false,
+        session: ServerSession::new(config),
+        socket,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{accept, server_config};
+    use futures::{Future, Stream};
+    use rcgen::{self, BasicConstraints, CertificateParams, IsCa};
+    use rustls::{self, Certificate, ClientConfig, ClientSession, PrivateKey, ServerConfig, Session};
+    use std::fs;
+    use std::io::{self, Read, Write};
+    use std::net::{SocketAddr, TcpStream as StdTcpStream};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use tempfile::{self, TempDir};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_io::io as async_io;
+    use util::test_repo::block_on;
+    use webpki::DNSNameRef;
+
+    // A CA that issued a certificate for the server on localhost and one for a client, written out
+    // as the PEM files the server is started with.
+    struct Pki {
+        ca: rcgen::Certificate,
+        client: rcgen::Certificate,
+        dir: TempDir,
+    }
+
+    impl Pki {
+        fn new() -> Self {
+            let mut ca_params = CertificateParams::new(Vec::new());
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = rcgen::Certificate::from_params(ca_params).unwrap();
+            let server =
+                rcgen::Certificate::from_params(CertificateParams::new(vec!["localhost".into()]))
+                    .unwrap();
+            let client =
+                rcgen::Certificate::from_params(CertificateParams::new(vec!["client".into()]))
+                    .unwrap();
+
+            let pki = Pki {
+                ca,
+                client,
+                dir: tempfile::tempdir().unwrap(),
+            };
+            pki.write("ca.pem", &pki.ca.serialize_pem().unwrap());
+            pki.write("server.pem", &server.serialize_pem_with_signer(&pki.ca).unwrap());
+            pki.write("server.key", &server.serialize_private_key_pem());
+            pki
+        }
+
+        fn write(&self, name: &str, contents: &str) {
+            fs::write(self.dir.path().join(name), contents).unwrap();
+        }
+
+        fn path(&self, name: &str) -> String {
+            self.dir.path().join(name).to_string_lossy().into_owned()
+        }
+
+        fn server_config(&self, requires_client_cert: bool) -> Arc<ServerConfig> {
+            let client_ca_path = if requires_client_cert {
+                Some(self.path("ca.pem"))
+            } else {
+                None
+            };
+            server_config(
+                &self.path("server.pem"),
+                &self.path("server.key"),
+                client_ca_path.as_deref(),
+            ).unwrap()
+        }
+
+        fn client_config(&self, presents_cert: bool) -> Arc<ClientConfig> {
+            let mut config = ClientConfig::new();
+            config
+                .root_store
+                .add(&Certificate(self.ca.serialize_der().unwrap()))
+                .unwrap();
+            if presents_cert {
+                let certificate = self.client.serialize_der_with_signer(&self.ca).unwrap();
+                let key = self.client.serialize_private_key_der();
+                config.set_single_client_cert(vec![Certificate(certificate)], PrivateKey(key));
+            }
+            Arc::new(config)
+        }
+    }
+
+    fn listen() -> (TcpListener, SocketAddr) {
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = listener.local_addr().unwrap();
+        (listener, address)
+    }
+
+    fn accept_one(listener: TcpListener) -> impl Future<Item = TcpStream, Error = io::Error> {
+        listener
+            .incoming()
+            .into_future()
+            .map(|(socket, _)| socket.expect("The listener stopped accepting!"))
+            .map_err(|(err, _)| err)
+    }
+
+    // Runs a client on a thread of its own, as it blocks on its socket.
+    fn spawn_client<F, T>(
+        config: Arc<ClientConfig>,
+        address: SocketAddr,
+        client: F,
+    ) -> thread::JoinHandle<T>
+    where
+        F: FnOnce(&mut rustls::Stream<ClientSession, StdTcpStream>) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        thread::spawn(move || {
+            let mut session =
+                ClientSession::new(&config, DNSNameRef::try_from_ascii_str("localhost").unwrap());
+            let mut socket = StdTcpStream::connect(address).unwrap();
+            client(&mut rustls::Stream::new(&mut session, &mut socket))
+        })
+    }
+
+    #[test]
+    fn exchanges_data_and_closes_with_close_notify() {
+        let pki = Pki::new();
+        let (listener, address) = listen();
+        let client = spawn_client(pki.client_config(false), address, |stream| {
+            stream.write_all(b"ping").unwrap();
+            let mut pong = [0; 4];
+            stream.read_exact(&mut pong).unwrap();
+            // A socket that was just closed would end without an alert.
+            let err = stream.read(&mut [0; 1]).unwrap_err();
+            (pong, err.kind())
+        });
+
+        let config = pki.server_config(false);
+        block_on(
+            accept_one(listener)
+                .and_then(move |socket| accept(&config, socket))
+                .and_then(|stream| async_io::read_exact(stream, [0; 4]))
+                .and_then(|(stream, ping)| {
+                    assert_eq!(&ping, b"ping");
+                    async_io::write_all(stream, b"pong")
+                })
+                .and_then(|(stream, _)| async_io::shutdown(stream)),
+        ).unwrap();
+
+        assert_eq!(
+            client.join().unwrap(),
+            (*b"pong", io::ErrorKind::ConnectionAborted)
+        );
+    }
+
+    #[test]
+    fn ends_the_stream_on_close_notify_from_the_client() {
+        let pki = Pki::new();
+        let (listener, address) = listen();
+        let client = spawn_client(pki.client_config(false), address, |stream| {
+            stream.write_all(b"ping").unwrap();
+            stream.sess.send_close_notify();
+            stream.flush().unwrap();
+            // Keeps the socket open until the server is done with it, skipping whatever else it
+            // sends, such as session tickets.
+            io::copy(stream.sock, &mut io::sink()).unwrap();
+        });
+
+        let config = pki.server_config(false);
+        let (_, received) = block_on(
+            accept_one(listener)
+                .and_then(move |socket| accept(&config, socket))
+                .and_then(|stream| async_io::read_to_end(stream, Vec::new())),
+        ).unwrap();
+
+        assert_eq!(received, b"ping");
+        client.join().unwrap();
+    }
+
+    #[test]
+    fn requires_a_client_certificate_from_the_ca() {
+        let pki = Pki::new();
+        for &presents_cert in &[false, true] {
+            let (listener, address) = listen();
+            let client = spawn_client(pki.client_config(presents_cert), address, |stream| {
+                // The client only learns that it was turned away once it reads.
+                let _ = stream.write_all(b"ping");
+                let _ = stream.read(&mut [0; 1]);
+            });
+
+            let config = pki.server_config(true);
+            let handshake = block_on(
+                accept_one(listener).and_then(move |socket| accept(&config, socket)),
+            );
+
+            assert_eq!(handshake.is_ok(), presents_cert);
+            drop(handshake);
+            client.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn waits_for_a_slow_client_to_take_what_was_written() {
+        let pki = Pki::new();
+        let (listener, address) = listen();
+        let sent: Vec<u8> = (0..1 << 23).map(|index| (index % 251) as u8).collect();
+        let client = spawn_client(pki.client_config(false), address, |stream| {
+            // By the time the client starts reading, the socket's buffers have long filled up.
+            thread::sleep(Duration::from_millis(200));
+            let mut received = Vec::new();
+            let err = stream.read_to_end(&mut received).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+            received
+        });
+
+        let config = pki.server_config(false);
+        block_on(
+            accept_one(listener)
+                .and_then(move |socket| accept(&config, socket))
+                .and_then({
+                    let sent = sent.clone();
+                    |stream| async_io::write_all(stream, sent)
+                })
+                .and_then(|(stream, _)| async_io::shutdown(stream)),
+        ).unwrap();
+
+        assert!(client.join().unwrap() == sent);
+    }
+}
@@ -1,27 +1,104 @@
 use bytes::{Bytes, BytesMut};
 use config;
 use error;
-use futures::future::{self, Future};
-use futures::{Sink, Stream};
+use futures::future::{self, loop_fn, Future, Loop};
+use futures::stream::{SplitSink, SplitStream};
+use futures::{Async, Poll, Sink, Stream};
+use message::{channel, protocol::{Capability, Outbound}};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 use serde_json;
 use state;
+use std::collections::VecDeque;
 use std::fmt::Debug;
+use std::io;
 use std::str;
-use tokio::net::TcpStream;
-use tokio_io::codec::length_delimited;
+use std::sync::{Arc, Mutex};
+use tokio::codec::length_delimited::Builder;
+use tokio_io::{AsyncRead, AsyncWrite};
 use types::DispatchFuture;
 
-pub type Transport = length_delimited::Framed<TcpStream, Bytes>;
+/// A connection to a client, whichever kind of socket it was accepted on.
+pub trait ClientStream: AsyncRead + AsyncWrite + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Send> ClientStream for T {}
+
+/// Carries whole messages to and from a client, however they are framed on the wire.
+pub trait MessageTransport:
+    Stream<Item = BytesMut, Error = io::Error>
+    + Sink<SinkItem = Bytes, SinkError = io::Error>
+    + Send
+{
+}
+
+impl<T> MessageTransport for T
+where
+    T: Stream<Item = BytesMut, Error = io::Error>
+        + Sink<SinkItem = Bytes, SinkError = io::Error>
+        + Send,
+{
+}
+
+pub type Transport = Box<dyn MessageTransport>;
+
+/// The half of a transport that messages are sent on.
+pub type TransportSink = SplitSink<Transport>;
+
+/// The half of a transport that messages are read from. While a command runs, the frames the
+/// client sends are read ahead to notice it hanging up, and kept here until they are asked for.
+pub struct Receiver {
+    read_ahead: VecDeque<BytesMut>,
+    stream: SplitStream<Transport>,
+}
+
+impl Receiver {
+    pub fn new(stream: SplitStream<Transport>) -> Self {
+        Receiver {
+            read_ahead: VecDeque::new(),
+            stream,
+        }
+    }
+
+    fn poll_frame(&mut self) -> Poll<Option<BytesMut>, io::Error> {
+        match self.read_ahead.pop_front() {
+            Some(frame) => Ok(Async::Ready(Some(frame))),
+            None => self.stream.poll(),
+        }
+    }
+}
+
+/// Resolves once the client has hung up or its transport broke, reading ahead whatever it sends
+/// until then.
+pub fn hang_up(receiver: Arc<Mutex<Receiver>>) -> impl Future<Item = (), Error = ()> + Send {
+    future::poll_fn(move || {
+        let mut receiver = receiver.lock().expect("Could not lock the receiver!");
+        loop {
+            match receiver.stream.poll() {
+                Ok(Async::Ready(Some(frame))) => receiver.read_ahead.push_back(frame),
+                Ok(Async::Ready(None)) | Err(_) => return Ok(Async::Ready(())),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+            }
+        }
+    })
+}
+
+/// Frames each message with its length ahead of it.
+pub fn length_delimited<S: ClientStream + 'static>(socket: S) -> Transport {
+    Box::new(
+        Builder::new()
+            // Frame header size + max size addressable size of unsigned 32 bit int
+            .max_frame_length(4 + (u32::MAX as usize))
+            .new_framed(Box::new(socket) as Box<dyn ClientStream>),
+    )
+}
 
 pub fn deserialize<T>(bytes: &BytesMut) -> Result<T, error::protocol::Error>
 where
     T: DeserializeOwned,
 {
-    str::from_utf8(&bytes)
+    str::from_utf8(bytes)
         .map_err(error::protocol::Error::from)
-        .and_then(|message| serde_json::from_str(&message).map_err(error::protocol::Error::from))
+        .and_then(|message| serde_json::from_str(message).map_err(error::protocol::Error::from))
 }
 
 pub fn serialize<T>(message: &T) -> Result<Bytes, error::protocol::Error>
@@ -32,59 +109,181 @@ where
     Ok(Bytes::from(message.into_bytes()))
 }
 
-pub fn read_message<T: Send + 'static>(
-    mut connection_state: state::Connection,
+enum Incoming {
+    Frame(Option<BytesMut>),
+    Notification(channel::Message),
+}
+
+// One turn of the loop reading a message: the message once one could be deserialized, otherwise
+// the connection to read the next frame with.
+type ReadStep<T> = Box<
+    dyn Future<
+            Item = Loop<(T, state::Connection), state::Connection>,
+            Error = (error::protocol::Error, state::Connection),
+        >
+        + Send,
+>;
+
+// Resolves with whichever comes first: a frame from the client or a message for the connection
+// from elsewhere in the server.
+fn next_incoming(
+    connection_state: state::Connection,
+) -> impl Future<
+    Item = (Incoming, state::Connection),
+    Error = (error::protocol::Error, state::Connection),
+> {
+    use error::protocol::{Error, TcpReceiveError};
+
+    let mut connection_state = Some(connection_state);
+    future::poll_fn(move || {
+        let result = {
+            let connection_state = connection_state
+                .as_mut()
+                .expect("Polled an incoming message after it resolved!");
+
+            match connection_state.poll_channel() {
+                Ok(Async::Ready(Some(message))) => {
+                    Ok(Async::Ready(Incoming::Notification(message)))
+                }
+                // The connection holds a sender of its own, so its channel never runs dry.
+                _ => match connection_state
+                    .receiver
+                    .lock()
+                    .expect("Could not lock the receiver!")
+                    .poll_frame()
+                {
+                    Ok(Async::Ready(frame)) => Ok(Async::Ready(Incoming::Frame(frame))),
+                    Ok(Async::NotReady) => Ok(Async::NotReady),
+                    Err(_) => Err(Error::TcpReceive(TcpReceiveError::Io)),
+                },
+            }
+        };
+
+        match result {
+            Ok(Async::Ready(incoming)) => Ok(Async::Ready((
+                incoming,
+                connection_state.take().unwrap(),
+            ))),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(err) => Err((err, connection_state.take().unwrap())),
+        }
+    })
+}
+
+// Notifications name repositories by the handle this connection knows them by, and are dropped
+// once the connection has closed the repository.
+fn to_outbound(
+    connection_state: &state::Connection,
+    message: channel::Message,
+) -> Option<Outbound> {
+    let find_handle = |repo_path: &str| {
+        connection_state
+            .repo_path_by_handle
+            .iter()
+            .find(|&(_, open_repo_path)| open_repo_path.path == repo_path)
+            .map(|(handle, _)| *handle)
+    };
+
+    match message {
+        channel::Message::HeadMoved {
+            from,
+            repo_path,
+            to,
+        } => find_handle(&repo_path).map(|repo| Outbound::HeadMoved { from, repo, to }),
+        channel::Message::RefsUpdated { refs, repo_path } => {
+            find_handle(&repo_path).map(|repo| Outbound::RefsUpdated { refs, repo })
+        }
+        channel::Message::RepoChanged { kind, repo_path } => {
+            find_handle(&repo_path).map(|repo| Outbound::RepoChanged { kind, repo })
+        }
+        channel::Message::WatchFailed { repo_path } => {
+            find_handle(&repo_path).map(|repo| Outbound::WatchFailed { repo })
+        }
+    }
+}
+
+// A request that could not be deserialized may still have an id to report the error against.
+fn find_request_id(bytes: &BytesMut) -> Option<String> {
+    serde_json::from_slice::<serde_json::Value>(bytes)
+        .ok()
+        .and_then(|message| {
+            message
+                .get("request_id")
+                .and_then(|request_id| request_id.as_str())
+                .map(String::from)
+        })
+}
+
+/// Reads the next message from the client, sending along any notifications for the connection
+/// while it waits. Messages that cannot be deserialized are answered with an `Error` and skipped.
+/// Fails with `TcpReceiveError::Eof` once the client has closed its end of the connection.
+pub fn read_message<T>(
+    connection_state: state::Connection,
 ) -> Box<
-    Future<Item = (T, state::Connection), Error = (error::protocol::Error, state::Connection)>
+    dyn Future<Item = (T, state::Connection), Error = (error::protocol::Error, state::Connection)>
         + Send,
 >
 where
-    T: DeserializeOwned + Debug,
+    T: DeserializeOwned + Debug + Send + 'static,
 {
-    use error::protocol::{Error, ProcessError, TcpReceiveError};
+    use error::protocol::{Error, TcpReceiveError};
 
-    match connection_state.transport.take() {
-        Some(transport) => Box::new(transport.into_future().then(|result| match result {
-            Ok((response, transport)) => {
-                let response = match response {
-                    Some(x) => x,
-                    None => unimplemented!(),
-                };
-                debug!({
-                    println!("received message; message={:?}", response);
-                });
-                connection_state.transport = Some(transport);
-                match deserialize(&response) {
-                    Ok(message) => {
+    Box::new(loop_fn(connection_state, |connection_state| {
+        next_incoming(connection_state).and_then(
+            |(incoming, connection_state)| -> ReadStep<T> {
+                match incoming {
+                    Incoming::Frame(response) => {
+                        let response = match response {
+                            Some(x) => x,
+                            None => {
+                                return Box::new(future::err((
+                                    Error::TcpReceive(TcpReceiveError::Eof),
+                                    connection_state,
+                                )))
+                            }
+                        };
                         debug!({
-                            println!("deserialized message; message={:?}", message);
+                            println!("received message; message={:?}", response);
                         });
-                        future::ok((message, connection_state))
+                        match deserialize(&response) {
+                            Ok(message) => {
+                                debug!({
+                                    println!("deserialized message; message={:?}", message);
+                                });
+                                Box::new(future::ok(Loop::Break((message, connection_state))))
+                            }
+                            Err(err) => {
+                                let request_id = find_request_id(&response);
+                                Box::new(
+                                    send_error(connection_state, &err, request_id)
+                                        .map(Loop::Continue),
+                                )
+                            }
+                        }
+                    }
+                    Incoming::Notification(message) => {
+                        match to_outbound(&connection_state, message) {
+                            Some(message) => Box::new(
+                                send_message(connection_state, message).map(Loop::Continue),
+                            ),
+                            None => Box::new(future::ok(Loop::Continue(connection_state))),
+                        }
                     }
-                    Err(err) => future::err((err, connection_state)),
                 }
-            }
-            Err((_, transport)) => {
-                connection_state.transport = Some(transport);
-                future::err((Error::TcpReceive(TcpReceiveError::Io), connection_state))
-            }
-        })),
-        None => Box::new(future::err((
-            Error::Process(ProcessError::Failed),
-            connection_state,
-        ))),
-    }
+            },
+        )
+    }))
 }
 
-#[allow(needless_pass_by_value)]
+#[allow(clippy::needless_pass_by_value)]
 pub fn send_message<T>(mut connection_state: state::Connection, message: T) -> DispatchFuture
 where
     T: Serialize + Debug,
 {
-    use error::protocol::{Error, ProcessError, TcpSendError};
+    use error::protocol::{Error, TcpSendError};
 
-    let message =
-        serialize(&message).expect(&format!("Could not serialize message: {:?}", message));
+    let message = serialize(&message)
+        .unwrap_or_else(|_| panic!("Could not serialize message: {:?}", message));
 
     match connection_state.transport.take() {
         Some(transport) => Box::new(transport.send(message).then(|result| match result {
@@ -94,9 +293,25 @@ where
             }
             Err(_) => future::err((Error::TcpSend(TcpSendError::Io), connection_state)),
         })),
-        None => Box::new(future::err((
-            Error::Process(ProcessError::Failed),
-            connection_state,
-        ))),
+        None => Box::new(future::err((Error::TcpSend(TcpSendError::Io), connection_state))),
     }
 }
+
+/// Tells the client that a request failed, without giving up on the connection. The request's id
+/// is only echoed to clients that asked for it in the handshake.
+pub fn send_error(
+    connection_state: state::Connection,
+    err: &error::protocol::Error,
+    request_id: Option<String>,
+) -> DispatchFuture {
+    let request_id = request_id.filter(|_| connection_state.has_capability(Capability::RequestIds));
+    send_message(
+        connection_state,
+        Outbound::Error {
+            code: err.code(),
+            git_error: err.git_error(),
+            message: err.message(),
+            request_id,
+        },
+    )
+}
@@ -0,0 +1,185 @@
+use bytes::{Bytes, BytesMut};
+use config;
+use futures::{Async, AsyncSink, Future, Poll, Sink, StartSend, Stream};
+use std::io::{self, Read, Write};
+use tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response, ServerHandshake};
+use tungstenite::handshake::{HandshakeError, MidHandshake};
+use tungstenite::http::{header, StatusCode};
+use tungstenite::{self, Message, WebSocket};
+
+fn to_io_error(err: tungstenite::Error) -> io::Error {
+    match err {
+        tungstenite::Error::Io(err) => err,
+        err => io::Error::other(err),
+    }
+}
+
+fn poll_ws<T>(result: tungstenite::Result<T>) -> Poll<T, io::Error> {
+    match result {
+        Ok(value) => Ok(Async::Ready(value)),
+        Err(tungstenite::Error::Io(ref err)) if err.kind() == io::ErrorKind::WouldBlock => {
+            Ok(Async::NotReady)
+        }
+        Err(err) => Err(to_io_error(err)),
+    }
+}
+
+/// A WebSocket connection that carries one message per text frame. Binary frames are taken as
+/// messages too, pings are answered and the stream ends when the client closes the connection.
+pub struct WebSocketTransport<S>(WebSocket<S>);
+
+impl<S: Read + Write> Stream for WebSocketTransport<S> {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            match self.0.read_message() {
+                Ok(Message::Text(text)) => return Ok(Async::Ready(Some(text.into_bytes().into()))),
+                Ok(Message::Binary(data)) => return Ok(Async::Ready(Some(data.into()))),
+                Ok(Message::Ping(_)) | Ok(Message::Pong(_)) => {}
+                // The reply to the close frame has been queued, and is sent on a best effort.
+                Ok(Message::Close(_)) => {
+                    let _ = self.0.write_pending();
+                    return Ok(Async::Ready(None));
+                }
+                Err(tungstenite::Error::ConnectionClosed)
+                | Err(tungstenite::Error::AlreadyClosed) => return Ok(Async::Ready(None)),
+                Err(tungstenite::Error::Io(ref err)) if err.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(Async::NotReady)
+                }
+                Err(err) => return Err(to_io_error(err)),
+            }
+        }
+    }
+}
+
+impl<S: Read + Write> Sink for WebSocketTransport<S> {
+    type SinkItem = Bytes;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        // Messages are always serialized JSON, so they fit in a text frame.
+        let message = match String::from_utf8(item.to_vec()) {
+            Ok(text) => Message::Text(text),
+            Err(err) => Message::Binary(err.into_bytes()),
+        };
+
+        // The message is queued even when the socket cannot take it yet, and sent on the next
+        // `poll_complete`.
+        match self.0.write_message(message) {
+            Ok(()) => Ok(AsyncSink::Ready),
+            Err(tungstenite::Error::Io(ref err)) if err.kind() == io::ErrorKind::WouldBlock => {
+                Ok(AsyncSink::Ready)
+            }
+            Err(err) => Err(to_io_error(err)),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        poll_ws(self.0.write_pending())
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        match self.0.close(None) {
+            Err(tungstenite::Error::ConnectionClosed) => Ok(Async::Ready(())),
+            result => poll_ws(result),
+        }
+    }
+}
+
+/// Turns away upgrade requests from web pages on origins that were not allowed with
+/// `--websocket-origin`, which would otherwise let any page the user visits talk to the server.
+/// Browsers always send an `Origin`, so requests without one come from other clients and are let
+/// through.
+pub struct OriginCheck;
+
+impl Callback for OriginCheck {
+    fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        let origin = match request.headers().get(header::ORIGIN) {
+            Some(origin) => origin.to_str().unwrap_or_default(),
+            None => return Ok(response),
+        };
+
+        let is_allowed = config::CONFIG
+            .read()
+            .unwrap()
+            .websocket_origins
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(origin));
+        if is_allowed {
+            Ok(response)
+        } else {
+            let message = format!("Origin {} is not allowed", origin);
+            let mut response = ErrorResponse::new(Some(message));
+            *response.status_mut() = StatusCode::FORBIDDEN;
+            Err(response)
+        }
+    }
+}
+
+enum HandshakeState<S: Read + Write> {
+    Started(S),
+    Interrupted(MidHandshake<ServerHandshake<S, OriginCheck>>),
+}
+
+/// Resolves to the transport once the client's upgrade request has been accepted.
+pub struct Handshake<S: Read + Write>(Option<HandshakeState<S>>);
+
+impl<S: Read + Write> Future for Handshake<S> {
+    type Item = WebSocketTransport<S>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = match self.0.take().expect("Handshake polled after it completed!") {
+            HandshakeState::Started(socket) => tungstenite::accept_hdr(socket, OriginCheck),
+            HandshakeState::Interrupted(handshake) => handshake.handshake(),
+        };
+
+        // The handshake is only interrupted when the socket would block, which has the task
+        // woken up once it is ready again.
+        match result {
+            Ok(websocket) => Ok(Async::Ready(WebSocketTransport(websocket))),
+            Err(HandshakeError::Interrupted(handshake)) => {
+                self.0 = Some(HandshakeState::Interrupted(handshake));
+                Ok(Async::NotReady)
+            }
+            Err(HandshakeError::Failure(err)) => Err(to_io_error(err)),
+        }
+    }
+}
+
+/// Starts accepting a WebSocket connection on a socket a client has just connected on.
+pub fn accept<S: Read + Write>(socket: S) -> Handshake<S> {
+    Handshake(Some(HandshakeState::Started(socket)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OriginCheck;
+    use config;
+    use tungstenite::handshake::server::{Callback, Request, Response};
+    use tungstenite::http::StatusCode;
+
+    fn check(origin: Option<&str>) -> Result<(), StatusCode> {
+        let mut request = Request::builder();
+        if let Some(origin) = origin {
+            request = request.header("Origin", origin);
+        }
+        OriginCheck
+            .on_request(&request.body(()).unwrap(), Response::new(()))
+            .map(|_| ())
+            .map_err(|response| response.status())
+    }
+
+    #[test]
+    fn only_lets_in_pages_on_allowed_origins() {
+        config::CONFIG.write().unwrap().websocket_origins = vec![String::from("https://a.test")];
+
+        assert_eq!(check(None), Ok(()));
+        assert_eq!(check(Some("https://a.test")), Ok(()));
+        assert_eq!(check(Some("HTTPS://A.TEST")), Ok(()));
+        assert_eq!(check(Some("https://b.test")), Err(StatusCode::FORBIDDEN));
+        assert_eq!(check(Some("null")), Err(StatusCode::FORBIDDEN));
+    }
+}
@@ -0,0 +1,337 @@
+use message::protocol::ChangeKind;
+use notify::{self, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use state::Shared;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+use util::git::{self, RepoPath};
+use util::lossless::os_string_from_bytes;
+use uuid::Uuid;
+
+/// How long a path has to stay untouched before a change to it is reported.
+const DEBOUNCE_DELAY_MS: u64 = 200;
+
+/// The directories a repository is spread across. In a linked worktree the git dir only holds
+/// `HEAD` and the index, while refs live in the common dir.
+pub struct RepoLayout {
+    pub common_dir: PathBuf,
+    pub git_dir: PathBuf,
+    /// Directories in the working tree that git ignores. Build output and dependencies easily
+    /// exhaust the system's limit on watches, so they are left alone.
+    pub ignored_dirs: HashSet<PathBuf>,
+    pub workdir: Option<PathBuf>,
+}
+
+impl RepoLayout {
+    fn classify(&self, path: &Path) -> Option<ChangeKind> {
+        if let Ok(relative_path) = path.strip_prefix(&self.git_dir) {
+            return classify_git_path(relative_path, true);
+        }
+
+        if let Ok(relative_path) = path.strip_prefix(&self.common_dir) {
+            return classify_git_path(relative_path, false);
+        }
+
+        match self.workdir {
+            Some(ref workdir) if path.starts_with(workdir) => Some(ChangeKind::Workdir),
+            _ => None,
+        }
+    }
+
+    // Git dirs are watched recursively, so one inside the other is already covered.
+    fn git_dirs(&self) -> Vec<&Path> {
+        if self.git_dir.starts_with(&self.common_dir) {
+            vec![&self.common_dir]
+        } else if self.common_dir.starts_with(&self.git_dir) {
+            vec![&self.git_dir]
+        } else {
+            vec![&self.common_dir, &self.git_dir]
+        }
+    }
+
+    // Git dirs are never part of the working tree, be they the repository's own or a nested one's.
+    fn is_workdir_dir(&self, dir: &Path) -> bool {
+        match self.workdir {
+            Some(ref workdir) => {
+                dir.starts_with(workdir)
+                    && dir.file_name() != Some(OsStr::new(".git"))
+                    && !dir.starts_with(&self.git_dir)
+                    && !dir.starts_with(&self.common_dir)
+            }
+            None => false,
+        }
+    }
+}
+
+/// Lists what git ignores in the working tree, or only below `pathspec`. Directories are only
+/// listed when an ignore rule matches them, not when everything in them happens to be ignored.
+pub fn new_ignored_dirs_command(repo_path: &RepoPath, pathspec: Option<&Path>) -> Command {
+    let mut command = git::new_command_with_repo_path(repo_path);
+    command
+        .arg("--no-optional-locks")
+        .arg("status")
+        .arg("--porcelain=v2")
+        .arg("-z")
+        .arg("--ignored=matching")
+        .arg("--untracked-files=normal")
+        .arg("--ignore-submodules=all")
+        .arg("--");
+    command.args(pathspec);
+    command
+}
+
+/// Picks the ignored directories out of the output of a `new_ignored_dirs_command`.
+pub fn parse_ignored_dirs(workdir: &Path, stdout: &[u8]) -> HashSet<PathBuf> {
+    stdout
+        .split(|&byte| byte == b'\0')
+        .filter(|entry| entry.starts_with(b"! ") && entry.ends_with(b"/"))
+        .map(|entry| workdir.join(os_string_from_bytes(&entry[2..entry.len() - 1])))
+        .collect()
+}
+
+// A directory that is gone by the time it is watched has nothing left to watch.
+fn is_not_found(err: &notify::Error) -> bool {
+    match *err {
+        notify::Error::PathNotFound => true,
+        notify::Error::Io(ref err) => err.kind() == io::ErrorKind::NotFound,
+        _ => false,
+    }
+}
+
+// Watches `dir` and every directory of the working tree below it. Each directory gets a watch of
+// its own, so that ignored ones can be left out.
+fn watch_workdir(
+    watcher: &mut RecommendedWatcher,
+    layout: &RepoLayout,
+    ignored_dirs: &HashSet<PathBuf>,
+    dir: &Path,
+) -> notify::Result<()> {
+    match watcher.watch(dir, RecursiveMode::NonRecursive) {
+        Err(ref err) if is_not_found(err) => return Ok(()),
+        result => result?,
+    }
+
+    let entries = match fs::read_dir(dir) {
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        entries => entries?,
+    };
+    for entry in entries {
+        let entry = entry?;
+        // Symbolic links are not followed, the same as git does not.
+        let is_dir = entry.file_type().map(|file_type| file_type.is_dir())?;
+        let path = entry.path();
+        if is_dir && layout.is_workdir_dir(&path) && !ignored_dirs.contains(&path) {
+            watch_workdir(watcher, layout, ignored_dirs, &path)?;
+        }
+    }
+    Ok(())
+}
+
+// Starts watching a directory that was created in, or moved into, the working tree. Git is asked
+// about ignore rules afresh, since the directory could not have been listed before.
+fn watch_new_dir(
+    watcher: &Weak<Mutex<RecommendedWatcher>>,
+    repo_path: &RepoPath,
+    layout: &RepoLayout,
+    dir: &Path,
+) -> notify::Result<()> {
+    let is_dir = fs::symlink_metadata(dir).is_ok_and(|metadata| metadata.is_dir());
+    if !is_dir || !layout.is_workdir_dir(dir) {
+        return Ok(());
+    }
+
+    // Should git fail, it is better to watch too much than to miss changes.
+    let output = new_ignored_dirs_command(repo_path, Some(dir)).output();
+    let ignored_dirs = match (&layout.workdir, output) {
+        (Some(workdir), Ok(ref output)) if output.status.success() => {
+            parse_ignored_dirs(workdir, &output.stdout)
+        }
+        _ => HashSet::new(),
+    };
+    if ignored_dirs.contains(dir) {
+        return Ok(());
+    }
+
+    match watcher.upgrade() {
+        Some(watcher) => watch_workdir(
+            &mut watcher.lock().expect("Could not lock the watcher!"),
+            layout,
+            &ignored_dirs,
+            dir,
+        ),
+        None => Ok(()),
+    }
+}
+
+// Lock files come and go during every write, the change is reported when they are renamed into
+// place. Objects, logs and the like only ever change alongside one of the files below.
+fn classify_git_path(relative_path: &Path, is_own_git_dir: bool) -> Option<ChangeKind> {
+    if relative_path.extension().is_some_and(|extension| extension == "lock") {
+        return None;
+    }
+
+    if relative_path == Path::new("packed-refs") || relative_path.starts_with("refs") {
+        Some(ChangeKind::Refs)
+    } else if is_own_git_dir && relative_path == Path::new("HEAD") {
+        Some(ChangeKind::Head)
+    } else if is_own_git_dir && relative_path == Path::new("index") {
+        Some(ChangeKind::Index)
+    } else {
+        None
+    }
+}
+
+// Paths that were created or moved into place are added to `new_paths`, since they may be
+// directories that need watching.
+fn collect_changes(
+    layout: &RepoLayout,
+    event: DebouncedEvent,
+    kinds: &mut HashSet<ChangeKind>,
+    new_paths: &mut Vec<PathBuf>,
+) {
+    match event {
+        DebouncedEvent::Create(path) => {
+            kinds.extend(layout.classify(&path));
+            new_paths.push(path);
+        }
+        DebouncedEvent::Write(path)
+        | DebouncedEvent::Chmod(path)
+        | DebouncedEvent::Remove(path) => kinds.extend(layout.classify(&path)),
+        DebouncedEvent::Rename(from, to) => {
+            kinds.extend(layout.classify(&from));
+            kinds.extend(layout.classify(&to));
+            new_paths.push(to);
+        }
+        // Events were missed, so anything could have changed.
+        DebouncedEvent::Rescan | DebouncedEvent::Error(_, _) => kinds.extend(&[
+            ChangeKind::Head,
+            ChangeKind::Index,
+            ChangeKind::Refs,
+            ChangeKind::Workdir,
+        ]),
+        DebouncedEvent::NoticeRemove(_) | DebouncedEvent::NoticeWrite(_) => {}
+    }
+}
+
+// Runs until the watcher is dropped, which hangs up the sending half of `receiver`. Only a weak
+// reference to the watcher is kept, so that it can be.
+fn forward_changes(
+    state: Arc<Mutex<Shared>>,
+    repo_path: RepoPath,
+    layout: RepoLayout,
+    receiver: Receiver<DebouncedEvent>,
+    watcher: Weak<Mutex<RecommendedWatcher>>,
+    watcher_id: Uuid,
+) {
+    while let Ok(event) = receiver.recv() {
+        let mut kinds = HashSet::new();
+        let mut new_paths = Vec::new();
+        collect_changes(&layout, event, &mut kinds, &mut new_paths);
+
+        // A single git command touches many files at once, so report whatever else has already
+        // settled along with the first change.
+        while let Ok(event) = receiver.try_recv() {
+            collect_changes(&layout, event, &mut kinds, &mut new_paths);
+        }
+
+        let watched_new_paths = new_paths
+            .iter()
+            .try_for_each(|path| watch_new_dir(&watcher, &repo_path, &layout, path));
+
+        let mut shared = state.lock().expect("Could not lock the shared state!");
+        if !kinds.is_empty() {
+            shared.notify_repo_changed(&repo_path.path, &kinds);
+        }
+        // Changes in a directory that could not be watched would go unnoticed.
+        if watched_new_paths.is_err() {
+            shared.stop_watching(&repo_path.path, &watcher_id);
+            return;
+        }
+    }
+}
+
+/// Watches a single repository on behalf of every connection subscribed to it. Should a directory
+/// created later on fail to be watched, the subscribers are told and the watcher is dropped.
+pub struct RepoWatcher {
+    pub id: Uuid,
+    pub subscribers: HashSet<Uuid>,
+    _watcher: Arc<Mutex<RecommendedWatcher>>,
+}
+
+impl RepoWatcher {
+    pub fn new(
+        state: Arc<Mutex<Shared>>,
+        repo_path: RepoPath,
+        layout: RepoLayout,
+    ) -> notify::Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::watcher(sender, Duration::from_millis(DEBOUNCE_DELAY_MS))?;
+        for dir in layout.git_dirs() {
+            watcher.watch(dir, RecursiveMode::Recursive)?;
+        }
+        if let Some(ref workdir) = layout.workdir {
+            watch_workdir(&mut watcher, &layout, &layout.ignored_dirs, workdir)?;
+        }
+
+        let id = Uuid::new_v4();
+        let watcher = Arc::new(Mutex::new(watcher));
+        let weak_watcher = Arc::downgrade(&watcher);
+        thread::spawn(move || {
+            forward_changes(state, repo_path, layout, receiver, weak_watcher, id)
+        });
+
+        Ok(RepoWatcher {
+            id,
+            subscribers: HashSet::new(),
+            _watcher: watcher,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{new_ignored_dirs_command, parse_ignored_dirs};
+    use std::collections::HashSet;
+    use std::path::{Path, PathBuf};
+    use util::test_repo::TestRepo;
+
+    fn ignored_dirs(repo: &TestRepo, pathspec: Option<&str>) -> HashSet<PathBuf> {
+        let output = new_ignored_dirs_command(&repo.repo_path(), pathspec.map(Path::new))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        parse_ignored_dirs(Path::new(repo.path()), &output.stdout)
+    }
+
+    #[test]
+    fn lists_directories_matched_by_an_ignore_rule() {
+        let repo = TestRepo::new();
+        repo.write(".gitignore", b"build/\n*.o\n");
+        repo.write(".git/info/exclude", b"target\n");
+        repo.write("build/out", b"");
+        repo.write("target/debug/out", b"");
+        repo.write("src/main.o", b"");
+        // Only holds ignored files, but new ones would not be.
+        repo.write("objects/main.o", b"");
+        repo.write("src/build/out", b"");
+
+        let expected: HashSet<PathBuf> = ["build", "target", "src/build"]
+            .iter()
+            .map(|dir| PathBuf::from(repo.join(dir)))
+            .collect();
+        assert_eq!(ignored_dirs(&repo, None), expected);
+
+        let expected: HashSet<PathBuf> = [PathBuf::from(repo.join("src/build"))]
+            .iter()
+            .cloned()
+            .collect();
+        assert_eq!(ignored_dirs(&repo, Some(&repo.join("src"))), expected);
+    }
+}